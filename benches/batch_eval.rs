@@ -0,0 +1,81 @@
+//! Per point evaluation against `SplineSampler` on the ground spline of the
+//! level, at `SAMPLES` points spaced evenly along it.
+//!
+//! Run with `cargo bench --bench batch_eval`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use nalgebra::Vector2;
+
+use spline_grind::batch::SplineSampler;
+use spline_grind::spline::{ArcLengthTable, BSpline};
+
+const SAMPLES: usize = 5000;
+const ROUNDS: usize = 200;
+
+fn ground() -> BSpline {
+    let points = (0..500)
+        .map(|i| {
+            let x = -1000.0 + i as f32 * 40.0;
+            let y = (x * 0.01).sin() * x * 0.01 + (x * 0.0085).sin() * x * 0.005 + (x * 0.0185).sin() * x * 0.0076 - 200.0;
+            Vector2::new(x, y)
+        })
+        .collect();
+    BSpline::clamped_uniform(points, 3).unwrap()
+}
+
+fn island() -> BSpline {
+    let points: Vec<Vector2<f32>> = (0..16)
+        .map(|i| {
+            let angle = i as f32 / 16.0 * std::f32::consts::TAU;
+            let radius = 250.0 + 60.0 * (3.0 * angle).sin();
+            Vector2::new(1500.0 + radius * angle.cos(), 400.0 + 0.5 * radius * angle.sin())
+        })
+        .collect();
+    let weights = (0..16).map(|i| 1.0 + (i % 3) as f32 * 0.5).collect();
+    BSpline::periodic(points, 3).unwrap().with_weights(weights).unwrap()
+}
+
+/// Fastest of `ROUNDS` runs of `f`.
+fn time(mut f: impl FnMut()) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn compare(name: &str, curve: &BSpline) {
+    // evenly spaced along the curve
+    let table = ArcLengthTable::new(curve, 4);
+    let step = table.total_length() / SAMPLES as f32;
+    let params: Vec<f32> = (0..SAMPLES).map(|i| table.param_at_length(i as f32 * step)).collect();
+
+    let per_point = time(|| {
+        for &u in &params {
+            black_box(curve.eval(black_box(u)));
+        }
+    });
+    let batched = time(|| {
+        let mut sampler = SplineSampler::new(curve);
+        for &u in &params {
+            black_box(sampler.eval(black_box(u)));
+        }
+    });
+
+    let mut sampler = SplineSampler::new(curve);
+    let error = params.iter().map(|&u| (sampler.eval(u) - curve.eval(u)).norm()).fold(0.0, f32::max);
+    println!(
+        "{name}: {SAMPLES} points, per point {per_point:?}, sampler {batched:?}, {:.1}x faster, largest difference {error}",
+        per_point.as_secs_f64() / batched.as_secs_f64(),
+    );
+}
+
+fn main() {
+    compare("ground", &ground());
+    compare("rational island", &island());
+}
@@ -0,0 +1,129 @@
+//! Evaluating a spline at many parameters at once, e.g. for rendering.
+//!
+//! [`BSpline::eval`] looks up the knot span with a binary search and runs de
+//! Boor's algorithm for every point. Sorted parameters visit the spans in
+//! order instead, so [`SplineSampler`] turns each span it reaches into a
+//! polynomial once and evaluates that with Horner's rule for every parameter
+//! inside it.
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::spline::{real, BSpline, Real, MAX_DEGREE};
+
+/// Evaluates a curve span by span, see the module docs.
+#[derive(Debug, Clone)]
+pub struct SplineSampler<'a, T: Real = f32> {
+    curve: &'a BSpline<T>,
+    span: usize,
+    /// Parameter range of `span`.
+    range: (T, T),
+    /// Homogeneous coefficients of `span` in the power basis of the local
+    /// parameter, which runs from 0 to 1 over `range`.
+    coefficients: [Vector3<T>; MAX_DEGREE + 1],
+}
+
+impl<'a, T: Real> SplineSampler<'a, T> {
+    pub fn new(curve: &'a BSpline<T>) -> Self {
+        let mut sampler = Self {
+            curve,
+            span: curve.degree(),
+            range: (T::zero(), T::zero()),
+            coefficients: [Vector3::zeros(); MAX_DEGREE + 1],
+        };
+        sampler.load(curve.find_span(curve.domain().0));
+        sampler
+    }
+
+    /// Point on the curve at `u`, same as [`BSpline::eval`]. Cheapest for
+    /// parameters that don't decrease from one call to the next, others fall
+    /// back to a binary search for their span.
+    pub fn eval(&mut self, u: T) -> Vector2<T> {
+        let (a, b) = self.range;
+        if u < a || u >= b {
+            let knots = self.curve.knots();
+            let last = self.curve.control_points().len() - 1;
+            let mut l = self.span;
+            if u < a {
+                l = self.curve.find_span(u);
+            }
+            while l < last && knots[l + 1] <= u {
+                l += 1;
+            }
+            if l != self.span {
+                self.load(l);
+            }
+        }
+
+        let (a, b) = self.range;
+        let s = if b > a { (u - a) / (b - a) } else { T::zero() };
+        let p = self.curve.degree();
+        let mut point = self.coefficients[p];
+        for j in (0..p).rev() {
+            point = point * s + self.coefficients[j];
+        }
+        point.xy() / point.z
+    }
+
+    /// Power basis of span `l` from the Taylor series at its start:
+    /// `c_j = C^(j)(a) h^j / j!` for the span's length `h`.
+    fn load(&mut self, l: usize) {
+        let p = self.curve.degree();
+        let knots = self.curve.knots();
+        let (a, b) = (knots[l], knots[l + 1]);
+        let ders = self.curve.homogeneous_derivatives(a, l);
+
+        let mut factor = T::one();
+        for (j, (coefficient, der)) in self.coefficients.iter_mut().zip(ders).take(p + 1).enumerate() {
+            *coefficient = der * factor;
+            factor = factor * (b - a) / real((j + 1) as f64);
+        }
+        self.span = l;
+        self.range = (a, b);
+    }
+}
+
+/// Points of `curve` at each of `params`, which should be sorted for speed.
+pub fn eval_sorted<T: Real>(curve: &BSpline<T>, params: &[T]) -> Vec<Vector2<T>> {
+    let mut sampler = SplineSampler::new(curve);
+    params.iter().map(|&u| sampler.eval(u)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::spline::tests::random_cases;
+
+    #[test]
+    fn sampler_matches_eval() {
+        let mut rng = StdRng::seed_from_u64(19);
+        for (curve, _) in random_cases() {
+            let (start, end) = curve.domain();
+            let scale = (curve.bounds().max - curve.bounds().min).amax().max(1.0);
+            // sorted, then every knot, then in random order
+            let mut params: Vec<f32> = (0..=200).map(|i| start + (end - start) * i as f32 / 200.0).collect();
+            params.extend(curve.knots().iter().copied().filter(|&u| start <= u && u <= end));
+            params.extend((0..50).map(|_| rng.gen_range(start..=end)));
+
+            let mut sampler = SplineSampler::new(&curve);
+            for u in params {
+                let (sampled, exact) = (sampler.eval(u), curve.eval(u));
+                assert!((sampled - exact).norm() <= 1e-4 * scale, "{sampled} is not {exact} at {u} on {curve:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn eval_sorted_matches_eval_in_f64() {
+        for (curve, _) in random_cases().into_iter().take(50) {
+            let curve = curve.cast::<f64>();
+            let (start, end) = curve.domain();
+            let params: Vec<f64> = (0..=100).map(|i| start + (end - start) * i as f64 / 100.0).collect();
+            for (point, &u) in eval_sorted(&curve, &params).iter().zip(&params) {
+                assert!((point - curve.eval(u)).norm() <= 1e-8, "{point} at {u}");
+            }
+        }
+    }
+}
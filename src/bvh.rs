@@ -0,0 +1,546 @@
+//! Bounding volume hierarchy over the knot spans of a spline.
+//!
+//! Every leaf bounds the control points of one span, which by the convex hull
+//! property bound that part of the curve. Spans follow each other along the
+//! curve, so splitting them by index already gives tight boxes. When control
+//! points move the tree is refitted instead of rebuilt.
+
+use nalgebra::{Vector2, Vector3};
+
+use crate::spline::{bezier_point, halve_bezier, Aabb, BSpline, ClosestPoint, CLOSEST_POINT_TOLERANCE, MAX_DEGREE};
+
+/// Shortest step of [`SpanBvh::circle_cast`], relative to the circle's radius,
+/// so circles grazing the curve still get anywhere.
+const MIN_CAST_STEP: f32 = 0.05;
+
+/// Halvings of the last step of [`SpanBvh::circle_cast`] when it overlaps the
+/// curve.
+const MAX_BISECTIONS: usize = 16;
+
+/// Fewest steps of [`SpanBvh::circle_cast`] along a part of the curve are a
+/// step this long, so a circle grazing a long part doesn't crawl along it.
+const MAX_WINDOW_STEPS: usize = 64;
+
+/// Halvings of a span when narrowing down where a swept circle could touch it,
+/// see [`sweep_windows`].
+const SWEEP_DEPTH: usize = 4;
+
+/// Where a ray or a swept circle first touches a curve.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CastHit {
+    pub param: f32,
+    pub point: Vector2<f32>,
+    /// Unit normal of the curve at `point`, facing the incoming ray or circle.
+    pub normal: Vector2<f32>,
+    /// Distance travelled along the cast until the hit.
+    pub distance: f32,
+    /// Knot span that contains `param`.
+    pub span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    bounds: Aabb,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NodeKind {
+    Leaf { span: usize },
+    Inner { left: usize, right: usize },
+}
+
+/// AABB tree over the non-empty knot spans of a [`BSpline`].
+#[derive(Debug, Clone, Default)]
+pub struct SpanBvh {
+    /// Parents come before their children, the root is the first node.
+    nodes: Vec<Node>,
+    /// Leaf node of each span, indexed by span.
+    leaves: Vec<Option<usize>>,
+    degree: usize,
+}
+
+impl SpanBvh {
+    pub fn new(curve: &BSpline) -> Self {
+        let spans: Vec<usize> = (curve.degree()..curve.control_points().len())
+            .filter(|&l| curve.knots()[l] < curve.knots()[l + 1])
+            .collect();
+        let mut bvh = SpanBvh {
+            nodes: Vec::with_capacity(2 * spans.len()),
+            leaves: vec![None; curve.control_points().len()],
+            degree: curve.degree(),
+        };
+        if !spans.is_empty() {
+            bvh.build(curve, &spans, None);
+        }
+        bvh
+    }
+
+    fn build(&mut self, curve: &BSpline, spans: &[usize], parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        if let [span] = spans {
+            self.nodes.push(Node {
+                bounds: curve.span_bounds(*span),
+                parent,
+                kind: NodeKind::Leaf { span: *span },
+            });
+            self.leaves[*span] = Some(index);
+            return index;
+        }
+
+        // children are filled in once they exist
+        self.nodes.push(Node {
+            bounds: curve.span_bounds(spans[0]),
+            parent,
+            kind: NodeKind::Leaf { span: spans[0] },
+        });
+        let (first, second) = spans.split_at(spans.len() / 2);
+        let left = self.build(curve, first, Some(index));
+        let right = self.build(curve, second, Some(index));
+        self.nodes[index].bounds = self.nodes[left].bounds.union(&self.nodes[right].bounds);
+        self.nodes[index].kind = NodeKind::Inner { left, right };
+        index
+    }
+
+    /// Whether the tree was built for a curve with the same spans as `curve` and
+    /// can be refitted to it.
+    pub fn fits(&self, curve: &BSpline) -> bool {
+        self.degree == curve.degree()
+            && self.leaves.len() == curve.control_points().len()
+            && self
+                .leaves
+                .iter()
+                .enumerate()
+                .all(|(l, leaf)| leaf.is_some() == (l >= self.degree && curve.knots()[l] < curve.knots()[l + 1]))
+    }
+
+    /// Updates the boxes after the control points `moved` of `curve` changed.
+    /// Only the spans using them and their ancestors are recomputed.
+    pub fn refit(&mut self, curve: &BSpline, moved: impl IntoIterator<Item = usize>) {
+        let mut dirty = vec![false; self.nodes.len()];
+        for i in moved {
+            // control point i shapes the spans i..=i + degree
+            for l in i..=(i + self.degree).min(self.leaves.len() - 1) {
+                let mut node = self.leaves[l];
+                while let Some(n) = node {
+                    if dirty[n] {
+                        break;
+                    }
+                    dirty[n] = true;
+                    node = self.nodes[n].parent;
+                }
+            }
+        }
+
+        for n in (0..self.nodes.len()).rev().filter(|&n| dirty[n]) {
+            self.nodes[n].bounds = match self.nodes[n].kind {
+                NodeKind::Leaf { span } => curve.span_bounds(span),
+                NodeKind::Inner { left, right } => self.nodes[left].bounds.union(&self.nodes[right].bounds),
+            };
+        }
+    }
+
+    /// Bounds of the whole curve, `None` for an empty tree.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.bounds)
+    }
+
+    /// Same as [`BSpline::closest_point`], but only visits spans whose boxes
+    /// could hold a closer point. `curve` has to be the one the tree fits.
+    pub fn closest_point(&self, curve: &BSpline, target: Vector2<f32>) -> Option<ClosestPoint> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let mut best = ClosestPoint {
+            param: 0.0,
+            point: Vector2::zeros(),
+            distance: f32::INFINITY,
+            span: self.degree,
+        };
+        self.visit_closest(0, curve, target, &mut best);
+        Some(curve.polish_closest(target, best))
+    }
+
+    fn visit_closest(&self, node: usize, curve: &BSpline, target: Vector2<f32>, best: &mut ClosestPoint) {
+        match self.nodes[node].kind {
+            NodeKind::Leaf { span } => curve.closest_in_span(span, target, best),
+            NodeKind::Inner { left, right } => {
+                let distance = |n: usize| self.nodes[n].bounds.distance_squared(target).sqrt();
+                let (near, far) = if distance(left) <= distance(right) {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                for child in [near, far] {
+                    if distance(child) < best.distance {
+                        self.visit_closest(child, curve, target, best);
+                    }
+                }
+            }
+        }
+    }
+
+    /// First point where the ray `origin + t * direction` meets the curve, for `t`
+    /// up to `max_distance`. `direction` has to be a unit vector.
+    pub fn raycast(&self, curve: &BSpline, origin: Vector2<f32>, direction: Vector2<f32>, max_distance: f32) -> Option<CastHit> {
+        let mut best: Option<(f32, f32, usize)> = None;
+        for span in self.along_ray(origin, direction, max_distance) {
+            let segment = curve.bezier_segment(span);
+            let ray = Ray {
+                origin,
+                direction,
+                max_distance,
+            };
+            let mut hit = best.map(|(distance, param, _)| (distance, param));
+            ray_bezier(&segment.points, segment.range, &ray, &mut hit, 24);
+            if let Some((distance, param)) = hit {
+                if best.is_none_or(|(best_distance, _, _)| distance < best_distance) {
+                    best = Some((distance, param, span));
+                }
+            }
+        }
+        let (_, guess, span) = best?;
+
+        // newton on the side of the ray the curve is on
+        let (a, b) = (curve.knots()[span], curve.knots()[span + 1]);
+        let side = |u: f32| direction.perp(&(curve.derivative_in_span(u, 0, span) - origin));
+        let mut u = guess;
+        for _ in 0..8 {
+            let slope = direction.perp(&curve.derivative_in_span(u, 1, span));
+            if slope.abs() <= f32::EPSILON {
+                break;
+            }
+            u = (u - side(u) / slope).clamp(a, b);
+        }
+        if side(u).abs() > side(guess).abs() {
+            u = guess;
+        }
+
+        let point = curve.derivative_in_span(u, 0, span);
+        let normal = curve.frame(u).normal;
+        let normal = if normal.dot(&direction) > 0.0 { -normal } else { normal };
+        Some(CastHit {
+            param: u,
+            point,
+            normal,
+            distance: direction.dot(&(point - origin)),
+            span,
+        })
+    }
+
+    /// First point where a circle of `radius` moving from `origin` along the unit
+    /// vector `direction` touches the curve, within `max_distance`. Only the
+    /// parts of spans the circle could reach are visited, see [`sweep_windows`].
+    /// Along each the circle advances by its distance to the span until it
+    /// touches, so it never tunnels, and at least `MIN_CAST_STEP` of its radius
+    /// or `1 / MAX_WINDOW_STEPS` of the part per step, so a circle grazing the
+    /// curve still gets somewhere. A step that overlaps the curve is moved back
+    /// onto it.
+    pub fn circle_cast(&self, curve: &BSpline, origin: Vector2<f32>, radius: f32, direction: Vector2<f32>, max_distance: f32) -> Option<CastHit> {
+        self.counted_circle_cast(curve, origin, radius, direction, max_distance, &mut 0)
+    }
+
+    /// [`SpanBvh::circle_cast`], counting the distance queries in `queries`.
+    fn counted_circle_cast(
+        &self,
+        curve: &BSpline,
+        origin: Vector2<f32>,
+        radius: f32,
+        direction: Vector2<f32>,
+        max_distance: f32,
+        queries: &mut usize,
+    ) -> Option<CastHit> {
+        let sweep = Sweep {
+            origin,
+            direction,
+            radius,
+            max_distance,
+        };
+        let mut windows = Vec::new();
+        for span in self.spans_where(|node| node.expanded(radius).ray_interval(origin, direction, max_distance).is_some()) {
+            let segment = curve.bezier_segment(span);
+            let mut found = Vec::new();
+            sweep_windows(&segment.points, &sweep, &mut found, SWEEP_DEPTH);
+            // neighbouring pieces reach over each other by the radius
+            found.sort_by(|a, b| a.0.total_cmp(&b.0));
+            for (start, end) in found {
+                match windows.last_mut() {
+                    Some((last, _, last_end)) if *last == span && start <= *last_end => *last_end = last_end.max(end),
+                    _ => windows.push((span, start, end)),
+                }
+            }
+        }
+        windows.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        let mut closest_in = |span: usize, travelled: f32| {
+            *queries += 1;
+            let target = origin + travelled * direction;
+            let mut best = ClosestPoint {
+                param: 0.0,
+                point: Vector2::zeros(),
+                distance: f32::INFINITY,
+                span,
+            };
+            curve.closest_in_span(span, target, &mut best);
+            curve.polish_closest(target, best)
+        };
+        let mut best: Option<(f32, ClosestPoint)> = None;
+        for (span, start, end) in windows {
+            if best.is_some_and(|(distance, _)| distance <= start) {
+                break;
+            }
+            let min_step = (MIN_CAST_STEP * radius).max((end - start) / MAX_WINDOW_STEPS as f32);
+            let mut travelled = start;
+            let mut closest = closest_in(span, travelled);
+            loop {
+                let gap = closest.distance - radius;
+                if gap <= CLOSEST_POINT_TOLERANCE {
+                    break;
+                }
+                if travelled >= end {
+                    break;
+                }
+                let next = (travelled + gap.max(min_step)).min(end);
+                let next_closest = closest_in(span, next);
+                if next_closest.distance - radius >= -CLOSEST_POINT_TOLERANCE {
+                    travelled = next;
+                    closest = next_closest;
+                    continue;
+                }
+
+                // stepped into the curve, bisect back to where the circle touches it
+                let (mut outside, mut inside) = (travelled, next);
+                closest = next_closest;
+                for _ in 0..MAX_BISECTIONS {
+                    let mid = 0.5 * (outside + inside);
+                    let mid_closest = closest_in(span, mid);
+                    if mid_closest.distance - radius > 0.0 {
+                        outside = mid;
+                    } else {
+                        inside = mid;
+                        closest = mid_closest;
+                    }
+                }
+                travelled = inside;
+                break;
+            }
+            if closest.distance - radius <= CLOSEST_POINT_TOLERANCE && best.is_none_or(|(distance, _)| travelled < distance) {
+                best = Some((travelled, closest));
+            }
+        }
+
+        let (travelled, closest) = best?;
+        let normal = (origin + travelled * direction - closest.point)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(|| -direction);
+        Some(CastHit {
+            param: closest.param,
+            point: closest.point,
+            normal,
+            distance: travelled,
+            span: closest.span,
+        })
+    }
+
+    /// Spans whose boxes overlap `bounds`, in curve order.
+    pub fn overlapping(&self, bounds: &Aabb) -> Vec<usize> {
+        self.spans_where(|node| node.intersects(bounds))
+    }
+
+    /// Spans whose boxes are hit by the ray `origin + t * direction` for `t` in
+    /// `0..=max_t`, in curve order.
+    pub fn along_ray(&self, origin: Vector2<f32>, direction: Vector2<f32>, max_t: f32) -> Vec<usize> {
+        self.spans_where(|node| node.ray_interval(origin, direction, max_t).is_some())
+    }
+
+    fn spans_where(&self, hit: impl Fn(&Aabb) -> bool) -> Vec<usize> {
+        let mut spans = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop() {
+            if !hit(&self.nodes[n].bounds) {
+                continue;
+            }
+            match self.nodes[n].kind {
+                NodeKind::Leaf { span } => spans.push(span),
+                NodeKind::Inner { left, right } => stack.extend([right, left]),
+            }
+        }
+        spans
+    }
+}
+
+struct Ray {
+    origin: Vector2<f32>,
+    direction: Vector2<f32>,
+    max_distance: f32,
+}
+
+/// A circle of `radius` moving along a ray, see [`SpanBvh::circle_cast`].
+struct Sweep {
+    origin: Vector2<f32>,
+    direction: Vector2<f32>,
+    radius: f32,
+    max_distance: f32,
+}
+
+/// Distances along `sweep` within which its circle could touch a homogeneous
+/// Bézier piece, one per part of it after `depth` halvings. By the convex hull
+/// property, parts whose control points all lie further than the radius to
+/// one side of the ray, behind it or past its end are left out.
+fn sweep_windows(points: &[Vector3<f32>], sweep: &Sweep, windows: &mut Vec<(f32, f32)>, depth: usize) {
+    let k = points.len();
+    let mut side = [0.0; MAX_DEGREE + 1];
+    let mut along = [0.0; MAX_DEGREE + 1];
+    for (i, c) in points.iter().enumerate() {
+        let relative = c.xy() / c.z - sweep.origin;
+        side[i] = sweep.direction.perp(&relative);
+        along[i] = sweep.direction.dot(&relative);
+    }
+    let (side, along) = (&side[..k], &along[..k]);
+
+    let r = sweep.radius;
+    if side.iter().all(|&s| s > r) || side.iter().all(|&s| s < -r) {
+        return;
+    }
+    let nearest = along.iter().copied().fold(f32::INFINITY, f32::min) - r;
+    let furthest = along.iter().copied().fold(f32::NEG_INFINITY, f32::max) + r;
+    if furthest < 0.0 || nearest > sweep.max_distance {
+        return;
+    }
+    if depth == 0 {
+        windows.push((nearest.max(0.0), furthest.min(sweep.max_distance)));
+        return;
+    }
+
+    let mut left = [Vector3::zeros(); MAX_DEGREE + 1];
+    let mut right = [Vector3::zeros(); MAX_DEGREE + 1];
+    halve_bezier(points, &mut left[..k], &mut right[..k]);
+    sweep_windows(&left[..k], sweep, windows, depth - 1);
+    sweep_windows(&right[..k], sweep, windows, depth - 1);
+}
+
+/// Subdivides a homogeneous Bézier piece to find where it crosses `ray` first,
+/// improving on `best`, a distance along the ray and a curve parameter.
+fn ray_bezier(points: &[Vector3<f32>], range: (f32, f32), ray: &Ray, best: &mut Option<(f32, f32)>, depth: usize) {
+    let k = points.len();
+    let limit = best.map_or(ray.max_distance, |(distance, _)| distance);
+    let mut side = [0.0; MAX_DEGREE + 1];
+    let mut along = [0.0; MAX_DEGREE + 1];
+    for (i, c) in points.iter().enumerate() {
+        let relative = c.xy() - ray.origin * c.z;
+        side[i] = ray.direction.perp(&relative);
+        along[i] = ray.direction.dot(&relative);
+    }
+    let (side, along) = (&side[..k], &along[..k]);
+
+    // by the convex hull property, skip pieces off the ray's line, behind it or past the limit
+    if side.iter().all(|&s| s > 0.0) || side.iter().all(|&s| s < 0.0) {
+        return;
+    }
+    if along.iter().all(|&a| a < 0.0) || along.iter().zip(points).all(|(&a, c)| a > limit * c.z) {
+        return;
+    }
+
+    let bounds = Aabb::from_points(points.iter().map(|c| c.xy() / c.z)).expect("piece has control points");
+    if depth == 0 || (bounds.max - bounds.min).amax() < CLOSEST_POINT_TOLERANCE {
+        // where the side changes sign between the ends
+        let (s0, s1) = (side[0] / points[0].z, side[k - 1] / points[k - 1].z);
+        let t = if s0 != s1 { (s0 / (s0 - s1)).clamp(0.0, 1.0) } else { 0.5 };
+        let distance = ray.direction.dot(&(bezier_point(points, t) - ray.origin));
+        if (0.0..=limit).contains(&distance) {
+            *best = Some((distance, range.0 + t * (range.1 - range.0)));
+        }
+        return;
+    }
+
+    let mut left = [Vector3::zeros(); MAX_DEGREE + 1];
+    let mut right = [Vector3::zeros(); MAX_DEGREE + 1];
+    halve_bezier(points, &mut left[..k], &mut right[..k]);
+    let mid = 0.5 * (range.0 + range.1);
+    ray_bezier(&left[..k], (range.0, mid), ray, best, depth - 1);
+    ray_bezier(&right[..k], (mid, range.1), ray, best, depth - 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spline::tests::{check_closest, random_cases, s_curve_cases};
+
+    #[test]
+    fn closest_point_matches_brute_force() {
+        for (curve, targets) in random_cases().into_iter().chain(s_curve_cases()) {
+            let bvh = SpanBvh::new(&curve);
+            for target in targets {
+                check_closest(&curve, target, bvh.closest_point(&curve, target).unwrap());
+            }
+        }
+    }
+
+    fn floor() -> BSpline {
+        let points = (0..=10).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        BSpline::clamped_uniform(points, 3).unwrap()
+    }
+
+    #[test]
+    fn circle_cast_stops_where_the_circle_touches() {
+        let curve = floor();
+        let bvh = SpanBvh::new(&curve);
+        let hit = bvh
+            .circle_cast(&curve, Vector2::new(500.0, 100.0), 10.0, Vector2::new(0.0, -1.0), 200.0)
+            .unwrap();
+        assert!((hit.distance - 90.0).abs() < 1e-2, "{hit:?}");
+        assert!((hit.point - Vector2::new(500.0, 0.0)).norm() < 1e-2, "{hit:?}");
+        assert!((hit.normal - Vector2::new(0.0, 1.0)).norm() < 1e-3, "{hit:?}");
+
+        assert!(bvh
+            .circle_cast(&curve, Vector2::new(500.0, 100.0), 10.0, Vector2::new(0.0, -1.0), 80.0)
+            .is_none());
+    }
+
+    #[test]
+    fn grazing_circle_cast_misses() {
+        // skims along the floor and stops before where it rises
+        let mut points: Vec<_> = (0..=10).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        points[9].y = 200.0;
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let bvh = SpanBvh::new(&curve);
+        let hit = bvh.circle_cast(&curve, Vector2::new(0.0, 10.5), 10.0, Vector2::new(1.0, 0.0), 300.0);
+        assert!(hit.is_none(), "{hit:?}");
+    }
+
+    #[test]
+    fn grazing_circle_cast_touches_where_the_curve_rises() {
+        let mut points: Vec<_> = (0..=10).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        points[10].y = 200.0;
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let bvh = SpanBvh::new(&curve);
+        let origin = Vector2::new(0.0, 10.5);
+        let direction = Vector2::new(1.0, 0.0);
+        let hit = bvh.circle_cast(&curve, origin, 10.0, direction, 1000.0).unwrap();
+        let distance = bvh.closest_point(&curve, origin + hit.distance * direction).unwrap().distance;
+        assert!((distance - 10.0).abs() < 1e-2, "{hit:?} {distance}");
+        // the floor only rises in the last spans
+        assert!(hit.distance > 700.0, "{hit:?}");
+    }
+
+    #[test]
+    fn grazing_circle_cast_takes_few_steps() {
+        // a long wavy floor the circle skims closer than a step from before it rises
+        let mut points: Vec<_> = (0..=100).map(|i| Vector2::new(100.0 * i as f32, 2.0 * (i % 2) as f32)).collect();
+        points[100].y = 200.0;
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let bvh = SpanBvh::new(&curve);
+        let origin = Vector2::new(0.0, 12.5);
+        let direction = Vector2::new(1.0, 0.0);
+        let mut queries = 0;
+        let hit = bvh.counted_circle_cast(&curve, origin, 10.0, direction, 20000.0, &mut queries).unwrap();
+        let distance = bvh.closest_point(&curve, origin + hit.distance * direction).unwrap().distance;
+        assert!((distance - 10.0).abs() < 1e-2, "{hit:?} {distance}");
+        assert!(hit.distance > 9700.0, "{hit:?}");
+        assert!(queries < 100, "{queries}");
+    }
+}
@@ -0,0 +1,159 @@
+//! Geometry of contacts between bodies.
+//!
+//! Bodies are circles or capsules, both a core segment grown by a radius, a
+//! circle's segment being a single point. [`SpatialHash`] finds the pairs of
+//! bodies close enough to touch, [`closest_points_on_segments`] where their
+//! cores come closest.
+
+use std::collections::HashMap;
+
+use nalgebra::Vector2;
+
+/// Uniform grid of square cells that finds pairs of nearby points. Two points
+/// less than a cell size apart are always in the same or neighbouring cells.
+#[derive(Debug, Clone)]
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+/// Neighbouring cells each cell is paired with, half of them so every pair of
+/// cells comes up once.
+const NEIGHBOURS: [(i32, i32); 4] = [(1, -1), (1, 0), (1, 1), (0, 1)];
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, index: usize, point: Vector2<f32>) {
+        let cell = ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32);
+        self.cells.entry(cell).or_default().push(index);
+    }
+
+    /// Pairs of indices in the same or neighbouring cells, smaller index first,
+    /// each once and sorted, so they come out the same in every run.
+    pub fn pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let mut add = |a: usize, b: usize| pairs.push((a.min(b), a.max(b)));
+        for (&(x, y), indices) in &self.cells {
+            for (k, &a) in indices.iter().enumerate() {
+                for &b in &indices[k + 1..] {
+                    add(a, b);
+                }
+            }
+            for (dx, dy) in NEIGHBOURS {
+                let Some(others) = self.cells.get(&(x + dx, y + dy)) else {
+                    continue;
+                };
+                for &a in indices {
+                    for &b in others {
+                        add(a, b);
+                    }
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+/// Closest points of the segments `p1 q1` and `p2 q2`, one on each. Either may
+/// be a single point.
+pub fn closest_points_on_segments(p1: Vector2<f32>, q1: Vector2<f32>, p2: Vector2<f32>, q2: Vector2<f32>) -> (Vector2<f32>, Vector2<f32>) {
+    let (d1, d2, r) = (q1 - p1, q2 - p2, p1 - p2);
+    let (a, e, f) = (d1.dot(&d1), d2.dot(&d2), d2.dot(&r));
+    if a <= f32::EPSILON && e <= f32::EPSILON {
+        return (p1, p2);
+    }
+    let (s, t) = if a <= f32::EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(&r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            // closest points of the infinite lines, then clamped to the segments
+            let b = d1.dot(&d2);
+            let denominator = a * e - b * b;
+            let s = if denominator > f32::EPSILON {
+                ((b * f - c * e) / denominator).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let t = (b * s + f) / e;
+            if t < 0.0 {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else if t > 1.0 {
+                (((b - c) / a).clamp(0.0, 1.0), 1.0)
+            } else {
+                (s, t)
+            }
+        }
+    };
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn spatial_hash_finds_every_close_pair() {
+        let mut rng = StdRng::seed_from_u64(23);
+        let points: Vec<Vector2<f32>> = (0..300)
+            .map(|_| Vector2::new(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)))
+            .collect();
+        let mut hash = SpatialHash::new(10.0);
+        for (i, &point) in points.iter().enumerate() {
+            hash.insert(i, point);
+        }
+        let pairs = hash.pairs();
+
+        assert!(pairs.windows(2).all(|w| w[0] < w[1]), "pairs not sorted or repeated");
+        assert!(pairs.iter().all(|&(a, b)| a < b));
+        for a in 0..points.len() {
+            for b in a + 1..points.len() {
+                if (points[a] - points[b]).norm() < 10.0 {
+                    assert!(pairs.binary_search(&(a, b)).is_ok(), "{a} and {b} are close but not paired");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn closest_points_on_segments_match_brute_force() {
+        let mut rng = StdRng::seed_from_u64(29);
+        let mut point = || Vector2::new(rng.gen_range(-10.0..10.0), rng.gen_range(-10.0..10.0));
+        for case in 0..300 {
+            let (p1, mut q1, p2, mut q2) = (point(), point(), point(), point());
+            // single points and parallel segments as well
+            match case % 5 {
+                0 => q1 = p1,
+                1 => q2 = p2,
+                2 => q2 = p2 + 0.5 * (q1 - p1),
+                _ => {}
+            }
+
+            let (c1, c2) = closest_points_on_segments(p1, q1, p2, q2);
+            let on = |c: Vector2<f32>, p: Vector2<f32>, q: Vector2<f32>| (c - p).norm() + (q - c).norm() - (q - p).norm() < 1e-3;
+            assert!(on(c1, p1, q1) && on(c2, p2, q2), "{c1} {c2} off their segments");
+
+            let samples = |p: Vector2<f32>, q: Vector2<f32>| (0..=200).map(move |i| p.lerp(&q, i as f32 / 200.0));
+            let brute = samples(p1, q1)
+                .flat_map(|a| samples(p2, q2).map(move |b| (a - b).norm()))
+                .fold(f32::INFINITY, f32::min);
+            assert!(
+                (c1 - c2).norm() <= brute + 1e-4,
+                "{} farther than {brute} for {p1} {q1} {p2} {q2}",
+                (c1 - c2).norm()
+            );
+        }
+    }
+}
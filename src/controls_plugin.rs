@@ -1,7 +1,5 @@
-use bevy::app::{App, FixedUpdate, Plugin};
+use bevy::app::{App, Plugin};
 use bevy::prelude::{Component, Deref, Entity, Query, RelationshipTarget, Res, Time, Transform, Update, Without};
-use crate::physics_plugin::PhySched;
-use crate::spines_plugin::{ControlledBy, Position, Spline, VisualizedBy};
 
 pub struct ControlsPlugin;
 
@@ -0,0 +1,437 @@
+//! Filled areas bounded by splines, e.g. the ground under a terrain spline.
+//!
+//! The area is a polygon: the center line of the spline as a polyline, closed
+//! by the curve itself for loops and by extra corners below it otherwise.
+//! [`triangulate`] sweeps across it to cut it into pieces that are monotone
+//! along x, then fans each piece out into triangles in one pass.
+
+use std::collections::HashSet;
+use std::f32::consts::TAU;
+
+use nalgebra::Vector2;
+
+use crate::batch::SplineSampler;
+use crate::spline::{distance_to_segment, BSpline};
+
+/// Intervals per knot span that [`outline`] starts from, so bends whose
+/// middle happens to lie on the chord aren't missed.
+const OUTLINE_SAMPLES: usize = 2;
+
+/// Subdivisions of a starting interval after which it is used as it is.
+const MAX_OUTLINE_DEPTH: usize = 8;
+
+/// Polygon of the area enclosed by `curve`, with the center line no farther
+/// than `tolerance` from the exact curve. Loops enclose an area on their own
+/// and ignore `below`, open curves go on from their end through the corners of
+/// `below` back to their start.
+pub fn outline(curve: &BSpline, tolerance: f32, below: &[Vector2<f32>]) -> Vec<Vector2<f32>> {
+    let knots = curve.knots();
+    let (start, _) = curve.domain();
+    // the parameters mostly go up, so the sampler sets up each span about once
+    let mut sampler = SplineSampler::new(curve);
+    let mut points = vec![sampler.eval(start)];
+
+    for l in curve.degree()..curve.control_points().len() {
+        let (a, b) = (knots[l], knots[l + 1]);
+        if a == b {
+            continue;
+        }
+        let step = (b - a) / OUTLINE_SAMPLES as f32;
+        for i in 0..OUTLINE_SAMPLES {
+            let u0 = a + i as f32 * step;
+            let p0 = points[points.len() - 1];
+            let end = (u0 + step, sampler.eval(u0 + step));
+            subdivide(&mut sampler, (u0, p0), end, tolerance, 0, &mut points);
+        }
+    }
+
+    if curve.is_closed() {
+        // the end of a loop is its start again
+        points.pop();
+    } else {
+        points.extend_from_slice(below);
+    }
+    points
+}
+
+/// Appends the points in `(u0, u1]`, given with their parameters, subdividing
+/// until the chord is within `tolerance` of the midpoint.
+fn subdivide(
+    sampler: &mut SplineSampler,
+    (u0, p0): (f32, Vector2<f32>),
+    (u1, p1): (f32, Vector2<f32>),
+    tolerance: f32,
+    depth: usize,
+    points: &mut Vec<Vector2<f32>>,
+) {
+    let mid = 0.5 * (u0 + u1);
+    let pm = sampler.eval(mid);
+    if distance_to_segment(pm, p0, p1) <= tolerance || depth >= MAX_OUTLINE_DEPTH {
+        points.push(p1);
+        return;
+    }
+    subdivide(sampler, (u0, p0), (mid, pm), tolerance, depth + 1, points);
+    subdivide(sampler, (mid, pm), (u1, p1), tolerance, depth + 1, points);
+}
+
+/// Triangles covering the simple polygon `points`, in either winding, as
+/// counter-clockwise triples of indices into it. Corners on a straight line
+/// get no triangle of their own. A self-intersecting polygon is only partly
+/// covered, or covered twice in places.
+pub fn triangulate(points: &[Vector2<f32>]) -> Vec<[u32; 3]> {
+    // the corners counter-clockwise, without repeats
+    let mut corners: Vec<usize> = (0..points.len()).collect();
+    if signed_area(points) < 0.0 {
+        corners.reverse();
+    }
+    corners.dedup_by(|a, b| points[*a] == points[*b]);
+    while corners.len() > 1 && points[corners[0]] == points[corners[corners.len() - 1]] {
+        corners.pop();
+    }
+    if corners.len() < 3 {
+        return Vec::new();
+    }
+
+    let polygon = Polygon::new(points, corners);
+    let diagonals = polygon.monotone_diagonals();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+    for piece in polygon.pieces(&diagonals) {
+        polygon.triangulate_monotone(&piece, &mut triangles);
+    }
+    triangles
+}
+
+/// Counter-clockwise polygon for [`triangulate`]. Its corners are turned a
+/// quarter clockwise, so sweeping from the top down goes along x in the input,
+/// which keeps the sweep line short for ground under a terrain spline.
+struct Polygon<'a> {
+    points: &'a [Vector2<f32>],
+    corners: Vec<usize>,
+    turned: Vec<Vector2<f32>>,
+}
+
+/// Edge from a corner to the next one that the sweep line crosses, with the
+/// interior of the polygon to its right.
+#[derive(Debug, Clone, Copy)]
+struct SweepEdge {
+    corner: usize,
+    /// Lowest corner above the sweep line that sees the edge, where a diagonal
+    /// can go.
+    helper: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CornerKind {
+    Start,
+    Split,
+    End,
+    Merge,
+    Regular,
+}
+
+impl<'a> Polygon<'a> {
+    fn new(points: &'a [Vector2<f32>], corners: Vec<usize>) -> Self {
+        let turned = corners.iter().map(|&i| Vector2::new(points[i].y, -points[i].x)).collect();
+        Self { points, corners, turned }
+    }
+
+    fn next(&self, k: usize) -> usize {
+        (k + 1) % self.corners.len()
+    }
+
+    fn prev(&self, k: usize) -> usize {
+        (k + self.corners.len() - 1) % self.corners.len()
+    }
+
+    /// Whether the sweep line reaches corner `a` before `b`. Ties go to the
+    /// left, as if the polygon were turned a little more.
+    fn above(&self, a: usize, b: usize) -> bool {
+        let (pa, pb) = (self.turned[a], self.turned[b]);
+        pa.y > pb.y || (pa.y == pb.y && pa.x < pb.x)
+    }
+
+    /// Corners in the order the sweep line reaches them.
+    fn sweep_order(&self, corners: &mut [usize]) {
+        corners.sort_by(|&a, &b| {
+            let (pa, pb) = (self.turned[a], self.turned[b]);
+            pb.y.total_cmp(&pa.y).then(pa.x.total_cmp(&pb.x))
+        });
+    }
+
+    fn kind(&self, k: usize) -> CornerKind {
+        let (p, n) = (self.prev(k), self.next(k));
+        let convex = cross(self.turned[k] - self.turned[p], self.turned[n] - self.turned[k]) > 0.0;
+        match (self.above(k, p) && self.above(k, n), self.above(p, k) && self.above(n, k)) {
+            (true, _) if convex => CornerKind::Start,
+            (true, _) => CornerKind::Split,
+            (_, true) if convex => CornerKind::End,
+            (_, true) => CornerKind::Merge,
+            _ => CornerKind::Regular,
+        }
+    }
+
+    /// Where the edge starting at corner `k` crosses the sweep line at `y`.
+    fn x_at(&self, k: usize, y: f32) -> f32 {
+        let (a, b) = (self.turned[k], self.turned[self.next(k)]);
+        if a.y == b.y {
+            a.x.min(b.x)
+        } else {
+            a.x + (y - a.y) * (b.x - a.x) / (b.y - a.y)
+        }
+    }
+
+    /// Diagonals that cut the polygon into pieces monotone along the sweep, one
+    /// from every split corner up and from every merge corner down.
+    fn monotone_diagonals(&self) -> Vec<(usize, usize)> {
+        let n = self.corners.len();
+        let kinds: Vec<CornerKind> = (0..n).map(|k| self.kind(k)).collect();
+        let mut order: Vec<usize> = (0..n).collect();
+        self.sweep_order(&mut order);
+
+        // ordered left to right along the sweep line
+        let mut status: Vec<SweepEdge> = Vec::new();
+        let mut diagonals = Vec::new();
+        for k in order {
+            let p = self.turned[k];
+            let left_of = |status: &[SweepEdge]| status.partition_point(|edge| self.x_at(edge.corner, p.y) < p.x).checked_sub(1);
+            let finish = |status: &mut Vec<SweepEdge>, diagonals: &mut Vec<(usize, usize)>| {
+                if let Some(i) = status.iter().position(|edge| edge.corner == self.prev(k)) {
+                    let edge = status.remove(i);
+                    if kinds[edge.helper] == CornerKind::Merge {
+                        diagonals.push((k, edge.helper));
+                    }
+                }
+            };
+            let start = |status: &mut Vec<SweepEdge>| {
+                let i = status.partition_point(|edge| self.x_at(edge.corner, p.y) < p.x);
+                status.insert(i, SweepEdge { corner: k, helper: k });
+            };
+
+            match kinds[k] {
+                CornerKind::Start => start(&mut status),
+                CornerKind::End => finish(&mut status, &mut diagonals),
+                CornerKind::Split => {
+                    if let Some(i) = left_of(&status) {
+                        diagonals.push((k, status[i].helper));
+                        status[i].helper = k;
+                    }
+                    start(&mut status);
+                }
+                CornerKind::Merge => {
+                    finish(&mut status, &mut diagonals);
+                    if let Some(i) = left_of(&status) {
+                        if kinds[status[i].helper] == CornerKind::Merge {
+                            diagonals.push((k, status[i].helper));
+                        }
+                        status[i].helper = k;
+                    }
+                }
+                // the interior is to the right where the boundary goes down
+                CornerKind::Regular if self.above(self.prev(k), k) => {
+                    finish(&mut status, &mut diagonals);
+                    start(&mut status);
+                }
+                CornerKind::Regular => {
+                    if let Some(i) = left_of(&status) {
+                        if kinds[status[i].helper] == CornerKind::Merge {
+                            diagonals.push((k, status[i].helper));
+                        }
+                        status[i].helper = k;
+                    }
+                }
+            }
+        }
+        diagonals
+    }
+
+    /// The pieces the diagonals cut the polygon into, as counter-clockwise
+    /// loops of corners.
+    fn pieces(&self, diagonals: &[(usize, usize)]) -> Vec<Vec<usize>> {
+        let n = self.corners.len();
+        let mut neighbours: Vec<Vec<usize>> = (0..n).map(|k| vec![self.prev(k), self.next(k)]).collect();
+        for &(a, b) in diagonals {
+            neighbours[a].push(b);
+            neighbours[b].push(a);
+        }
+
+        // every side of a piece, walked with the piece on its left
+        let mut sides: Vec<(usize, usize)> = (0..n).map(|k| (k, self.next(k))).collect();
+        sides.extend(diagonals.iter().flat_map(|&(a, b)| [(a, b), (b, a)]));
+        let mut walked = HashSet::new();
+        let mut pieces = Vec::new();
+        for side in sides {
+            if walked.contains(&side) {
+                continue;
+            }
+            let mut piece = Vec::new();
+            let (mut from, mut to) = side;
+            while walked.insert((from, to)) && piece.len() <= n {
+                piece.push(from);
+                // the sharpest left turn stays in the piece
+                let back = self.turned[from] - self.turned[to];
+                let turn = |k: &usize| {
+                    let d = self.turned[*k] - self.turned[to];
+                    cross(d, back).atan2(back.dot(&d)).rem_euclid(TAU)
+                };
+                let Some(&next) = neighbours[to].iter().filter(|&&k| k != from).min_by(|a, b| turn(a).total_cmp(&turn(b))) else {
+                    break;
+                };
+                (from, to) = (to, next);
+            }
+            pieces.push(piece);
+        }
+        pieces
+    }
+
+    /// Triangles of the monotone counter-clockwise `piece`, fanning out from
+    /// each corner in sweep order to the corners above it that it sees.
+    fn triangulate_monotone(&self, piece: &[usize], triangles: &mut Vec<[u32; 3]>) {
+        if piece.len() < 3 {
+            return;
+        }
+        let mut order = piece.to_vec();
+        self.sweep_order(&mut order);
+        let (top, bottom) = (order[0], order[order.len() - 1]);
+
+        // counter-clockwise from the top is down the left chain
+        let mut left = HashSet::new();
+        let first = piece.iter().position(|&k| k == top).unwrap_or(0);
+        for &k in piece.iter().cycle().skip(first + 1).take_while(|&&k| k != bottom) {
+            left.insert(k);
+        }
+
+        let mut stack = vec![order[0], order[1]];
+        for j in 2..order.len() - 1 {
+            let k = order[j];
+            let on_left = left.contains(&k);
+            if stack.last().is_some_and(|top| left.contains(top) != on_left) {
+                for w in stack.windows(2) {
+                    self.push_triangle(triangles, k, w[0], w[1]);
+                }
+                stack = vec![order[j - 1], k];
+                continue;
+            }
+            let Some(mut last) = stack.pop() else {
+                continue;
+            };
+            while let Some(&top) = stack.last() {
+                let (a, b, c) = (self.turned[top], self.turned[last], self.turned[k]);
+                let convex = if on_left { cross(b - a, c - b) > 0.0 } else { cross(b - c, a - b) > 0.0 };
+                if !convex {
+                    break;
+                }
+                self.push_triangle(triangles, k, last, top);
+                last = top;
+                stack.pop();
+            }
+            stack.push(last);
+            stack.push(k);
+        }
+        for w in stack.windows(2) {
+            self.push_triangle(triangles, bottom, w[0], w[1]);
+        }
+    }
+
+    /// Adds the triangle of three corners counter-clockwise, unless it is flat.
+    fn push_triangle(&self, triangles: &mut Vec<[u32; 3]>, a: usize, b: usize, c: usize) {
+        let [a, b, c] = [a, b, c].map(|k| self.corners[k]);
+        let turn = cross(self.points[b] - self.points[a], self.points[c] - self.points[a]);
+        if turn > 0.0 {
+            triangles.push([a as u32, b as u32, c as u32]);
+        } else if turn < 0.0 {
+            triangles.push([a as u32, c as u32, b as u32]);
+        }
+    }
+}
+
+/// Twice the area of the polygon, positive for counter-clockwise ones.
+fn signed_area(points: &[Vector2<f32>]) -> f32 {
+    let n = points.len();
+    (0..n).map(|i| cross(points[i], points[(i + 1) % n])).sum()
+}
+
+fn cross(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// Whether `point` is inside `polygon`, by counting crossings of a ray to the right.
+    fn inside(polygon: &[Vector2<f32>], point: Vector2<f32>) -> bool {
+        let n = polygon.len();
+        (0..n)
+            .filter(|&i| {
+                let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+                (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+            })
+            .count()
+            % 2
+            == 1
+    }
+
+    /// Asserts that `triangles` are counter-clockwise, inside `polygon`, and add
+    /// up to its area.
+    fn check_covers(polygon: &[Vector2<f32>], triangles: &[[u32; 3]]) {
+        let mut area = 0.0;
+        for &[a, b, c] in triangles {
+            let (a, b, c) = (polygon[a as usize], polygon[b as usize], polygon[c as usize]);
+            let twice = cross(b - a, c - a);
+            assert!(twice > 0.0, "{a} {b} {c} is not counter-clockwise");
+            assert!(inside(polygon, (a + b + c) / 3.0), "{a} {b} {c} is outside");
+            area += twice;
+        }
+        let expected = signed_area(polygon).abs();
+        assert!((area - expected).abs() <= 1e-3 * expected, "triangles cover {area}, polygon {expected}");
+    }
+
+    #[test]
+    fn comb_is_covered_by_n_minus_two_triangles() {
+        // teeth pointing up and down, with split and merge corners on both sides
+        let mut comb = Vec::new();
+        for i in 0..10 {
+            let x = 10.0 * i as f32;
+            comb.extend([Vector2::new(x, 0.0), Vector2::new(x + 5.0, if i % 2 == 0 { -20.0 } else { -30.0 })]);
+        }
+        comb.push(Vector2::new(100.0, 0.0));
+        for i in (0..10).rev() {
+            let x = 10.0 * i as f32;
+            comb.extend([Vector2::new(x + 10.0, 40.0), Vector2::new(x + 5.0, 15.0)]);
+        }
+
+        let triangles = triangulate(&comb);
+        assert_eq!(triangles.len(), comb.len() - 2);
+        check_covers(&comb, &triangles);
+
+        comb.reverse();
+        check_covers(&comb, &triangulate(&comb));
+    }
+
+    #[test]
+    fn ground_under_a_wavy_line_is_covered() {
+        let mut ground: Vec<_> = (0..500).map(|i| Vector2::new(i as f32 * 40.0, 200.0 * (i as f32 * 0.3).sin())).collect();
+        ground.extend([Vector2::new(19960.0, -1000.0), Vector2::new(0.0, -1000.0)]);
+        let triangles = triangulate(&ground);
+        assert_eq!(triangles.len(), ground.len() - 2);
+        check_covers(&ground, &triangles);
+    }
+
+    #[test]
+    fn random_star_polygons_are_covered() {
+        let mut rng = StdRng::seed_from_u64(13);
+        for _ in 0..50 {
+            let n = rng.gen_range(3..60);
+            let mut angles: Vec<f32> = (0..n).map(|_| rng.gen_range(0.0..TAU)).collect();
+            angles.sort_by(f32::total_cmp);
+            let star: Vec<_> = angles
+                .iter()
+                .map(|a| rng.gen_range(10.0..100.0) * Vector2::new(a.cos(), a.sin()))
+                .collect();
+            check_covers(&star, &triangulate(&star));
+        }
+    }
+}
@@ -0,0 +1,366 @@
+//! Fitting splines to dense point sequences such as mouse strokes.
+//!
+//! [`simplify`] thins out the points with Douglas-Peucker. [`fit`] starts from
+//! a single knot span and solves for the control points in the least squares
+//! sense, with the ends pinned to the first and last point. After each solve
+//! the points move along the curve to where they are closest, and spans that
+//! still have points farther away than the tolerance are split at their median
+//! point, until the whole curve is within the tolerance. [`fit_stroke`] does
+//! both for raw input, with knots at the corners of the simplified stroke and
+//! few control points anywhere else, and tells how close it got.
+
+use nalgebra::{DMatrix, Vector2};
+
+use crate::bvh::SpanBvh;
+use crate::spline::{basis_functions, distance_to_segment, Aabb, BSpline, MAX_DEGREE};
+
+/// Solves per knot vector, each after moving the points' parameters to their
+/// closest points on the last curve.
+const PARAMETER_ROUNDS: usize = 3;
+
+/// Weight of a penalty on bending control polygons, in units of the size of the
+/// points. Keeps the system solvable where a span has no points of its own.
+const FAIRING: f64 = 1e-6;
+
+/// Part of the tolerance [`fit_stroke`] spends on simplifying the stroke.
+const SIMPLIFY_SHARE: f32 = 0.25;
+
+/// Most points [`fit_stroke`] fits, spread along the simplified stroke.
+const MAX_FIT_POINTS: usize = 1024;
+
+/// Cosine of the turn above which a point of the simplified stroke is a
+/// corner, see [`fit_stroke`]: 45 degrees.
+const CORNER_COS: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+/// Length, in tolerances, that the simplified stroke has to run straight on
+/// both sides of a corner, so a shaky stroke doesn't get corners everywhere.
+const CORNER_LEG: f32 = 4.0;
+
+/// The points of `points` that Douglas-Peucker keeps: the ends, and recursively
+/// the point farthest from the line between the points kept on either side, as
+/// long as it is farther than `tolerance`.
+pub fn simplify(points: &[Vector2<f32>], tolerance: f32) -> Vec<Vector2<f32>> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let last = points.len() - 1;
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[last] = true;
+
+    let mut stack = vec![(0, last)];
+    while let Some((a, b)) = stack.pop() {
+        let farthest = (a + 1..b)
+            .map(|i| (i, distance_to_segment(points[i], points[a], points[b])))
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+        if let Some((i, distance)) = farthest {
+            if distance > tolerance {
+                keep[i] = true;
+                stack.push((a, i));
+                stack.push((i, b));
+            }
+        }
+    }
+    points.iter().zip(keep).filter(|(_, keep)| *keep).map(|(point, _)| *point).collect()
+}
+
+/// Least squares B-spline of `degree` from the first to the last of `points`
+/// that none of them is farther than `tolerance` from, with knots only where
+/// they are needed. Knots run from 0 to 1. `None` for fewer than two distinct
+/// points.
+pub fn fit(points: &[Vector2<f32>], degree: usize, tolerance: f32) -> Option<BSpline> {
+    fit_at_most(points, degree, tolerance, usize::MAX, &[])
+}
+
+/// [`fit`] with a knot of full multiplicity at each of the points with the
+/// indices `corners`, where the curve may turn sharply, that stops splitting
+/// spans at `max_control_points`, even where the curve isn't within the
+/// tolerance yet. The corners get their control points regardless.
+fn fit_at_most(points: &[Vector2<f32>], degree: usize, tolerance: f32, max_control_points: usize, corners: &[usize]) -> Option<BSpline> {
+    let mut data: Vec<Vector2<f32>> = Vec::with_capacity(points.len());
+    // where each of the points ended up in `data`
+    let mut index = Vec::with_capacity(points.len());
+    for &point in points {
+        if data.last() != Some(&point) {
+            data.push(point);
+        }
+        index.push(data.len() - 1);
+    }
+    if data.len() < 2 {
+        return None;
+    }
+    let m = data.len();
+    let p = degree.clamp(1, MAX_DEGREE).min(m - 1);
+
+    // solved around the first point at unit size, so the fairing is the same at any scale
+    let origin = data[0];
+    let bounds = Aabb::from_points(data.iter().copied())?;
+    let scale = (bounds.max - bounds.min).amax();
+    for point in &mut data {
+        *point = (*point - origin) / scale;
+    }
+    let tolerance = tolerance / scale;
+
+    // chord length parameters
+    let mut chord = Vec::with_capacity(m);
+    let mut length = 0.0;
+    chord.push(0.0);
+    for w in data.windows(2) {
+        length += (w[1] - w[0]).norm();
+        chord.push(length);
+    }
+    for u in &mut chord {
+        *u /= length;
+    }
+
+    let mut knots: Vec<f32> = [vec![0.0; p + 1], vec![1.0; p + 1]].concat();
+    for &corner in corners {
+        let u = chord[index[corner]];
+        if 0.0 < u && u < 1.0 && !knots.contains(&u) {
+            knots.extend([u].repeat(p));
+        }
+    }
+    knots.sort_by(f32::total_cmp);
+    // as many control points as points interpolate them
+    let limit = max_control_points.min(m).max(knots.len() - p - 1);
+
+    loop {
+        // corrections on a coarse curve would only mislead finer ones
+        let mut params = chord.clone();
+        let mut curve = solve(&data, &params, &knots, p)?;
+        for _ in 0..PARAMETER_ROUNDS {
+            project(&curve, &data, &mut params);
+            curve = solve(&data, &params, &knots, p)?;
+        }
+
+        let count = knots.len() - p - 1;
+        if count >= limit {
+            return unscaled(&curve, origin, scale);
+        }
+        let mut splits = Vec::new();
+        for l in p..count {
+            let (a, b) = (knots[l], knots[l + 1]);
+            let mut inside: Vec<(f32, f32)> = params
+                .iter()
+                .zip(&data)
+                .filter(|(&u, _)| a <= u && (u < b || (l == count - 1 && u <= b)))
+                .map(|(&u, &point)| (u, (curve.eval(u) - point).norm()))
+                .collect();
+            if a == b || inside.iter().all(|&(_, error)| error <= tolerance) {
+                continue;
+            }
+            inside.sort_by(|x, y| x.0.total_cmp(&y.0));
+            let median = inside[inside.len() / 2].0;
+            splits.push(if a < median && median < b { median } else { 0.5 * (a + b) });
+        }
+        if splits.is_empty() {
+            return unscaled(&curve, origin, scale);
+        }
+        splits.truncate(limit - count);
+        knots.extend(splits);
+        knots.sort_by(f32::total_cmp);
+    }
+}
+
+fn unscaled(curve: &BSpline, origin: Vector2<f32>, scale: f32) -> Option<BSpline> {
+    let control_points = curve.control_points().iter().map(|c| origin + c * scale).collect();
+    BSpline::new(control_points, curve.degree(), curve.knots().to_vec()).ok()
+}
+
+/// Result of [`fit_stroke`].
+#[derive(Debug, Clone)]
+pub struct StrokeFit {
+    pub curve: BSpline,
+    /// Farthest any point of the stroke is from `curve`, within the tolerance
+    /// unless the stroke has more detail than the curve's control points allow.
+    pub error: f32,
+}
+
+/// [`fit`] on raw input such as a mouse stroke. The stroke is thinned out with
+/// [`simplify`] first, which takes a quarter of the tolerance, and the fit
+/// follows the simplified polyline rather than only its corners. Where the
+/// simplified stroke turns sharply between two long straight stretches, as in
+/// a zigzag, the curve gets a knot of full multiplicity and follows the corner.
+/// Apart from those the curve gets at most as many control points as the
+/// simplified stroke has points, so a shaky stroke doesn't make a wiggly curve.
+/// A stroke with more detail than that many control points can follow, such
+/// as tight teeth too short to be corners, ends up farther than `tolerance`
+/// from the curve, see [`StrokeFit::error`].
+pub fn fit_stroke(stroke: &[Vector2<f32>], degree: usize, tolerance: f32) -> Option<StrokeFit> {
+    let simplified = simplify(stroke, SIMPLIFY_SHARE * tolerance);
+    let length: f32 = simplified.windows(2).map(|w| (w[1] - w[0]).norm()).sum();
+    let spacing = tolerance.max(length / MAX_FIT_POINTS as f32);
+
+    let mut points = Vec::with_capacity(MAX_FIT_POINTS + simplified.len());
+    let mut corners = Vec::new();
+    points.extend(simplified.first());
+    for (i, w) in simplified.windows(2).enumerate() {
+        let steps = ((w[1] - w[0]).norm() / spacing).ceil().max(1.0) as usize;
+        points.extend((1..=steps).map(|i| w[0].lerp(&w[1], i as f32 / steps as f32)));
+        if simplified.get(i + 2).is_some_and(|&next| is_corner(w[0], w[1], next, tolerance)) {
+            corners.push(points.len() - 1);
+        }
+    }
+    let max_control_points = simplified.len() + degree * corners.len();
+    let curve = fit_at_most(&points, degree, (1.0 - SIMPLIFY_SHARE) * tolerance, max_control_points, &corners)?;
+
+    let bvh = SpanBvh::new(&curve);
+    let error = stroke
+        .iter()
+        .filter_map(|&point| bvh.closest_point(&curve, point))
+        .map(|closest| closest.distance)
+        .fold(0.0, f32::max);
+    Some(StrokeFit { curve, error })
+}
+
+/// Whether a stroke running from `a` through `b` to `c` turns sharply at `b`,
+/// see [`CORNER_COS`] and [`CORNER_LEG`].
+fn is_corner(a: Vector2<f32>, b: Vector2<f32>, c: Vector2<f32>, tolerance: f32) -> bool {
+    let (before, after) = (b - a, c - b);
+    let leg = CORNER_LEG * tolerance;
+    before.norm() >= leg && after.norm() >= leg && before.normalize().dot(&after.normalize()) < CORNER_COS
+}
+
+/// Control points of the curve with `knots` closest to `data` at `params` in the
+/// least squares sense, with the ends fixed to the first and last point.
+fn solve(data: &[Vector2<f32>], params: &[f32], knots: &[f32], p: usize) -> Option<BSpline> {
+    let m = data.len();
+    let n = knots.len() - p - 1;
+    let (first, last) = (data[0], data[m - 1]);
+    let mut control_points = vec![first; n];
+    control_points[n - 1] = last;
+    if n <= 2 {
+        return BSpline::new(control_points, p, knots.to_vec()).ok();
+    }
+
+    // normal equations for the inner control points 1..n - 1
+    let fixed = |i: usize| match i {
+        0 => Some(first),
+        i if i == n - 1 => Some(last),
+        _ => None,
+    };
+    let mut a = DMatrix::<f64>::zeros(n - 2, n - 2);
+    let mut b = DMatrix::<f64>::zeros(n - 2, 2);
+    let mut add = |row: &[(usize, f64)], target: Vector2<f32>, weight: f64| {
+        let mut rest = target.cast::<f64>();
+        for &(i, value) in row {
+            if let Some(point) = fixed(i) {
+                rest -= value * point.cast::<f64>();
+            }
+        }
+        for &(i, value) in row.iter().filter(|(i, _)| fixed(*i).is_none()) {
+            b[(i - 1, 0)] += weight * value * rest.x;
+            b[(i - 1, 1)] += weight * value * rest.y;
+            for &(j, other) in row.iter().filter(|(j, _)| fixed(*j).is_none()) {
+                a[(i - 1, j - 1)] += weight * value * other;
+            }
+        }
+    };
+
+    for k in 1..m - 1 {
+        let u = params[k];
+        let l = knots.partition_point(|&knot| knot <= u).saturating_sub(1).clamp(p, n - 1);
+        let basis = basis_functions(knots, p, u, l);
+        let row: Vec<(usize, f64)> = (0..=p).map(|j| (l - p + j, basis[j] as f64)).collect();
+        add(&row, data[k], 1.0);
+    }
+    for i in 1..n - 1 {
+        add(&[(i - 1, 1.0), (i, -2.0), (i + 1, 1.0)], Vector2::zeros(), FAIRING);
+    }
+
+    let x = a.cholesky()?.solve(&b);
+    for i in 1..n - 1 {
+        control_points[i] = Vector2::new(x[(i - 1, 0)] as f32, x[(i - 1, 1)] as f32);
+    }
+    BSpline::new(control_points, p, knots.to_vec()).ok()
+}
+
+/// Moves the parameters of the inner points to their closest points on `curve`
+/// with a few Newton steps.
+fn project(curve: &BSpline, data: &[Vector2<f32>], params: &mut [f32]) {
+    let m = data.len();
+    for k in 1..m - 1 {
+        let mut u = params[k];
+        for _ in 0..2 {
+            let diff = curve.eval(u) - data[k];
+            let d1 = curve.derivative(u, 1);
+            let d2 = curve.derivative(u, 2);
+            let curvature = d1.dot(&d1) + diff.dot(&d2);
+            if curvature <= f32::EPSILON {
+                break;
+            }
+            u = (u - diff.dot(&d1) / curvature).clamp(0.0, 1.0);
+        }
+        params[k] = u;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worst_distance(curve: &BSpline, points: &[Vector2<f32>]) -> f32 {
+        points.iter().map(|&point| curve.closest_point(point).distance).fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn smooth_stroke_is_fitted_within_the_tolerance() {
+        let stroke: Vec<_> = (0..300).map(|i| Vector2::new(3.0 * i as f32, 150.0 * (0.05 * i as f32).sin())).collect();
+        let StrokeFit { curve, error } = fit_stroke(&stroke, 3, 2.0).unwrap();
+        assert!(worst_distance(&curve, &stroke) <= 2.0, "{curve:?}");
+        assert!((error - worst_distance(&curve, &stroke)).abs() < 1e-3, "{error}");
+        assert!(curve.control_points().len() <= simplify(&stroke, SIMPLIFY_SHARE * 2.0).len());
+    }
+
+    #[test]
+    fn zigzag_stroke_follows_its_corners_with_few_control_points() {
+        let corners = 7;
+        let corner = |i: usize| Vector2::new(100.0 * i as f32, if i.is_multiple_of(2) { 0.0 } else { 60.0 });
+        // densely sampled, as a mouse would
+        let mut stroke: Vec<_> = (0..=corners)
+            .flat_map(|i| (0..50).map(move |k| corner(i).lerp(&corner(i + 1), k as f32 / 50.0)))
+            .collect();
+        stroke.push(corner(corners + 1));
+
+        let degree = 3;
+        let curve = fit_stroke(&stroke, degree, 2.0).unwrap().curve;
+        // a knot of full multiplicity per corner and nothing else
+        assert!(
+            curve.control_points().len() <= degree * corners + degree + 1,
+            "{} control points",
+            curve.control_points().len()
+        );
+        assert!(worst_distance(&curve, &stroke) <= 2.0, "{curve:?}");
+        assert!((curve.eval(0.0) - stroke[0]).norm() < 1e-3);
+        assert!((curve.eval(1.0) - stroke[stroke.len() - 1]).norm() < 1e-3);
+    }
+
+    #[test]
+    fn shaky_stroke_gets_no_corners() {
+        // a gentle curve, drawn by a shaky hand
+        let stroke: Vec<_> = (0..300)
+            .map(|i| Vector2::new(2.0 * i as f32, 50.0 * (0.01 * i as f32).sin() + if i % 2 == 0 { 1.5 } else { -1.5 }))
+            .collect();
+        let curve = fit_stroke(&stroke, 3, 4.0).unwrap().curve;
+        let knots = curve.knots();
+        assert!(knots.windows(3).all(|w| w[0] != w[2] || w[0] == 0.0 || w[0] == 1.0), "{knots:?}");
+    }
+
+    #[test]
+    fn stroke_too_detailed_for_its_control_points_reports_how_far_off_it_is() {
+        // teeth too short to be corners, as many control points as tips can't follow them
+        let tips: Vec<_> = (0..=40)
+            .map(|i| Vector2::new(5.0 * i as f32, if i % 2 == 0 { 0.0 } else { 10.0 }))
+            .collect();
+        let stroke: Vec<_> = tips
+            .windows(2)
+            .flat_map(|w| (0..10).map(move |k| w[0].lerp(&w[1], k as f32 / 10.0)))
+            .chain(tips.last().copied())
+            .collect();
+
+        let StrokeFit { curve, error } = fit_stroke(&stroke, 3, 4.0).unwrap();
+        assert!(curve.control_points().len() <= tips.len(), "{curve:?}");
+        assert!(error > 4.0, "{error}");
+        assert!((error - worst_distance(&curve, &stroke)).abs() < 1e-3, "{error}");
+    }
+}
@@ -0,0 +1,506 @@
+//! Intersections between splines and of a spline with itself.
+//!
+//! Both curves are cut into pieces whose control polygons are monotone in x and
+//! y, which can't cross themselves. Pieces with overlapping boxes are found by
+//! sweeping along x and subdivided until the crossing is pinned down, then
+//! Newton's method polishes the parameters on the actual curves. Where the
+//! curves run along each other, subdividing would never end, so pieces that
+//! have become straight and lie on one line are reported as an overlap instead.
+
+use nalgebra::{Matrix2, Vector2, Vector3};
+
+use crate::spline::{bezier_point, distance_to_segment, halve_bezier, Aabb, BSpline};
+
+/// Size below which pieces are treated as a single point.
+pub const INTERSECTION_TOLERANCE: f32 = 1e-3;
+
+/// Subdivisions after which a piece is used as it is, e.g. near cusps.
+const MAX_DEPTH: usize = 20;
+
+/// Subdivisions of a pair of pieces after which they are taken to meet. Each
+/// split halves only one of the two.
+const MAX_PAIR_DEPTH: usize = 48;
+
+/// A point where two curves, or two parts of one curve, cross or touch, or the
+/// start of a stretch they share.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Intersection {
+    /// Parameter on the first curve. For self intersections the smaller one.
+    pub param_a: f32,
+    /// Parameter on the second curve.
+    pub param_b: f32,
+    pub point: Vector2<f32>,
+    /// Where the curves run along each other from here, the parameters on the
+    /// first and second curve that the shared stretch ends at.
+    pub overlap: Option<(f32, f32)>,
+}
+
+/// Homogeneous Bézier piece of a curve.
+#[derive(Debug, Clone)]
+struct Piece {
+    points: Vec<Vector3<f32>>,
+    range: (f32, f32),
+    bounds: Aabb,
+}
+
+impl Piece {
+    fn new(points: Vec<Vector3<f32>>, range: (f32, f32)) -> Self {
+        let bounds = Aabb::from_points(points.iter().map(|c| c.xy() / c.z)).expect("piece has control points");
+        Self { points, range, bounds }
+    }
+
+    fn split(&self) -> (Piece, Piece) {
+        let mut left = self.points.clone();
+        let mut right = self.points.clone();
+        halve_bezier(&self.points, &mut left, &mut right);
+        let mid = 0.5 * (self.range.0 + self.range.1);
+        (Piece::new(left, (self.range.0, mid)), Piece::new(right, (mid, self.range.1)))
+    }
+
+    /// Whether the control polygon never turns back in x or y.
+    fn is_monotone(&self) -> bool {
+        let projected: Vec<Vector2<f32>> = self.points.iter().map(|c| c.xy() / c.z).collect();
+        (0..2).all(|axis| {
+            let steps = projected.windows(2).map(|w| w[1][axis] - w[0][axis]);
+            steps.clone().all(|d| d >= 0.0) || steps.clone().all(|d| d <= 0.0)
+        })
+    }
+
+    fn size(&self) -> f32 {
+        (self.bounds.max - self.bounds.min).amax()
+    }
+
+    /// Ends of the piece's chord, if no control point strays from it by more
+    /// than the tolerance.
+    fn chord(&self) -> Option<(Vector2<f32>, Vector2<f32>)> {
+        let first = self.points.first()?;
+        let last = self.points.last()?;
+        let (start, end) = (first.xy() / first.z, last.xy() / last.z);
+        let straight = self
+            .points
+            .iter()
+            .all(|c| distance_to_segment(c.xy() / c.z, start, end) <= INTERSECTION_TOLERANCE);
+        straight.then_some((start, end))
+    }
+
+    /// Whether both pieces have the same control points, in the same or in
+    /// reverse order, so they trace the same stretch of curve.
+    fn coincides(&self, other: &Piece, reversed: bool) -> bool {
+        let close = |p: &Vector3<f32>, q: &Vector3<f32>| (p.xy() / p.z - q.xy() / q.z).norm() <= INTERSECTION_TOLERANCE;
+        self.points.len() == other.points.len()
+            && if reversed {
+                self.points.iter().zip(other.points.iter().rev()).all(|(p, q)| close(p, q))
+            } else {
+                self.points.iter().zip(&other.points).all(|(p, q)| close(p, q))
+            }
+    }
+
+    /// Parameter on the curve at the parameter `t` of the piece.
+    fn param_at(&self, t: f32) -> f32 {
+        self.range.0 + t * (self.range.1 - self.range.0)
+    }
+
+    /// Parameter on the curve of the point of the piece that lies `t` of the way
+    /// along the chord from `start` to `end`. Monotone pieces never turn back
+    /// along their chord, so it's found by bisection.
+    fn param_along_chord(&self, start: Vector2<f32>, end: Vector2<f32>, t: f32) -> f32 {
+        let chord = end - start;
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..24 {
+            let mid = 0.5 * (low + high);
+            if (bezier_point(&self.points, mid) - start).dot(&chord) < t * chord.norm_squared() {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        self.param_at(0.5 * (low + high))
+    }
+}
+
+/// Cuts `curve` into monotone pieces, in order along the curve.
+fn monotone_pieces(curve: &BSpline) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    for segment in curve.bezier_segments() {
+        let mut stack = vec![(Piece::new(segment.points, segment.range), 0)];
+        while let Some((piece, depth)) = stack.pop() {
+            if depth >= MAX_DEPTH || piece.is_monotone() {
+                pieces.push(piece);
+            } else {
+                let (left, right) = piece.split();
+                stack.push((right, depth + 1));
+                stack.push((left, depth + 1));
+            }
+        }
+    }
+    pieces
+}
+
+/// All points where `a` and `b` cross or touch, ordered along `a`.
+pub fn intersections(a: &BSpline, b: &BSpline) -> Vec<Intersection> {
+    let pieces_a = monotone_pieces(a);
+    let pieces_b = monotone_pieces(b);
+    let mut found = Vec::new();
+    for (i, j) in overlapping_pairs(&pieces_a, &pieces_b) {
+        intersect_pieces(a, b, &pieces_a[i], &pieces_b[j], None, 0, &mut found);
+    }
+    finish(found)
+}
+
+/// All points where `curve` crosses or touches itself, ordered along the curve.
+/// Neighbouring parts of the curve meeting where they join don't count.
+pub fn self_intersections(curve: &BSpline) -> Vec<Intersection> {
+    self_intersections_touching(curve, |_| true)
+}
+
+/// The points of [`self_intersections`] where at least one of the two
+/// parameters lies within `range`. Only the parts of the curve around
+/// `range` are searched, for checking a curve that changed only there.
+pub fn self_intersections_within(curve: &BSpline, range: (f32, f32)) -> Vec<Intersection> {
+    let within = |u: f32| range.0 <= u && u <= range.1;
+    let mut found = self_intersections_touching(curve, |piece| piece.range.0 <= range.1 && range.0 <= piece.range.1);
+    found.retain(|x| within(x.param_a) || within(x.param_b));
+    found
+}
+
+/// Self intersections between pairs of pieces of which at least one is `kept`.
+fn self_intersections_touching(curve: &BSpline, kept: impl Fn(&Piece) -> bool) -> Vec<Intersection> {
+    let pieces = monotone_pieces(curve);
+    let last = pieces.len().saturating_sub(1);
+    let mut found = Vec::new();
+    for (i, j) in overlapping_pairs(&pieces, &pieces) {
+        // neighbours always meet where they join, as do both ends of a loop
+        let joint = if j == i + 1 {
+            Some(Joint { at_end_of_a: true })
+        } else if curve.is_closed() && i == 0 && j == last && last > 0 {
+            Some(Joint { at_end_of_a: false })
+        } else {
+            None
+        };
+        if i < j && (kept(&pieces[i]) || kept(&pieces[j])) {
+            intersect_pieces(curve, curve, &pieces[i], &pieces[j], joint, 0, &mut found);
+        }
+    }
+
+    // sharp corners can still lead newton back onto the joint itself
+    let (start, end) = curve.domain();
+    let tolerance = 10.0 * INTERSECTION_TOLERANCE;
+    found.retain(|x| {
+        let seam = curve.is_closed() && x.param_a - start <= tolerance && end - x.param_b <= tolerance;
+        x.param_b - x.param_a > tolerance && !seam
+    });
+    finish(found)
+}
+
+/// End point shared by two pieces of the same curve. It's at the end of the
+/// first piece and the start of the second, or the other way around.
+#[derive(Debug, Clone, Copy)]
+struct Joint {
+    at_end_of_a: bool,
+}
+
+/// Index pairs of pieces whose boxes overlap, by sweeping along x.
+fn overlapping_pairs(a: &[Piece], b: &[Piece]) -> Vec<(usize, usize)> {
+    let mut order_b: Vec<usize> = (0..b.len()).collect();
+    order_b.sort_by(|&i, &j| b[i].bounds.min.x.total_cmp(&b[j].bounds.min.x));
+
+    let width = max_width(b);
+    let mut pairs = Vec::new();
+    for (i, piece) in a.iter().enumerate() {
+        let first = order_b.partition_point(|&j| b[j].bounds.min.x < piece.bounds.min.x - width);
+        for &j in &order_b[first..] {
+            if b[j].bounds.min.x > piece.bounds.max.x {
+                break;
+            }
+            if piece.bounds.intersects(&b[j].bounds) {
+                pairs.push((i, j));
+            }
+        }
+    }
+    pairs
+}
+
+fn max_width(pieces: &[Piece]) -> f32 {
+    pieces.iter().map(|piece| piece.bounds.max.x - piece.bounds.min.x).fold(0.0, f32::max)
+}
+
+fn intersect_pieces(a: &BSpline, b: &BSpline, pa: &Piece, pb: &Piece, joint: Option<Joint>, depth: usize, found: &mut Vec<Intersection>) {
+    if !pa.bounds.intersects(&pb.bounds) {
+        return;
+    }
+    let small = |piece: &Piece, scale: f32| piece.size() < scale * INTERSECTION_TOLERANCE;
+    if joint.is_some() && small(pa, 10.0) && small(pb, 10.0) {
+        return;
+    }
+    if let Some(overlap) = shared_piece(pa, pb).or_else(|| overlap(pa, pb)) {
+        found.push(overlap);
+        return;
+    }
+    if depth >= MAX_PAIR_DEPTH || (small(pa, 1.0) && small(pb, 1.0)) {
+        let guess = (0.5 * (pa.range.0 + pa.range.1), 0.5 * (pb.range.0 + pb.range.1));
+        found.push(refine(a, b, guess, pa.range, pb.range));
+        return;
+    }
+
+    // only the halves at the shared end point keep it
+    if pa.size() >= pb.size() {
+        let (left, right) = pa.split();
+        let (joint_left, joint_right) = match joint {
+            Some(Joint { at_end_of_a: true }) => (None, joint),
+            Some(Joint { at_end_of_a: false }) => (joint, None),
+            None => (None, None),
+        };
+        intersect_pieces(a, b, &left, pb, joint_left, depth + 1, found);
+        intersect_pieces(a, b, &right, pb, joint_right, depth + 1, found);
+    } else {
+        let (left, right) = pb.split();
+        let (joint_left, joint_right) = match joint {
+            Some(Joint { at_end_of_a: true }) => (joint, None),
+            Some(Joint { at_end_of_a: false }) => (None, joint),
+            None => (None, None),
+        };
+        intersect_pieces(a, b, pa, &left, joint_left, depth + 1, found);
+        intersect_pieces(a, b, pa, &right, joint_right, depth + 1, found);
+    }
+}
+
+/// Both pieces whole, if they trace the same stretch of curve, as happens
+/// when the curves are copies of each other.
+fn shared_piece(pa: &Piece, pb: &Piece) -> Option<Intersection> {
+    // tiny pieces near any crossing match as well
+    let reversed = if pa.size() <= 10.0 * INTERSECTION_TOLERANCE {
+        return None;
+    } else if pa.coincides(pb, false) {
+        false
+    } else if pa.coincides(pb, true) {
+        true
+    } else {
+        return None;
+    };
+    let (start_b, end_b) = if reversed { (pb.range.1, pb.range.0) } else { pb.range };
+    let first = pa.points[0];
+    Some(Intersection {
+        param_a: pa.range.0,
+        param_b: start_b,
+        point: first.xy() / first.z,
+        overlap: Some((pa.range.1, end_b)),
+    })
+}
+
+/// The stretch two straight pieces on one line share, if clearly longer than
+/// the tolerance.
+fn overlap(pa: &Piece, pb: &Piece) -> Option<Intersection> {
+    let (a0, a1) = pa.chord()?;
+    let (b0, b1) = pb.chord()?;
+    let (da, db) = (a1 - a0, b1 - b0);
+    let (length_a, length_b) = (da.norm(), db.norm());
+    if length_a <= INTERSECTION_TOLERANCE || length_b <= INTERSECTION_TOLERANCE {
+        return None;
+    }
+    // both ends of b on the line through a
+    let off_line = |p: Vector2<f32>| (da.x * (p.y - a0.y) - da.y * (p.x - a0.x)).abs() / length_a;
+    if off_line(b0) > 2.0 * INTERSECTION_TOLERANCE || off_line(b1) > 2.0 * INTERSECTION_TOLERANCE {
+        return None;
+    }
+
+    let along = |p: Vector2<f32>, start: Vector2<f32>, d: Vector2<f32>| (p - start).dot(&d) / d.norm_squared();
+    let (t0, t1) = (along(b0, a0, da), along(b1, a0, da));
+    let (start, end) = (t0.min(t1).max(0.0), t0.max(t1).min(1.0));
+    if (end - start) * length_a <= 10.0 * INTERSECTION_TOLERANCE {
+        return None;
+    }
+    let (p0, p1) = (a0 + start * da, a0 + end * da);
+    let on_b = |p: Vector2<f32>| pb.param_along_chord(b0, b1, along(p, b0, db).clamp(0.0, 1.0));
+    Some(Intersection {
+        param_a: pa.param_along_chord(a0, a1, start),
+        param_b: on_b(p0),
+        point: p0,
+        overlap: Some((pa.param_along_chord(a0, a1, end), on_b(p1))),
+    })
+}
+
+/// Newton's method on `a(u) - b(v) = 0` from `guess`, keeping the guess if the
+/// iteration leaves the pieces or doesn't get closer.
+fn refine(a: &BSpline, b: &BSpline, guess: (f32, f32), range_a: (f32, f32), range_b: (f32, f32)) -> Intersection {
+    let gap = |u: f32, v: f32| a.eval(u) - b.eval(v);
+    let (mut u, mut v) = guess;
+    for _ in 0..8 {
+        let f = gap(u, v);
+        let jacobian = Matrix2::from_columns(&[a.derivative(u, 1), -b.derivative(v, 1)]);
+        let Some(inverse) = jacobian.try_inverse() else {
+            break;
+        };
+        let step = inverse * f;
+        u -= step.x;
+        v -= step.y;
+    }
+
+    let slack = |range: (f32, f32)| 0.5 * (range.1 - range.0) + INTERSECTION_TOLERANCE;
+    let inside = (u - 0.5 * (range_a.0 + range_a.1)).abs() <= slack(range_a) && (v - 0.5 * (range_b.0 + range_b.1)).abs() <= slack(range_b);
+    if !inside || gap(u, v).norm() > gap(guess.0, guess.1).norm() {
+        (u, v) = guess;
+    }
+    Intersection {
+        param_a: u,
+        param_b: v,
+        point: 0.5 * (a.eval(u) + b.eval(v)),
+        overlap: None,
+    }
+}
+
+/// Sorts along the first curve, joins overlaps that continue one another, and
+/// merges hits on the same spot, which come from neighbouring pieces sharing a
+/// crossing or from tangential contact. Hits inside an overlap are dropped.
+fn finish(mut found: Vec<Intersection>) -> Vec<Intersection> {
+    found.sort_by(|x, y| x.param_a.total_cmp(&y.param_a));
+    let tolerance = 10.0 * INTERSECTION_TOLERANCE;
+
+    let mut overlaps: Vec<Intersection> = Vec::new();
+    for x in found.iter().filter(|x| x.overlap.is_some()) {
+        let (end_a, end_b) = x.overlap.unwrap_or_default();
+        if let Some(last) = overlaps.last_mut() {
+            let (last_end_a, last_end_b) = last.overlap.unwrap_or_default();
+            let continues = |b: f32| (last.param_b.min(last_end_b) - tolerance..=last.param_b.max(last_end_b) + tolerance).contains(&b);
+            if x.param_a <= last_end_a + tolerance && continues(x.param_b) {
+                if end_a > last_end_a {
+                    last.overlap = Some((end_a, end_b));
+                }
+                continue;
+            }
+        }
+        overlaps.push(*x);
+    }
+    let in_overlap = |x: &Intersection| {
+        overlaps.iter().any(|o| {
+            let (end_a, end_b) = o.overlap.unwrap_or_default();
+            let within = |v: f32, s: f32, e: f32| s.min(e) - tolerance <= v && v <= s.max(e) + tolerance;
+            within(x.param_a, o.param_a, end_a) && within(x.param_b, o.param_b, end_b)
+        })
+    };
+
+    let points = found.iter().filter(|x| x.overlap.is_none() && !in_overlap(x));
+    let mut merged: Vec<Intersection> = Vec::with_capacity(found.len());
+    for &x in points {
+        let duplicate = merged
+            .iter()
+            .rev()
+            .take_while(|m| x.param_a - m.param_a <= tolerance)
+            .any(|m| (m.param_b - x.param_b).abs() <= tolerance || (m.point - x.point).norm() <= tolerance);
+        if !duplicate {
+            merged.push(x);
+        }
+    }
+    merged.extend(overlaps);
+    merged.sort_by(|x, y| x.param_a.total_cmp(&y.param_a));
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, TAU};
+
+    use super::*;
+
+    fn polyline(points: &[(f32, f32)], degree: usize) -> BSpline {
+        BSpline::clamped_uniform(points.iter().map(|&(x, y)| Vector2::new(x, y)).collect(), degree).unwrap()
+    }
+
+    #[test]
+    fn line_crosses_a_circle_twice() {
+        let line = polyline(&[(-2.0, 0.0), (2.0, 0.0)], 1);
+        // quarter segments starting at the top, so the crossings are at knots
+        let circle = BSpline::circular_arc(Vector2::zeros(), 1.0, FRAC_PI_2, TAU);
+        let found = intersections(&line, &circle);
+
+        assert_eq!(found.len(), 2, "{found:?}");
+        for (x, (param_a, param_b, point)) in found
+            .iter()
+            .zip([(0.25, 1.0, Vector2::new(-1.0, 0.0)), (0.75, 3.0, Vector2::new(1.0, 0.0))])
+        {
+            assert!((x.param_a - param_a).abs() < 1e-3, "{x:?}");
+            assert!((x.param_b - param_b).abs() < 1e-3, "{x:?}");
+            assert!((x.point - point).norm() < 1e-3, "{x:?}");
+            assert!(x.overlap.is_none(), "{x:?}");
+        }
+    }
+
+    #[test]
+    fn loop_crosses_itself_once() {
+        // the ends of the control polygon cross, so the curve makes a loop
+        let curve = polyline(&[(0.0, 0.0), (3.0, 2.0), (-1.0, 2.0), (2.0, 0.0)], 3);
+        let found = self_intersections(&curve);
+
+        assert_eq!(found.len(), 1, "{found:?}");
+        let x = found[0];
+        assert!(x.param_b - x.param_a > 0.1, "{x:?}");
+        assert!((curve.eval(x.param_a) - curve.eval(x.param_b)).norm() < 1e-3, "{x:?}");
+        assert!((x.point - curve.eval(x.param_a)).norm() < 1e-3, "{x:?}");
+        // symmetric about x = 1
+        assert!((x.point.x - 1.0).abs() < 1e-3, "{x:?}");
+    }
+
+    #[test]
+    fn crossings_within_a_range_are_those_of_the_whole_curve() {
+        // two loops, one in each half
+        let curve = polyline(
+            &[
+                (0.0, 0.0),
+                (3.0, 2.0),
+                (-1.0, 2.0),
+                (2.0, 0.0),
+                (4.0, 0.0),
+                (7.0, 2.0),
+                (3.0, 2.0),
+                (6.0, 0.0),
+            ],
+            3,
+        );
+        let all = self_intersections(&curve);
+        assert_eq!(all.len(), 2, "{all:?}");
+
+        let (start, end) = curve.domain();
+        let middle = 0.5 * (start + end);
+        for range in [
+            (start, middle),
+            (middle, end),
+            (start, end),
+            (all[0].param_b + 0.01, all[1].param_a - 0.01),
+        ] {
+            let within = |u: f32| range.0 <= u && u <= range.1;
+            let expected: Vec<_> = all.iter().filter(|x| within(x.param_a) || within(x.param_b)).collect();
+            let found = self_intersections_within(&curve, range);
+            assert_eq!(found.len(), expected.len(), "{range:?} {found:?}");
+            for (x, y) in found.iter().zip(expected) {
+                assert!((x.point - y.point).norm() < 1e-3, "{range:?} {x:?} {y:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn shared_stretch_is_one_overlap() {
+        let floor = polyline(&[(0.0, 0.0), (250.0, 0.0), (500.0, 0.0), (750.0, 0.0), (1000.0, 0.0)], 3);
+        // runs along the floor for its first two spans, then climbs away from it
+        let ramp = polyline(
+            &[
+                (300.0, 0.0),
+                (400.0, 0.0),
+                (500.0, 0.0),
+                (600.0, 0.0),
+                (700.0, 0.0),
+                (800.0, 100.0),
+                (900.0, 200.0),
+            ],
+            3,
+        );
+
+        for (a, b) in [(&floor, &ramp), (&ramp, &floor)] {
+            let found = intersections(a, b);
+            assert_eq!(found.len(), 1, "{found:?}");
+            let x = found[0];
+            let (end_a, end_b) = x.overlap.expect("shared stretch is an overlap");
+            assert!((x.point - Vector2::new(300.0, 0.0)).norm() < 1e-2, "{x:?}");
+            assert!((a.eval(end_a) - b.eval(end_b)).norm() < 1e-2, "{x:?}");
+            assert!(a.eval(end_a).x > 500.0 && a.eval(end_a).y.abs() < 1e-2, "{x:?}");
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Interpolating and Bézier splines next to B-splines.
+//!
+//! Every kind of spline is a chain of cubic Bézier curves in the end, which
+//! [`BSpline::piecewise_bezier`] turns into an exact B-spline, so evaluation and
+//! all queries work the same for each of them. Conversions between kinds go
+//! through that chain as well and fail where the target kind can't describe the
+//! curve exactly.
+
+use nalgebra::Vector2;
+
+use crate::spline::BSpline;
+
+/// Distance a control point may be off after a conversion before the
+/// conversion counts as lossy.
+pub const CONVERSION_TOLERANCE: f32 = 1e-2;
+
+/// How the control points of a spline describe its curve.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CurveKind {
+    /// Control points pull on the curve without it passing through them.
+    #[default]
+    BSpline,
+    /// Cubic Bézier curves end to end. Every third control point is on the
+    /// curve, the two in between are the handles of the curves on either side.
+    Bezier,
+    /// Cubic curve through every control point, with the tangent given for it
+    /// or a Catmull-Rom tangent where there is none. Each segment between two
+    /// control points takes one unit of the parameter.
+    Hermite,
+    /// Cubic curve through every control point, with tangents from the
+    /// neighbouring control points. Knots are spaced by distance to the power
+    /// of `alpha`: 0 is uniform, 0.5 centripetal and 1 chordal. Centripetal
+    /// curves never form cusps or loops within a segment.
+    CatmullRom { alpha: f32 },
+}
+
+impl CurveKind {
+    pub const CENTRIPETAL: CurveKind = CurveKind::CatmullRom { alpha: 0.5 };
+
+    /// Whether the curve passes through every control point.
+    pub fn interpolates(&self) -> bool {
+        matches!(self, CurveKind::Hermite | CurveKind::CatmullRom { .. })
+    }
+
+    /// Whether the control points are those of the curve's B-spline, one to one.
+    pub fn maps_control_points(&self) -> bool {
+        matches!(self, CurveKind::BSpline | CurveKind::Bezier)
+    }
+}
+
+/// Control points of a spline of some [`CurveKind`], see [`from_bezier`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlPolygon {
+    pub points: Vec<Vector2<f32>>,
+    /// Tangent of each control point of a Hermite spline, empty otherwise.
+    pub tangents: Vec<Vector2<f32>>,
+    /// Knots of a B-spline.
+    pub knots: Option<Vec<f32>>,
+}
+
+/// Bézier chain, as taken by [`BSpline::piecewise_bezier`], of the curve
+/// described by `points` of `kind`. Missing `tangents` of Hermite splines are
+/// filled in like Catmull-Rom ones, other kinds ignore them. `None` for
+/// B-splines, which go through [`bspline_to_bezier`], and for too few points.
+/// Bézier points left over after the last full segment are dropped.
+pub fn to_bezier(kind: CurveKind, points: &[Vector2<f32>], tangents: &[Option<Vector2<f32>>], closed: bool) -> Option<Vec<Vector2<f32>>> {
+    let n = points.len();
+    match kind {
+        CurveKind::BSpline => None,
+        CurveKind::Bezier => {
+            let count = if closed { n - n % 3 } else { n.saturating_sub(1) / 3 * 3 + 1 };
+            (count >= if closed { 3 } else { 4 }).then(|| points[..count].to_vec())
+        }
+        CurveKind::Hermite => {
+            let tangents: Vec<Vector2<f32>> = (0..n)
+                .map(|i| {
+                    tangents
+                        .get(i)
+                        .copied()
+                        .flatten()
+                        .unwrap_or_else(|| catmull_rom_tangent(points, i, closed))
+                })
+                .collect();
+            hermite_chain(points, closed, |i| tangents[i], |i| tangents[i])
+        }
+        CurveKind::CatmullRom { alpha } => {
+            // chord length to the power of alpha between point i and the next
+            let point = |i: isize| neighbour(points, i, closed);
+            let spacing = |i: isize| (point(i + 1) - point(i)).norm().powf(alpha).max(f32::EPSILON);
+            let outgoing = |i: usize| {
+                let i = i as isize;
+                let (d0, d1) = (spacing(i - 1), spacing(i));
+                (point(i + 1) - point(i)) + d1 * ((point(i) - point(i - 1)) / d0 - (point(i + 1) - point(i - 1)) / (d0 + d1))
+            };
+            let incoming = |i: usize| {
+                // tangent at the end of the segment from i - 1 to i
+                let i = i as isize;
+                let (d1, d2) = (spacing(i - 1), spacing(i));
+                (point(i) - point(i - 1)) + d1 * ((point(i + 1) - point(i)) / d2 - (point(i + 1) - point(i - 1)) / (d1 + d2))
+            };
+            hermite_chain(points, closed, outgoing, incoming)
+        }
+    }
+}
+
+/// Bézier chain through `points`, leaving point `i` with tangent `outgoing(i)`
+/// and arriving there with `incoming(i)`, both per unit of the parameter.
+fn hermite_chain(
+    points: &[Vector2<f32>],
+    closed: bool,
+    outgoing: impl Fn(usize) -> Vector2<f32>,
+    incoming: impl Fn(usize) -> Vector2<f32>,
+) -> Option<Vec<Vector2<f32>>> {
+    let n = points.len();
+    if n < 2 {
+        return None;
+    }
+    let segments = if closed { n } else { n - 1 };
+    let mut bezier = Vec::with_capacity(3 * segments + 1);
+    for i in 0..segments {
+        let j = (i + 1) % n;
+        bezier.extend([points[i], points[i] + outgoing(i) / 3.0, points[j] - incoming(j) / 3.0]);
+    }
+    if !closed {
+        bezier.push(points[n - 1]);
+    }
+    Some(bezier)
+}
+
+/// Point `i` of `points`, wrapping around loops. Open curves are extended by
+/// mirroring the second and second to last points at their ends.
+fn neighbour(points: &[Vector2<f32>], i: isize, closed: bool) -> Vector2<f32> {
+    let n = points.len() as isize;
+    if closed {
+        return points[i.rem_euclid(n) as usize];
+    }
+    match i {
+        i if i < 0 => 2.0 * points[0] - points[1],
+        i if i >= n => 2.0 * points[n as usize - 1] - points[n as usize - 2],
+        i => points[i as usize],
+    }
+}
+
+/// Uniform Catmull-Rom tangent at point `i`, half the step between its
+/// neighbours.
+fn catmull_rom_tangent(points: &[Vector2<f32>], i: usize, closed: bool) -> Vector2<f32> {
+    let i = i as isize;
+    0.5 * (neighbour(points, i + 1, closed) - neighbour(points, i - 1, closed))
+}
+
+/// Bézier chain of a polynomial B-spline of degree 3 or lower, lower degrees
+/// elevated to cubic. `None` for rational splines and higher degrees, which no
+/// cubic chain describes exactly.
+pub fn bspline_to_bezier(curve: &BSpline) -> Option<Vec<Vector2<f32>>> {
+    if curve.weights().is_some() || curve.degree() > 3 {
+        return None;
+    }
+    let mut bezier = Vec::new();
+    for segment in curve.bezier_segments() {
+        let mut points: Vec<Vector2<f32>> = segment.points.iter().map(|c| c.xy() / c.z).collect();
+        while points.len() < 4 {
+            points = elevate(&points);
+        }
+        if bezier.is_empty() {
+            bezier.push(points[0]);
+        }
+        bezier.extend_from_slice(&points[1..]);
+    }
+    // loops end where they started
+    if curve.is_closed() {
+        bezier.pop();
+    }
+    Some(bezier)
+}
+
+/// Same Bézier curve with one more control point.
+fn elevate(points: &[Vector2<f32>]) -> Vec<Vector2<f32>> {
+    let n = points.len();
+    (0..=n)
+        .map(|i| {
+            let a = i as f32 / n as f32;
+            match i {
+                0 => points[0],
+                i if i == n => points[n - 1],
+                i => a * points[i - 1] + (1.0 - a) * points[i],
+            }
+        })
+        .collect()
+}
+
+/// Control points of a spline of `kind` describing the same curve as the
+/// `bezier` chain, if there is one. Hermite splines need a single tangent at
+/// every point on the curve, Catmull-Rom splines handles that match the ones
+/// they would pick, and B-splines can't be loops with tripled knots.
+pub fn from_bezier(kind: CurveKind, bezier: &[Vector2<f32>], closed: bool) -> Option<ControlPolygon> {
+    let polygon = |points: Vec<Vector2<f32>>| ControlPolygon {
+        points,
+        tangents: Vec::new(),
+        knots: None,
+    };
+    match kind {
+        CurveKind::Bezier => Some(polygon(bezier.to_vec())),
+        CurveKind::BSpline => {
+            let curve = BSpline::piecewise_bezier(bezier.to_vec(), closed).ok().filter(|_| !closed)?;
+            Some(ControlPolygon {
+                knots: Some(curve.knots().to_vec()),
+                ..polygon(bezier.to_vec())
+            })
+        }
+        CurveKind::Hermite => {
+            let n = bezier.len();
+            let on_curve: Vec<Vector2<f32>> = bezier.iter().step_by(3).copied().collect();
+            let handle = |i: usize| bezier[i % n];
+            let mut tangents = Vec::with_capacity(on_curve.len());
+            for k in 0..on_curve.len() {
+                let i = 3 * k;
+                let outgoing = (i + 1 < n).then(|| 3.0 * (handle(i + 1) - bezier[i]));
+                let incoming = (i > 0 || closed).then(|| 3.0 * (bezier[i] - handle(i + n - 1)));
+                let tangent = match (outgoing, incoming) {
+                    (Some(outgoing), Some(incoming)) if (outgoing - incoming).norm() > 3.0 * CONVERSION_TOLERANCE => return None,
+                    (Some(tangent), _) | (None, Some(tangent)) => tangent,
+                    (None, None) => return None,
+                };
+                tangents.push(tangent);
+            }
+            Some(ControlPolygon {
+                tangents,
+                ..polygon(on_curve)
+            })
+        }
+        CurveKind::CatmullRom { .. } => {
+            let on_curve: Vec<Vector2<f32>> = bezier.iter().step_by(3).copied().collect();
+            let rebuilt = to_bezier(kind, &on_curve, &[], closed)?;
+            let same = rebuilt.len() == bezier.len() && rebuilt.iter().zip(bezier).all(|(a, b)| (a - b).norm() <= CONVERSION_TOLERANCE);
+            same.then(|| polygon(on_curve))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave(n: usize) -> Vec<Vector2<f32>> {
+        (0..n).map(|i| Vector2::new(50.0 * i as f32, 80.0 * (0.9 * i as f32).sin())).collect()
+    }
+
+    /// Largest distance between the curves at the same parameters.
+    fn max_deviation(a: &BSpline, b: &BSpline) -> f32 {
+        let (start, end) = a.domain();
+        assert_eq!((start, end), b.domain());
+        (0..=500)
+            .map(|i| start + (end - start) * i as f32 / 500.0)
+            .map(|u| (a.eval(u) - b.eval(u)).norm())
+            .fold(0.0, f32::max)
+    }
+
+    fn assert_close(a: &[Vector2<f32>], b: &[Vector2<f32>]) {
+        assert_eq!(a.len(), b.len());
+        for (p, q) in a.iter().zip(b) {
+            assert!((p - q).norm() <= CONVERSION_TOLERANCE, "{p} is not {q}");
+        }
+    }
+
+    #[test]
+    fn interpolating_kinds_round_trip_through_bezier() {
+        let points = wave(7);
+        for closed in [false, true] {
+            for kind in [CurveKind::CatmullRom { alpha: 0.0 }, CurveKind::CENTRIPETAL, CurveKind::Hermite] {
+                let bezier = to_bezier(kind, &points, &[], closed).unwrap();
+                let polygon = from_bezier(kind, &bezier, closed).unwrap();
+                assert_close(&polygon.points, &points);
+
+                // uniform ones as a Hermite spline with the tangents they picked,
+                // centripetal ones have different tangents on either side
+                let Some(hermite) = from_bezier(CurveKind::Hermite, &bezier, closed) else {
+                    assert_eq!(kind, CurveKind::CENTRIPETAL);
+                    continue;
+                };
+                let tangents: Vec<_> = hermite.tangents.iter().copied().map(Some).collect();
+                assert_close(&to_bezier(CurveKind::Hermite, &hermite.points, &tangents, closed).unwrap(), &bezier);
+            }
+        }
+    }
+
+    #[test]
+    fn bspline_round_trips_through_bezier() {
+        for degree in 1..=3 {
+            let curve = BSpline::clamped_uniform(wave(8), degree).unwrap();
+            let bezier = bspline_to_bezier(&curve).unwrap();
+            let chain = BSpline::piecewise_bezier(bezier.clone(), false).unwrap();
+            assert!(max_deviation(&curve, &chain) < 1e-3, "degree {degree}");
+
+            let polygon = from_bezier(CurveKind::BSpline, &bezier, false).unwrap();
+            let back = BSpline::new(polygon.points, 3, polygon.knots.unwrap()).unwrap();
+            assert!(max_deviation(&curve, &back) < 1e-3, "degree {degree}");
+        }
+    }
+
+    #[test]
+    fn lossy_conversions_fail() {
+        // a kink at the middle point
+        let kinked = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(10.0, 10.0),
+            Vector2::new(20.0, 10.0),
+            Vector2::new(30.0, 0.0),
+            Vector2::new(40.0, 20.0),
+            Vector2::new(50.0, 20.0),
+            Vector2::new(60.0, 0.0),
+        ];
+        assert!(from_bezier(CurveKind::Hermite, &kinked, false).is_none());
+        assert!(from_bezier(CurveKind::CENTRIPETAL, &kinked, false).is_none());
+        assert!(from_bezier(CurveKind::BSpline, &kinked[..6], true).is_none());
+
+        let rational = BSpline::circular_arc(Vector2::zeros(), 10.0, 0.0, 1.0);
+        assert!(bspline_to_bezier(&rational).is_none());
+    }
+}
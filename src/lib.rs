@@ -0,0 +1,13 @@
+//! Spline math without any ECS. The game in `main.rs` wraps it in Bevy
+//! plugins.
+
+pub mod batch;
+pub mod bvh;
+pub mod contacts;
+pub mod fill;
+pub mod fit;
+pub mod intersect;
+pub mod kinds;
+pub mod offset;
+pub mod ribbon;
+pub mod spline;
@@ -2,27 +2,29 @@ mod spines_plugin;
 mod physics_plugin;
 mod controls_plugin;
 mod player_plugin;
+mod spline_debug_plugin;
 mod assets_plugin;
 
-use bevy::math::ops::{cos, sin};
+use std::collections::HashMap;
+use bevy::math::ops::sin;
 use bevy::{
     dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin},
     prelude::*,
     text::FontSmoothing,
 };
 
-use nalgebra::{Vector, Vector2};
-use rand::Rng;
+use nalgebra::Vector2;
 use crate::assets_plugin::AssetsPlugin;
-use crate::controls_plugin::{ControlsPlugin, Follower};
-use crate::physics_plugin::{Collider, Gravitate, PhysicsPlugin, VerletObject};
+use crate::controls_plugin::ControlsPlugin;
+use crate::physics_plugin::{BodyShape, Collider, Gravitate, Grindable, PhysicsMaterial, PhysicsPlugin, SplineMemory, VerletObject};
 use crate::player_plugin::PlayerPlugin;
-use crate::spines_plugin::{OldPosition, Position, SplinePlugin};
+use crate::spline_debug_plugin::SplineDebugPlugin;
+use spline_grind::offset::ThicknessProfile;
+use crate::spines_plugin::{OldPosition, Position, SplineFill, SplinePlugin, SplineRibbon, SplineThickness};
 
 struct OverlayColor;
 
 impl OverlayColor {
-    const RED: Color = Color::srgb(1.0, 0.0, 0.0);
     const GREEN: Color = Color::srgb(0.0, 1.0, 0.0);
 }
 fn main() {
@@ -41,6 +43,7 @@ fn main() {
             PhysicsPlugin,
             ControlsPlugin,
             PlayerPlugin,
+            SplineDebugPlugin,
             AssetsPlugin,
             FpsOverlayPlugin {
                 config: FpsOverlayConfig {
@@ -61,33 +64,35 @@ fn main() {
                 },
             },
         ));
-    app.insert_resource(Time::<Fixed>::from_seconds(0.002));
     app.add_systems(Startup, setup);
     app.run();
 }
 
 fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>) {
 
-    let circle = meshes.add(Circle::new(30.0));
     let color = Color::WHITE;
     let material = materials.add(color);
-
-    let mid_circle = meshes.add(Circle::new(10.0));
-    let small_circle = meshes.add(Circle::new(5.0));
-    let rec = meshes.add(Rectangle::new(2.0, 15.0));
+    let ground_material = materials.add(Color::srgb(0.25, 0.22, 0.2));
 
     // spline
     let mut splines:Vec<Entity> = Vec::with_capacity(100);
 
-    for i in 0..1{
+    for _ in 0..1{
 
-        splines.push(commands.spawn((crate::spines_plugin::Spline())).id());
+        splines.push(commands.spawn((crate::spines_plugin::Spline(),
+                                     SplineRibbon::new(material.clone()),
+                                     SplineFill::new(ground_material.clone(), -1000.0),
+        )).id());
     }
-    let mut rng = rand::thread_rng();
 
     commands.spawn((Position(Vector2::new(0.0, 1000.0)),
                     crate::spines_plugin::Pusher(),
+                    crate::spines_plugin::RefineSplines { max_span_length: 25.0 },
                     crate::spines_plugin::FollowMouse(),
+                    crate::spines_plugin::DrawSplines {
+                        tolerance: 2.0,
+                        material: material.clone(),
+                    },
                     // Transform::from_xyz(
                     //     0.0,
                     //     0.0,
@@ -97,7 +102,7 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
                     // MeshMaterial2d(material.clone()),
     ));
 
-    for j in 0..1{
+    for (j, spline) in splines.iter().enumerate() {
         for i in 0..500{
             let x =  -1000.0 + i as f32 * 40.0;
             let y: f32 = sin(x*0.01)*x * 0.01 + sin(x*0.0085)*x * 0.005 +  sin(x*0.0185)*x * 0.0076 - 200.0;
@@ -119,20 +124,20 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
                             // ),
                             // Mesh2d(mid_circle.clone()),
                             // MeshMaterial2d(material.clone()),
-                            crate::spines_plugin::ControlPoint(splines[j]),
+                            crate::spines_plugin::ControlPoint(*spline),
 
             ));
 
 
         }
 
-        commands.spawn((Position(Vector2::new(10000.0,-1000.0)),
-                        crate::spines_plugin::HiddenControlPoint(splines[j]),
+        commands.spawn((Position(Vector2::new(19000.0,-1000.0)),
+                        crate::spines_plugin::HiddenControlPoint(*spline),
 
         ));
 
         commands.spawn((Position(Vector2::new(-1100.0,-1000.0)),
-                        crate::spines_plugin::HiddenControlPoint(splines[j]),
+                        crate::spines_plugin::HiddenControlPoint(*spline),
 
         ));
 
@@ -141,47 +146,40 @@ fn setup(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials
 
 
 
-    for i in 0..5000 {
-
-        for j in 0..1{
-
-            commands.spawn((Position(Vector2::new(5.0 * i as f32,0.0)),
-                            Transform::from_xyz(
-                                0.0,
-                                0.0,
-                                0.0,
-                            ),
-                            Mesh2d(small_circle.clone()),
-                            MeshMaterial2d(material.clone()),
-                            crate::spines_plugin::Visualization(splines[j])
-            ));
-
-
-        }
-
-
-    }
-
-    for i in 0..3 {
-
-        for j in 0..1{
-
-            commands.spawn((Position(Vector2::new(5.0 * i as f32,0.0)),
-                            Transform::from_xyz(
-                                0.0,
-                                0.0,
-                                0.0,
-                            ),
-                            Mesh2d(rec.clone()),
-                            MeshMaterial2d(material.clone()),
-                            crate::spines_plugin::VisualizationGradient(splines[j])
-            ));
-
-
-        }
-
+    // loose post to bump into, muddy so it doesn't slide away. Splines see
+    // the circle around it, which its bottom stands on
+    commands.spawn((Position(Vector2::new(600.0, 300.0)),
+                    Transform::from_xyz(600.0, 300.0, 0.0),
+                    Mesh2d(meshes.add(Capsule2d::new(15.0, 120.0))),
+                    MeshMaterial2d(material.clone()),
+                    BodyShape::Capsule { a: Vector2::new(0.0, -60.0), b: Vector2::new(0.0, 60.0), radius: 15.0 },
+                    PhysicsMaterial::MUD,
+                    SplineMemory{spline_intersections: HashMap::new()},
+                    Gravitate(),
+                    Collider::new(),
+                    VerletObject { position_old: Vector2::new(600.0, 300.0), velocity: Vector2::new(0.0, 0.0), acceleration: Vector2::new(0.0, 0.0) }
+    ));
 
+    // floating island
+    let island_thickness = ThicknessProfile::new(vec![(0.0, 20.0), (0.5, 60.0)]).unwrap();
+    let island = commands.spawn((crate::spines_plugin::Spline(),
+                                 crate::spines_plugin::SplineClosed,
+                                 SplineThickness(island_thickness),
+                                 PhysicsMaterial::ICE,
+                                 Grindable,
+                                 SplineRibbon::new(material.clone()),
+    )).id();
+    for i in 0..16 {
+        let angle = i as f32 / 16.0 * std::f32::consts::TAU;
+        let radius = 250.0 + 60.0 * sin(3.0 * angle);
+        let p = Vector2::new(1500.0 + radius * angle.cos(), 400.0 + 0.5 * radius * angle.sin());
+
+        commands.spawn((Position(p),
+                        crate::spines_plugin::Target(p),
+                        OldPosition(p),
+                        crate::spines_plugin::Movable {default_position: p},
+                        crate::spines_plugin::ControlPoint(island),
+        ));
     }
 
-
 }
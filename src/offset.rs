@@ -0,0 +1,390 @@
+//! Thickness of splines and the offset curves bounding them.
+//!
+//! A thick spline is the area swept by a disc moving along the center line,
+//! with a diameter that follows a [`ThicknessProfile`]. Its surface on either
+//! side is the envelope of those discs. For constant thickness that is the
+//! classic offset curve `C(u) ± w N(u)`, otherwise the touching point leans
+//! towards the thinner side.
+
+use nalgebra::Vector2;
+
+use crate::bvh::SpanBvh;
+use crate::spline::{distance_to_segment, Aabb, BSpline, ClosestPoint};
+
+/// Samples per knot span that [`ThickSpline::offset_polyline`] starts from
+/// before subdividing, and that [`ThickSpline::closest_point`] tries.
+const OFFSET_SAMPLES: usize = 4;
+
+/// Subdivisions of a sample interval after which it is used as it is.
+const MAX_OFFSET_DEPTH: usize = 8;
+
+/// Segments ahead that are checked for loops to cut out of an offset polyline.
+/// Loops come from the center line bending tighter than the thickness, which
+/// only happens over a few segments.
+const LOOP_WINDOW: usize = 64;
+
+/// Thickness along a spline, as `(fraction, thickness)` keys where the fraction
+/// runs from 0 at the start of the spline's domain to 1 at its end.
+///
+/// In between keys the thickness blends with a smoothstep, so the surface has
+/// no kinks. Open splines keep the first and last thickness past the outer
+/// keys, closed ones blend from the last key around to the first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThicknessProfile {
+    keys: Vec<(f32, f32)>,
+}
+
+impl ThicknessProfile {
+    pub fn constant(thickness: f32) -> Self {
+        Self {
+            keys: vec![(0.0, thickness.max(0.0))],
+        }
+    }
+
+    /// Sorts `keys` by fraction. Negative thicknesses count as zero. `None`
+    /// without any keys.
+    pub fn new(mut keys: Vec<(f32, f32)>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for key in &mut keys {
+            key.1 = key.1.max(0.0);
+        }
+        Some(Self { keys })
+    }
+
+    pub fn keys(&self) -> &[(f32, f32)] {
+        &self.keys
+    }
+
+    pub fn max_thickness(&self) -> f32 {
+        self.keys.iter().map(|key| key.1).fold(0.0, f32::max)
+    }
+
+    pub fn min_thickness(&self) -> f32 {
+        self.keys.iter().map(|key| key.1).fold(f32::INFINITY, f32::min)
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.iter().all(|key| key.1 == self.keys[0].1)
+    }
+
+    /// Thickness at `fraction` and its derivative with respect to the fraction.
+    pub fn sample(&self, fraction: f32, closed: bool) -> (f32, f32) {
+        let (first, last) = (self.keys[0], self.keys[self.keys.len() - 1]);
+        if self.keys.len() == 1 {
+            return (first.1, 0.0);
+        }
+        let t = if closed { fraction.rem_euclid(1.0) } else { fraction };
+        let i = self.keys.partition_point(|key| key.0 <= t);
+        let (a, b) = match (i, closed) {
+            (0, false) => return (first.1, 0.0),
+            (i, false) if i == self.keys.len() => return (last.1, 0.0),
+            (0, true) => ((last.0 - 1.0, last.1), first),
+            (i, true) if i == self.keys.len() => (last, (first.0 + 1.0, first.1)),
+            (i, _) => (self.keys[i - 1], self.keys[i]),
+        };
+
+        let h = b.0 - a.0;
+        if h <= 0.0 {
+            return (b.1, 0.0);
+        }
+        let s = (t - a.0) / h;
+        let rise = b.1 - a.1;
+        (a.1 + rise * s * s * (3.0 - 2.0 * s), rise * 6.0 * s * (1.0 - s) / h)
+    }
+}
+
+impl Default for ThicknessProfile {
+    fn default() -> Self {
+        Self::constant(0.0)
+    }
+}
+
+/// Side of a spline, looking along increasing parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// The side the curve's normal points to, see [`BSpline::frame`].
+    Left,
+    Right,
+}
+
+impl Side {
+    fn sign(self) -> f32 {
+        match self {
+            Side::Left => 1.0,
+            Side::Right => -1.0,
+        }
+    }
+}
+
+/// Result of [`ThickSpline::closest_point`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfacePoint {
+    pub param: f32,
+    /// Point on the center line at `param`.
+    pub center: Vector2<f32>,
+    /// Point on the surface closest to the target.
+    pub point: Vector2<f32>,
+    /// Unit normal of the surface at `point`, pointing from the center line to
+    /// the target.
+    pub normal: Vector2<f32>,
+    /// Distance from the surface to the target, negative inside the spline.
+    pub distance: f32,
+    /// Half the thickness at `param`.
+    pub half_width: f32,
+}
+
+/// A spline together with its thickness.
+#[derive(Debug, Clone, Copy)]
+pub struct ThickSpline<'a> {
+    pub curve: &'a BSpline,
+    pub profile: &'a ThicknessProfile,
+}
+
+impl<'a> ThickSpline<'a> {
+    pub fn new(curve: &'a BSpline, profile: &'a ThicknessProfile) -> Self {
+        Self { curve, profile }
+    }
+
+    /// Half the thickness at `u`, the distance from the center line to the
+    /// surface, and its derivative with respect to `u`.
+    pub fn half_width(&self, u: f32) -> (f32, f32) {
+        let (start, end) = self.curve.domain();
+        let length = (end - start).max(f32::EPSILON);
+        let (thickness, slope) = self.profile.sample((u - start) / length, self.curve.is_closed());
+        (0.5 * thickness, 0.5 * slope / length)
+    }
+
+    /// Point on the surface on `side` that the disc at `u` touches. `None` where
+    /// the thickness changes faster than the curve moves, so the disc is inside
+    /// its neighbours.
+    pub fn offset(&self, u: f32, side: Side) -> Option<Vector2<f32>> {
+        let frame = self.curve.frame(u);
+        let (w, dw) = self.half_width(u);
+        if w == 0.0 {
+            return Some(frame.point);
+        }
+        let speed = self.curve.derivative(u, 1).norm();
+        if speed <= f32::EPSILON {
+            return None;
+        }
+        // the envelope touches where the direction e to it has e.C' = -w'
+        let along = -dw / speed;
+        let across = (1.0 - along * along).max(0.0).sqrt();
+        (along.abs() <= 1.0).then(|| frame.point + w * (along * frame.tangent + side.sign() * across * frame.normal))
+    }
+
+    /// The surface on `side` as a polyline in order along the curve, no farther
+    /// than `tolerance` from the exact offset curve. Where the center line bends
+    /// tighter than the thickness the offset curve loops back on itself, those
+    /// loops are cut off.
+    pub fn offset_polyline(&self, side: Side, tolerance: f32) -> Vec<Vector2<f32>> {
+        let curve = self.curve;
+        let knots = curve.knots();
+        let (start, _) = curve.domain();
+        let mut points = Vec::new();
+        points.extend(self.offset(start, side));
+
+        for l in curve.degree()..curve.control_points().len() {
+            let (a, b) = (knots[l], knots[l + 1]);
+            if a == b {
+                continue;
+            }
+            let step = (b - a) / OFFSET_SAMPLES as f32;
+            for i in 0..OFFSET_SAMPLES {
+                let u0 = a + i as f32 * step;
+                self.subdivide(side, u0, u0 + step, tolerance, 0, &mut points);
+            }
+        }
+        trim_loops(points)
+    }
+
+    /// Appends the offset points in `(u0, u1]`, subdividing until the chord is
+    /// within `tolerance` of the midpoint.
+    fn subdivide(&self, side: Side, u0: f32, u1: f32, tolerance: f32, depth: usize, points: &mut Vec<Vector2<f32>>) {
+        let mid = 0.5 * (u0 + u1);
+        let (p0, pm, p1) = (self.offset(u0, side), self.offset(mid, side), self.offset(u1, side));
+        let flat = match (p0, pm, p1) {
+            (Some(p0), Some(pm), Some(p1)) => distance_to_segment(pm, p0, p1) <= tolerance,
+            _ => false,
+        };
+        if flat || depth >= MAX_OFFSET_DEPTH {
+            points.extend(p1);
+            return;
+        }
+        self.subdivide(side, u0, mid, tolerance, depth + 1, points);
+        self.subdivide(side, mid, u1, tolerance, depth + 1, points);
+    }
+
+    /// Point on the surface closest to `target`, given `center`, the closest
+    /// point on the center line. For constant thickness they share the
+    /// parameter. Otherwise spans that could be closer once the thickness is
+    /// taken off are sampled, and Newton's method polishes the best sample.
+    /// `bvh`, a tree fitting the curve, finds those spans without going through
+    /// all of them.
+    pub fn closest_point(&self, target: Vector2<f32>, center: &ClosestPoint, bvh: Option<&SpanBvh>) -> SurfacePoint {
+        let curve = self.curve;
+        let mut u = center.param;
+        if !self.profile.is_constant() {
+            let gap = |u: f32| (target - curve.eval(u)).norm() - self.half_width(u).0;
+            let max_half_width = 0.5 * self.profile.max_thickness();
+            let knots = curve.knots();
+            let mut best = gap(u);
+            let reach = Aabb { min: target, max: target }.expanded((best + max_half_width).max(0.0));
+            let spans = match bvh {
+                Some(bvh) => bvh.overlapping(&reach),
+                None => (curve.degree()..curve.control_points().len()).collect(),
+            };
+            for l in spans {
+                let (a, b) = (knots[l], knots[l + 1]);
+                if a == b || curve.span_bounds(l).distance_squared(target).sqrt() - max_half_width >= best {
+                    continue;
+                }
+                for i in 0..=OFFSET_SAMPLES {
+                    let v = a + (b - a) * i as f32 / OFFSET_SAMPLES as f32;
+                    let g = gap(v);
+                    if g < best {
+                        (u, best) = (v, g);
+                    }
+                }
+            }
+
+            // newton on the slope of |target - C(u)| - w(u)
+            let slope = |u: f32| {
+                let d = target - curve.eval(u);
+                let norm = d.norm();
+                let along = if norm > f32::EPSILON {
+                    d.dot(&curve.derivative(u, 1)) / norm
+                } else {
+                    0.0
+                };
+                -along - self.half_width(u).1
+            };
+            let (start, end) = curve.domain();
+            let h = 1e-4 * (end - start);
+            for _ in 0..8 {
+                let g = slope(u);
+                let dg = (slope(u + h) - slope(u - h)) / (2.0 * h);
+                if dg <= f32::EPSILON {
+                    break;
+                }
+                let next = curve.clamp_param(u - g / dg);
+                let next_gap = gap(next);
+                if next_gap >= best {
+                    break;
+                }
+                (u, best) = (next, next_gap);
+            }
+        }
+
+        let point = curve.eval(u);
+        let (half_width, _) = self.half_width(u);
+        let d = target - point;
+        let norm = d.norm();
+        let normal = if norm > f32::EPSILON { d / norm } else { curve.normal(u) };
+        SurfacePoint {
+            param: u,
+            center: point,
+            point: point + half_width * normal,
+            normal,
+            distance: norm - half_width,
+            half_width,
+        }
+    }
+}
+
+/// Where the segments `a0 a1` and `b0 b1` cross, not counting shared ends.
+fn segment_crossing(a0: Vector2<f32>, a1: Vector2<f32>, b0: Vector2<f32>, b1: Vector2<f32>) -> Option<Vector2<f32>> {
+    let (da, db) = (a1 - a0, b1 - b0);
+    let denominator = da.perp(&db);
+    if denominator.abs() <= f32::EPSILON {
+        return None;
+    }
+    let s = (b0 - a0).perp(&db) / denominator;
+    let t = (b0 - a0).perp(&da) / denominator;
+    (0.0 < s && s < 1.0 && 0.0 < t && t < 1.0).then(|| a0 + s * da)
+}
+
+/// Cuts out the parts of `points` between a segment and a later one it crosses
+/// within [`LOOP_WINDOW`] segments, keeping the crossing.
+fn trim_loops(points: Vec<Vector2<f32>>) -> Vec<Vector2<f32>> {
+    let mut trimmed = Vec::with_capacity(points.len());
+    let mut i = 0;
+    while i + 1 < points.len() {
+        trimmed.push(points[i]);
+        let last = (i + LOOP_WINDOW).min(points.len() - 1);
+        let crossing = (i + 2..last)
+            .rev()
+            .find_map(|j| segment_crossing(points[i], points[i + 1], points[j], points[j + 1]).map(|x| (j, x)));
+        match crossing {
+            Some((j, x)) => {
+                trimmed.push(x);
+                i = j + 1;
+            }
+            None => i += 1,
+        }
+    }
+    trimmed.extend(points.last());
+    trimmed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spline::tests::s_curve_cases;
+
+    #[test]
+    fn closest_point_through_the_bvh_matches_all_spans() {
+        let profile = ThicknessProfile::new(vec![(0.0, 10.0), (0.3, 80.0), (0.6, 5.0), (1.0, 40.0)]).unwrap();
+        for (curve, targets) in s_curve_cases() {
+            let thick = ThickSpline::new(&curve, &profile);
+            let bvh = SpanBvh::new(&curve);
+            for target in targets {
+                let center = curve.closest_point(target);
+                let all = thick.closest_point(target, &center, None);
+                let through_bvh = thick.closest_point(target, &center, Some(&bvh));
+                assert!((all.distance - through_bvh.distance).abs() < 1e-3, "{target:?}: {all:?} {through_bvh:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn offset_of_a_circle_keeps_the_thickness() {
+        // counter-clockwise, so the left side is the inside
+        let circle = BSpline::circular_arc(Vector2::zeros(), 200.0, 0.0, std::f32::consts::TAU);
+        let profile = ThicknessProfile::constant(40.0);
+        let thick = ThickSpline::new(&circle, &profile);
+        let (start, end) = circle.domain();
+        for i in 0..=100 {
+            let u = start + (end - start) * i as f32 / 100.0;
+            assert!((thick.offset(u, Side::Left).unwrap().norm() - 180.0).abs() < 1e-2);
+            assert!((thick.offset(u, Side::Right).unwrap().norm() - 220.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn offset_touches_the_swept_discs() {
+        let points = (0..8)
+            .map(|i| Vector2::new(100.0 * i as f32, if i % 2 == 0 { 0.0 } else { 60.0 }))
+            .collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let profile = ThicknessProfile::new(vec![(0.0, 10.0), (0.4, 50.0), (1.0, 20.0)]).unwrap();
+        let thick = ThickSpline::new(&curve, &profile);
+        let (start, end) = curve.domain();
+        let at = |i: usize, n: usize| start + (end - start) * i as f32 / n as f32;
+
+        for i in 0..=100 {
+            for side in [Side::Left, Side::Right] {
+                let point = thick.offset(at(i, 100), side).unwrap();
+                // on the edge of the area the discs sweep: in none of them, but on the one it comes from
+                let gap = (0..=4000)
+                    .map(|j| (point - curve.eval(at(j, 4000))).norm() - thick.half_width(at(j, 4000)).0)
+                    .fold(f32::INFINITY, f32::min);
+                assert!(gap.abs() < 1e-2, "{point} is {gap} from the surface");
+                assert!(((point - curve.eval(at(i, 100))).norm() - thick.half_width(at(i, 100)).0).abs() < 1e-3);
+            }
+        }
+    }
+}
@@ -1,14 +1,14 @@
 use std::collections::HashMap;
-use std::f32::consts::PI;
-use std::iter::Map;
 use std::mem;
-use bevy::app::{App, FixedUpdate, Plugin};
+use bevy::app::{App, FixedUpdate, Plugin, PreUpdate};
+use bevy::ecs::query::QueryData;
 use bevy::ecs::schedule::ScheduleLabel;
-use bevy::math::ops::atan2;
-use bevy::math::Quat;
-use bevy::prelude::{Component, Entity, IntoScheduleConfigs, Query, SystemSet, Update, With, Without, World};
-use nalgebra::{Normed, Vector2};
-use crate::spines_plugin::{point_inside, ControlledBy, FollowMouse, HiddenControlledBy, OldPosition, Position, Spline, SplinePlugin, SplineSet};
+use bevy::prelude::{Commands, Component, DetectChanges, Entity, Has, Event, EventReader, EventWriter, Fixed, IntoScheduleConfigs, Query, Res, ResMut, Resource, SystemSet, Time, With, Without, World};
+use nalgebra::Vector2;
+use spline_grind::bvh::{CastHit, SpanBvh};
+use spline_grind::contacts::{closest_points_on_segments, SpatialHash};
+use spline_grind::offset::ThickSpline;
+use crate::spines_plugin::{Position, Spline, SplineBvh, SplineCurve, Junction, SplineJunctions, SplineSet, SplineThickness};
 
 pub struct PhysicsPlugin;
 
@@ -17,33 +17,142 @@ pub struct PhySet;
 
 impl Plugin for PhysicsPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<PhysicsConfig>();
+        app.init_resource::<PhysicsStep>();
         app.init_schedule(PhySched);
-        app.add_systems(FixedUpdate,((update_position, apply_gravity.before(update_position), collide.before(update_position),reset_collisions.before(collide)).in_set(PhySet).after(SplineSet)));
+        app.add_event::<StopGrinding>();
+        app.add_event::<GrindStarted>();
+        app.add_event::<GrindEnded>();
+        app.add_systems(PreUpdate, apply_timestep);
+        app.add_systems(FixedUpdate, (reset_collisions, run_physics).chain().in_set(PhySet).after(SplineSet));
+        app.add_systems(PhySched, (update_position, apply_gravity.before(update_position), collide_bodies.before(collide), collide.before(update_position), sweep.after(update_position)));
+        app.add_systems(PhySched, (grind.after(apply_gravity).after(collide_bodies), start_grinding.after(sweep).after(grind)));
     }
 }
+
+/// One substep of the simulation, run [`PhysicsConfig::substeps`] times per
+/// fixed step.
 #[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct PhySched;
 
-fn run_my_schedule(world: &mut World) {
-    // Run your schedule multiple times per frame:
-    for _ in 0..1 {
+/// How the simulation steps. The fixed timestep follows `timestep`, and each
+/// fixed step is split into `substeps` equal steps of [`PhySched`]. Units are
+/// seconds and free flight is integrated exactly, so bodies fly the same way
+/// whatever the timestep and substep count. Contacts are resolved once per
+/// substep, so only where bodies touch can the steps make a difference.
+#[derive(Resource, Debug, Clone)]
+pub struct PhysicsConfig {
+    pub timestep: f64,
+    pub substeps: usize,
+    /// Acceleration of `Gravitate` objects, in units per second squared.
+    pub gravity: Vector2<f32>,
+    /// Passes of collision resolution per substep.
+    pub solver_iterations: usize,
+}
+
+impl Default for PhysicsConfig {
+    fn default() -> Self {
+        Self {
+            timestep: 0.002,
+            substeps: 1,
+            gravity: Vector2::new(0.0, -640.0),
+            solver_iterations: 1,
+        }
+    }
+}
+
+/// Length of the current substep in seconds.
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct PhysicsStep {
+    pub dt: f32,
+}
+
+fn apply_timestep(config: Res<PhysicsConfig>, mut time: ResMut<Time<Fixed>>) {
+    if config.is_changed() {
+        time.set_timestep_seconds(config.timestep);
+    }
+}
+
+fn run_physics(world: &mut World) {
+    let substeps = world.resource::<PhysicsConfig>().substeps.max(1);
+    let dt = world.resource::<Time<Fixed>>().delta_secs() / substeps as f32;
+    world.resource_mut::<PhysicsStep>().dt = dt;
+    for _ in 0..substeps {
         world.run_schedule(PhySched);
     }
 }
 
+/// Mass of a `VerletObject`, 1 if not given otherwise. Splines don't move
+/// and count as infinitely heavy, as do bodies without a positive mass, whose
+/// velocity no contact changes.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Mass(pub f32);
+
+impl Default for Mass {
+    fn default() -> Self {
+        Mass(1.0)
+    }
+}
+
+impl Mass {
+    /// 1 over the mass, 0 for infinitely heavy bodies.
+    pub fn inverse(&self) -> f32 {
+        if self.0 > 0.0 { 1.0 / self.0 } else { 0.0 }
+    }
+}
+
+/// How a body or the surface of a `Spline` behaves in contacts. Both sides of
+/// a contact are combined, see [`PhysicsMaterial::combine`]. Splines without
+/// one have the default.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct PhysicsMaterial {
+    /// Ratio of the tangential to the normal impulse up to which a contact
+    /// holds a body still.
+    pub static_friction: f32,
+    /// Ratio of the tangential to the normal impulse while a body slides.
+    pub dynamic_friction: f32,
+    /// Part of the speed into the surface that a body bounces back with.
+    pub restitution: f32,
+}
+
+impl PhysicsMaterial {
+    pub const ICE: Self = Self { static_friction: 0.01, dynamic_friction: 0.005, restitution: 0.0 };
+    pub const MUD: Self = Self { static_friction: 2.0, dynamic_friction: 1.5, restitution: 0.0 };
+
+    /// Material of a contact between `self` and `other`: the geometric mean of
+    /// the frictions, so either side being slippery makes the contact slippery,
+    /// and the larger restitution.
+    pub fn combine(&self, other: &Self) -> Self {
+        Self {
+            static_friction: (self.static_friction * other.static_friction).sqrt(),
+            dynamic_friction: (self.dynamic_friction * other.dynamic_friction).sqrt(),
+            restitution: self.restitution.max(other.restitution),
+        }
+    }
+}
+
+impl Default for PhysicsMaterial {
+    fn default() -> Self {
+        Self { static_friction: 0.05, dynamic_friction: 0.03, restitution: 0.0 }
+    }
+}
+
 #[derive(Component)]
-struct Mass(f32);
-#[derive(Component)]
+#[require(Mass, PhysicsMaterial, BodyShape)]
 pub struct VerletObject{
+    /// Position before the last substep.
     pub position_old: Vector2<f32>,
+    /// In units per second. Kept apart from the positions, which are too
+    /// coarse far from the origin to carry it through short substeps.
+    pub velocity: Vector2<f32>,
     pub acceleration: Vector2<f32>,
 
 }
 
+/// Which side of an open spline a body was last outside of, see [`collide`].
 pub struct SplineColliderInfo {
-    pub intersections: i32,
-    pub start_sector: i32,
-    pub end_sector: i32,
+    /// 1 on the side the curve's normals point to, -1 on the other.
+    pub side: f32,
 }
 
 #[derive(Component)]
@@ -70,183 +179,1172 @@ impl Collider {
         Self { collisions: Vec::new(), collisions_old: Vec::new() }
     }
 
+    /// Records a contact with `c.other`, replacing any earlier one with it in
+    /// this fixed step, so there is one per other entity, the latest.
     pub fn add_collision(&mut self, c: Collision) {
-        self.collisions.push(c.clone());
-        self.collisions_old.push(c);
+        self.collisions.retain(|old| old.other != c.other);
+        self.collisions.push(c);
     }
 }
 
 #[derive(Component)]
 pub struct Gravitate();
-const gravity: Vector2<f32> = Vector2::<f32>::new(0.0, -10.0);
 
-fn setup(){
+/// Radius of the objects colliding with splines.
+const RIDER_RADIUS: f32 = 60.0;
+
+/// Shape of a `VerletObject`, a circle or a capsule around the segment between
+/// `a` and `b`, relative to the body's position. Splines only see the circle
+/// around it, see [`BodyShape::bounding_radius`]. Rider sized circles if not
+/// given otherwise.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub enum BodyShape {
+    Circle { radius: f32 },
+    Capsule { a: Vector2<f32>, b: Vector2<f32>, radius: f32 },
+}
+
+impl Default for BodyShape {
+    fn default() -> Self {
+        BodyShape::Circle { radius: RIDER_RADIUS }
+    }
+}
 
+impl BodyShape {
+    /// Radius of the smallest circle around the position that holds the shape.
+    pub fn bounding_radius(&self) -> f32 {
+        match *self {
+            BodyShape::Circle { radius } => radius,
+            BodyShape::Capsule { a, b, radius } => a.norm().max(b.norm()) + radius,
+        }
+    }
+
+    /// Ends of the core segment at `position` and the radius around it.
+    fn core(&self, position: Vector2<f32>) -> (Vector2<f32>, Vector2<f32>, f32) {
+        match *self {
+            BodyShape::Circle { radius } => (position, position, radius),
+            BodyShape::Capsule { a, b, radius } => (position + a, position + b, radius),
+        }
+    }
 }
 
+/// Bodies not [`Grinding`], which follow their spline instead.
+type FreeBody = Without<Grinding>;
+
+/// Splines a [`Grinder`] can start [`Grinding`] on.
+type GrindableSpline = (With<Spline>, With<Grindable>);
+
+/// Most splines a body can hit within one substep, see [`sweep`].
+const MAX_SWEEPS: usize = 4;
+
+/// Speed into a surface, in units per second, below which bodies don't bounce,
+/// so resting contacts stay at rest.
+const RESTITUTION_THRESHOLD: f32 = 100.0;
+
 fn apply_gravity(
-   mut query: Query<(&mut VerletObject), With<Gravitate>>
+   config: Res<PhysicsConfig>,
+   mut query: Query<&mut VerletObject, With<Gravitate>>
 ){
 
-    for (mut verlet_object) in &mut query {
-        verlet_object.acceleration += gravity;
+    for mut verlet_object in &mut query {
+        verlet_object.acceleration += config.gravity;
     }
 
 }
 
 fn reset_collisions(
 
-    mut query: Query<(&mut Collider)>
+    mut query: Query<&mut Collider>
 ){
-    for (mut collider) in &mut query {
+    for mut collider in &mut query {
         collider.collisions_old = mem::take(&mut collider.collisions);
     }
 
 }
 fn update_position(
 
-
-    mut query: Query<(&mut VerletObject, &mut Position)>
+    step: Res<PhysicsStep>,
+    mut query: Query<(&mut VerletObject, &mut Position), Without<Grinding>>
 ){
 
-    let dt = 0.016;
-
     for (mut verlet_object, mut pos) in &mut query {
+        integrate(&mut verlet_object, &mut pos, step.dt);
+    }
+}
 
-        let vel = pos.0 - verlet_object.position_old;
+/// Moves a free body through one substep of length `dt`, exactly for an
+/// acceleration that stays the same through it, like gravity.
+fn integrate(verlet_object: &mut VerletObject, pos: &mut Position, dt: f32) {
+    let acceleration = verlet_object.acceleration;
+    verlet_object.position_old = pos.0;
+    pos.0 += (verlet_object.velocity + 0.5 * acceleration * dt) * dt;
 
-        verlet_object.position_old = pos.0;
-        pos.0 = pos.0 + vel + verlet_object.acceleration * dt * dt;
+    verlet_object.velocity += acceleration * dt;
 
-        verlet_object.acceleration = Vector2::zeros();
+    verlet_object.acceleration = Vector2::zeros();
+}
 
-    }
+
+/// What [`collide`] needs of a spline.
+#[derive(QueryData)]
+struct SplineCollider {
+    entity: Entity,
+    curve: &'static SplineCurve,
+    thickness: &'static SplineThickness,
+    bvh: &'static SplineBvh,
+    material: Option<&'static PhysicsMaterial>,
+    junctions: &'static SplineJunctions,
 }
 
+/// What [`collide`] needs of a body.
+type CollidingBody = (
+    &'static mut Position,
+    &'static mut VerletObject,
+    &'static Mass,
+    &'static PhysicsMaterial,
+    &'static BodyShape,
+    &'static mut Collider,
+    &'static mut SplineMemory,
+    Option<&'static Grinder>,
+);
 
 fn collide(
-    mut query: Query<(&mut Position, &mut VerletObject, &mut Collider, &mut SplineMemory)>,
-    spline_query: Query<(&Spline, &ControlledBy, &HiddenControlledBy, Entity)>,
-    position_query: Query<(&Position), Without<VerletObject>>,
+    config: Res<PhysicsConfig>,
+    mut query: Query<CollidingBody, FreeBody>,
+    spline_query: Query<SplineCollider, With<Spline>>,
 ){
+    for _ in 0..config.solver_iterations.max(1) {
+        for spline in &spline_query {
+            let (entity, thickness, bvh) = (spline.entity, spline.thickness, spline.bvh);
+            let Some(curve) = &spline.curve.0 else {
+                continue;
+            };
+            let thick = ThickSpline::new(curve, thickness);
+            // the tree is refitted at the end of the spline set, after any knot edits
+            let bvh = bvh.fits(curve).then_some(&bvh.0);
+            let reach = bvh
+                .and_then(SpanBvh::bounds)
+                .map(|bounds| bounds.expanded(0.5 * thickness.max_thickness()));
+
+            let surface_material = spline.material.copied().unwrap_or_default();
+
+            for (mut pos, mut verlet, mass, material, shape, mut collider, mut spline_memory, grinder) in &mut query {
+                let radius = shape.bounding_radius();
+
+                if rides_through(&spline, &collider, grinder, pos.0, radius) {
+                    // the side it comes out on is the one it stays on
+                    if !curve.is_closed() {
+                        let closest = curve.closest_point(pos.0);
+                        let side = if (pos.0 - closest.point).dot(&curve.frame(closest.param).normal) < 0.0 { -1.0 } else { 1.0 };
+                        spline_memory.spline_intersections.insert(entity, SplineColliderInfo { side });
+                    }
+                    continue;
+                }
+
+                // nothing to collide with and no inside to be in
+                if curve.is_closed() && reach.is_some_and(|reach| !reach.expanded(radius).contains(pos.0)) {
+                    continue;
+                }
+
+                let closest = bvh.and_then(|bvh| bvh.closest_point(curve, pos.0));
+                let closest = closest.unwrap_or_else(|| curve.closest_point(pos.0));
+                let surface = thick.closest_point(pos.0, &closest, bvh);
+                let point = surface.center;
+                let half_width = surface.half_width;
+
+                let mut normal: Vector2<f32> = surface.normal;
+
+                if curve.is_closed() {
+                    if curve.contains(pos.0) {
+                        normal *= -1.0;
+                    }
+                }
+                else {
+                    let side = if (pos.0 - closest.point).dot(&curve.frame(closest.param).normal) < 0.0 { -1.0 } else { 1.0 };
+                    // the side only changes around the ends, anywhere else the
+                    // body went through the spline and goes back
+                    let (start, end) = curve.domain();
+                    let through = start < closest.param && closest.param < end;
+                    match spline_memory.spline_intersections.get(&entity) {
+                        Some(info) if info.side != side && through => {
+                            normal *= -1.0;
+                        }
+                        _ => {
+                            spline_memory.spline_intersections.insert(entity, SplineColliderInfo { side });
+                        }
+                    }
+                }
+
+                let overground = point + normal *( radius + half_width);
+
+                let depth = radius + half_width - ((pos.0 - point).transpose() * normal).x;
+
+                if depth > 0.0 {
+
+                    pos.0 = overground;
+                    let impulse = contact_impulse(verlet.velocity, normal, mass.inverse(), &material.combine(&surface_material));
+                    verlet.velocity += impulse * mass.inverse();
+
+                    collider.add_collision(Collision{other: entity, point: point + normal * half_width, normal});
+
+                }
+            }
+        }
+    }
+}
 
-    let line_widht = 5.0;
-    // let dt = 0.1;
+/// Lowest crossing angle's sine [`rides_through`] reckons with, so splines
+/// running along each other don't reach out forever.
+const MIN_JUNCTION_SINE: f32 = 0.1;
+
+/// Whether a body at `position` of `radius` rides through `spline` where it
+/// crosses another spline, see [`Junction`]. It does near the crossing if it
+/// is touching the other spline and not this one, unless its [`Grinder`]
+/// wants to switch rails.
+fn rides_through(spline: &SplineColliderItem, collider: &Collider, grinder: Option<&Grinder>, position: Vector2<f32>, radius: f32) -> bool {
+    if grinder.is_some_and(|grinder| grinder.switch_rails) {
+        return false;
+    }
+    let touching = |other: Entity| collider.collisions.iter().chain(&collider.collisions_old).any(|c| c.other == other);
+    if touching(spline.entity) {
+        return false;
+    }
+    // the flatter the crossing, the further apart the body touches both
+    let offset = radius + 0.5 * spline.thickness.max_thickness();
+    spline.junctions.0.iter().any(|junction| {
+        touching(junction.other) && (position - junction.point).norm() < offset * (1.0 + 2.0 / junction.sine.max(MIN_JUNCTION_SINE))
+    })
+}
 
-    let mut temp_buf: [Vector2<f32>; 4] = [Vector2::new(0.0, 0.0); 4];
+/// Continuous collision detection for bodies fast enough to pass through a
+/// spline within one substep. The body's circle, shrunk to the thinnest part
+/// of the spline, is swept from where it started the substep. At the first
+/// spline it touches the body is put onto the surface, gets the contact
+/// impulse, and moves on at its new velocity for the rest of the substep.
+fn sweep(
+    step: Res<PhysicsStep>,
+    mut query: Query<Body, FreeBody>,
+    spline_query: Query<SplineCollider, With<Spline>>,
+){
+    for mut body in &mut query {
+        let radius = body.shape.bounding_radius();
+        let mut start = body.verlet.position_old;
+        let mut motion = body.position.0 - start;
+
+        for _ in 0..MAX_SWEEPS {
+            // shorter moves from outside a spline's surface, where collide left
+            // the body, end outside its core and are handled there
+            let length = motion.norm();
+            if length < radius {
+                break;
+            }
+            let direction = motion / length;
+
+            let mut first: Option<(CastHit, f32, Entity, PhysicsMaterial)> = None;
+            for spline in &spline_query {
+                let Some(curve) = &spline.curve.0 else {
+                    continue;
+                };
+                if body.collider.as_deref().is_some_and(|collider| rides_through(&spline, collider, body.grinder, start, radius)) {
+                    continue;
+                }
+                // the tree is only out of date right after knot edits
+                let rebuilt;
+                let bvh = if spline.bvh.fits(curve) {
+                    &spline.bvh.0
+                } else {
+                    rebuilt = SpanBvh::new(curve);
+                    &rebuilt
+                };
+                let core = 0.5 * spline.thickness.min_thickness();
+                let max_distance = first.as_ref().map_or(length, |(hit, ..)| hit.distance);
+                let Some(hit) = bvh.circle_cast(curve, start, core, direction, max_distance) else {
+                    continue;
+                };
+                let (half_width, _) = ThickSpline::new(curve, spline.thickness).half_width(hit.param);
+                first = Some((hit, half_width, spline.entity, spline.material.copied().unwrap_or_default()));
+            }
+            let Some((hit, half_width, entity, surface_material)) = first else {
+                break;
+            };
+
+            body.position.0 = hit.point + hit.normal * (radius + half_width);
+            let impulse = contact_impulse(body.verlet.velocity, hit.normal, body.mass.inverse(), &body.material.combine(&surface_material));
+            body.verlet.velocity += impulse * body.mass.inverse();
+            if let Some(collider) = body.collider.as_mut() {
+                collider.add_collision(Collision { other: entity, point: hit.point + hit.normal * half_width, normal: hit.normal });
+            }
+
+            // the rest of the substep from the point of impact
+            start = body.position.0;
+            motion = body.verlet.velocity * step.dt * (1.0 - hit.distance / length);
+            body.position.0 = start + motion;
+        }
+    }
+}
+
+/// What [`collide_bodies`] and [`sweep`] need of a body.
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct Body {
+    entity: Entity,
+    position: &'static mut Position,
+    verlet: &'static mut VerletObject,
+    mass: &'static Mass,
+    material: &'static PhysicsMaterial,
+    shape: &'static BodyShape,
+    collider: Option<&'static mut Collider>,
+    grinder: Option<&'static Grinder>,
+    grinding: Has<Grinding>,
+}
 
+impl BodyItem<'_> {
+    /// Changes the velocity by `change` over a substep of `dt`. Grinding bodies
+    /// take it as an acceleration, which [`grind`] splits into the part along
+    /// the spline and the part pressing onto it or pulling off.
+    fn change_velocity(&mut self, change: Vector2<f32>, dt: f32) {
+        if self.grinding {
+            self.verlet.acceleration += change / dt;
+        } else {
+            self.verlet.velocity += change;
+        }
+    }
+}
 
-    for (spline, controlled_by, hidden_controlled_by, entity) in &spline_query {
-        let control_points = controlled_by.as_slice();
-        let hidden_control_points = hidden_controlled_by.as_slice();
+/// Pushes overlapping bodies apart, each by its share of the inverse masses,
+/// and exchanges the contact impulse between them. Pairs come from a grid with
+/// cells as large as the largest body.
+fn collide_bodies(
+    config: Res<PhysicsConfig>,
+    step: Res<PhysicsStep>,
+    mut query: Query<Body>,
+){
+    let mut bodies: Vec<BodyItem> = query.iter_mut().collect();
+    let cell_size = 2.0 * bodies.iter().map(|body| body.shape.bounding_radius()).fold(0.0, f32::max);
+    let mut grid = SpatialHash::new(cell_size);
+    for (i, body) in bodies.iter().enumerate() {
+        grid.insert(i, body.position.0);
+    }
+    let pairs = grid.pairs();
+
+    for _ in 0..config.solver_iterations.max(1) {
+        for &(i, j) in &pairs {
+            let [a, b] = bodies.get_disjoint_mut([i, j]).expect("pairs are of different bodies");
+            let (a1, a2, radius_a) = a.shape.core(a.position.0);
+            let (b1, b2, radius_b) = b.shape.core(b.position.0);
+            let (on_a, on_b) = closest_points_on_segments(a1, a2, b1, b2);
+            let distance = (on_a - on_b).norm();
+            let depth = radius_a + radius_b - distance;
+            if depth <= 0.0 {
+                continue;
+            }
+            // bodies right on top of each other are pushed apart vertically
+            let normal = if distance > f32::EPSILON { (on_a - on_b) / distance } else { Vector2::y() };
+            let (inverse_a, inverse_b) = (a.mass.inverse(), b.mass.inverse());
+            // two infinitely heavy bodies share the push evenly
+            let share = if inverse_a + inverse_b > 0.0 { inverse_a / (inverse_a + inverse_b) } else { 0.5 };
+            a.position.0 += normal * depth * share;
+            b.position.0 -= normal * depth * (1.0 - share);
+
+            let material = a.material.combine(b.material);
+            let impulse = contact_impulse(a.verlet.velocity - b.verlet.velocity, normal, inverse_a + inverse_b, &material);
+            a.change_velocity(impulse * inverse_a, step.dt);
+            b.change_velocity(-impulse * inverse_b, step.dt);
+
+            let point = 0.5 * (on_a - normal * radius_a + on_b + normal * radius_b);
+            let (entity_a, entity_b) = (a.entity, b.entity);
+            if let Some(collider) = a.collider.as_mut() {
+                collider.add_collision(Collision { other: entity_b, point, normal });
+            }
+            if let Some(collider) = b.collider.as_mut() {
+                collider.add_collision(Collision { other: entity_a, point, normal: -normal });
+            }
+        }
+    }
+}
 
+/// Impulse on a body moving at `velocity` relative to a surface it touches with
+/// `normal` facing it, where `inverse_mass` is the sum of both sides' inverse
+/// masses. It stops the body moving into the surface, or bounces it back if it
+/// was fast enough, and holds it still or slows its sliding with Coulomb
+/// friction.
+fn contact_impulse(velocity: Vector2<f32>, normal: Vector2<f32>, inverse_mass: f32, material: &PhysicsMaterial) -> Vector2<f32> {
+    let approach = velocity.dot(&normal);
+    if approach >= 0.0 || inverse_mass <= 0.0 {
+        return Vector2::zeros();
+    }
+    let restitution = if -approach > RESTITUTION_THRESHOLD { material.restitution } else { 0.0 };
+    let normal_impulse = -(1.0 + restitution) * approach / inverse_mass;
 
+    let sliding = velocity - approach * normal;
+    let speed = sliding.norm();
+    if speed <= f32::EPSILON {
+        return normal * normal_impulse;
+    }
+    // what it takes to stop the sliding, if static friction manages
+    let stop = speed / inverse_mass;
+    let friction = if stop <= material.static_friction * normal_impulse {
+        stop
+    } else {
+        stop.min(material.dynamic_friction * normal_impulse)
+    };
+    normal * normal_impulse - sliding / speed * friction
+}
 
-        let positions: Vec<Vector2<f32>> = control_points
-            .iter()
-            .filter_map(|e| position_query.get(*e).ok())
-            .map(|p| p.0)
-            .collect();
+/// Seconds after leaving a spline before a [`Grinder`] can start grinding again.
+const REGRIND_DELAY: f32 = 0.25;
+
+/// Lowest ratio of a grinding body's path length to that of the spline's
+/// center line, where the spline bends tighter than the body is wide.
+const MIN_STRETCH: f32 = 0.1;
+
+/// Makes a body start [`Grinding`] on the [`Grindable`] spline it touches,
+/// once `cooldown` seconds have run out.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Grinder {
+    pub cooldown: f32,
+    /// Whether to take the other spline where two cross, see [`Junction`],
+    /// rather than riding on through it.
+    pub switch_rails: bool,
+}
 
-        let hidden_positions: Vec<Vector2<f32>> = hidden_control_points
-            .iter()
-            .filter_map(|e| position_query.get(*e).ok())
-            .map(|p| p.0)
-            .collect();
+/// Splines a [`Grinder`] can grind on. Others, like the ground, it only
+/// collides with.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct Grindable;
+
+/// A body riding along `spline` at `param` on either side. Instead of moving
+/// freely it stays on the surface, pressed onto it by gravity and pushed along
+/// by the part of gravity along the curve, until the surface would have to
+/// pull it back to keep it on a bend, it runs off an end, or a
+/// [`StopGrinding`] comes in.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Grinding {
+    pub spline: Entity,
+    pub param: f32,
+    /// Speed along the curve's tangent at `param`, in units per second, negative
+    /// towards the start. Can be changed at any time, e.g. for a boost.
+    pub speed: f32,
+}
 
+/// Asks `body` to jump off the spline it is grinding.
+#[derive(Event, Debug, Clone)]
+pub struct StopGrinding {
+    pub body: Entity,
+}
 
-        for (mut pos, mut verlet, mut collider, mut spline_memory) in &mut query {
+/// `body` started [`Grinding`] on `spline`.
+#[derive(Event, Debug, Clone)]
+pub struct GrindStarted {
+    pub body: Entity,
+    pub spline: Entity,
+}
 
+/// `body` came off `spline`, asked to or not.
+#[derive(Event, Debug, Clone)]
+pub struct GrindEnded {
+    pub body: Entity,
+    pub spline: Entity,
+}
 
-            let t = crate::spines_plugin::get_nearest_spline_point(pos.0, &positions);
+/// What [`grind`] needs of a body.
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct GrindingBody {
+    entity: Entity,
+    grinding: &'static mut Grinding,
+    position: &'static mut Position,
+    verlet: &'static mut VerletObject,
+    shape: &'static BodyShape,
+    collider: Option<&'static mut Collider>,
+    memory: Option<&'static mut SplineMemory>,
+    grinder: Option<&'static mut Grinder>,
+}
 
-            let mut v:Vec<f32> = Vec::with_capacity(positions.len() + 4);
-            v.extend(std::iter::repeat(0.0).take(4)); // first n zeros
-            for i in 1..(positions.len() - 3 ) {
-                v.push(i as f32);
+fn grind(
+    mut commands: Commands,
+    step: Res<PhysicsStep>,
+    mut stop_requests: EventReader<StopGrinding>,
+    mut started: EventWriter<GrindStarted>,
+    mut ended: EventWriter<GrindEnded>,
+    mut query: Query<GrindingBody>,
+    spline_query: Query<GrindSpline, With<Spline>>,
+){
+    let dt = step.dt;
+    let stopping: Vec<Entity> = stop_requests.read().map(|request| request.body).collect();
+
+    for mut body in &mut query {
+        let spline = body.grinding.spline;
+        let curve = spline_query.get(spline).ok().and_then(|(curve, thickness, ..)| Some((curve.0.as_ref()?, thickness)));
+        let next = curve.filter(|_| !stopping.contains(&body.entity)).and_then(|(curve, thickness)| {
+            grind_step(&ThickSpline::new(curve, thickness), body.shape.bounding_radius(), &body.grinding, body.position.0, body.verlet.acceleration, dt)
+        });
+
+        match next {
+            Some(next) => {
+                let switching = body.grinder.as_ref().is_some_and(|grinder| grinder.switch_rails);
+                let junction = switching.then(|| junction_passed(&spline_query, spline, body.grinding.param, next.param)).flatten();
+                match junction {
+                    // on from the crossing along the other spline, at the speed along it
+                    Some((junction, tangent)) => {
+                        body.grinding.spline = junction.other;
+                        body.grinding.param = junction.other_param;
+                        body.grinding.speed = next.velocity.dot(&tangent);
+                        ended.write(GrindEnded { body: body.entity, spline });
+                        started.write(GrindStarted { body: body.entity, spline: junction.other });
+                    }
+                    None => {
+                        body.grinding.param = next.param;
+                        body.grinding.speed = next.speed;
+                    }
+                }
+                body.verlet.position_old = body.position.0;
+                body.position.0 = next.position;
+                body.verlet.velocity = next.velocity;
+                body.verlet.acceleration = Vector2::zeros();
+                if let Some(collider) = body.collider.as_mut() {
+                    collider.add_collision(Collision { other: body.grinding.spline, point: next.contact, normal: next.normal });
+                }
             }
-            v.extend(std::iter::repeat((positions.len() - 3) as f32).take(4)); // last n zeros
+            None => {
+                // off on its own for the rest of the substep
+                let GrindingBodyItem { verlet, position, .. } = &mut body;
+                integrate(verlet, position, dt);
+                // which side of open splines it is on is only known from here on
+                if let Some(memory) = body.memory.as_mut() {
+                    memory.spline_intersections.clear();
+                }
+                if let Some(grinder) = body.grinder.as_mut() {
+                    grinder.cooldown = REGRIND_DELAY;
+                }
+                commands.entity(body.entity).remove::<Grinding>();
+                ended.write(GrindEnded { body: body.entity, spline });
+            }
+        }
+    }
+}
 
+/// What [`grind`] needs of a spline.
+type GrindSpline = (&'static SplineCurve, &'static SplineThickness, &'static SplineJunctions, Has<Grindable>);
+
+/// The first junction a body grinding `spline` passes going from `from` to
+/// `to` where a [`Grindable`] spline crosses, with that spline's tangent there.
+fn junction_passed(spline_query: &Query<GrindSpline, With<Spline>>, spline: Entity, from: f32, to: f32) -> Option<(Junction, Vector2<f32>)> {
+    let (curve, _, junctions, _) = spline_query.get(spline).ok()?;
+    let (start, end) = curve.0.as_ref()?.domain();
+    // steps over the seam of a loop are left out
+    if (to - from).abs() > 0.5 * (end - start) {
+        return None;
+    }
+    // not the one it just came onto this spline at
+    let passed = |param: f32| if from < to { from < param && param <= to } else { to <= param && param < from };
+    junctions.0.iter()
+        .filter(|junction| passed(junction.param))
+        .min_by(|a, b| (a.param - from).abs().total_cmp(&(b.param - from).abs()))
+        .and_then(|junction| {
+            let (other, _, _, grindable) = spline_query.get(junction.other).ok()?;
+            let other = other.0.as_ref().filter(|_| grindable)?;
+            Some((*junction, other.tangent(junction.other_param)))
+        })
+}
 
-            let l = crate::spines_plugin::find_knot::<4>(t, &v);
-            let point = crate::spines_plugin::de_boors::<4>(&positions, t, &v, &mut temp_buf, l);
-            let grad = crate::spines_plugin::de_boors_derivative::<4>(&positions, t, &v, &mut temp_buf, l);
+/// Where a body grinding ends up after a substep, see [`grind_step`].
+#[derive(Debug)]
+struct GrindStep {
+    param: f32,
+    speed: f32,
+    position: Vector2<f32>,
+    velocity: Vector2<f32>,
+    /// Point on the surface under the body, and the surface's normal there.
+    contact: Vector2<f32>,
+    normal: Vector2<f32>,
+}
 
+/// One substep of a body of `radius` at `position` grinding `thick`, with
+/// `acceleration` from gravity and bumps. `None` if it comes off: where the
+/// surface would have to pull on it to keep it on a bend, or past the end of an
+/// open spline.
+fn grind_step(thick: &ThickSpline, radius: f32, grinding: &Grinding, position: Vector2<f32>, acceleration: Vector2<f32>, dt: f32) -> Option<GrindStep> {
+    let curve = thick.curve;
+    let u = grinding.param;
+    let frame = curve.frame(u);
+    let offset = radius + thick.half_width(u).0;
+    let side = if (position - frame.point).dot(&frame.normal) < 0.0 { -1.0 } else { 1.0 };
+
+    // the body's path runs parallel to the center line, shorter and bending
+    // tighter on the inside of a bend
+    let stretch = (1.0 - side * frame.curvature * offset).max(MIN_STRETCH);
+    let bend = frame.curvature / stretch;
+    let speed = grinding.speed + acceleration.dot(&frame.tangent) * dt;
+    // what the surface has to push with for the body to follow the bend
+    let pressure = side * (speed * speed * bend - acceleration.dot(&frame.normal));
+    if pressure < 0.0 {
+        return None;
+    }
 
+    // a midpoint step along the arc length, corrected once by newton on the
+    // length of the step by Simpson's rule. Like free flight, the distance is
+    // exact for the acceleration along the curve staying the same
+    let distance = 0.5 * (grinding.speed + speed) * dt / stretch;
+    let param_speed = |u: f32| curve.derivative(u, 1).norm().max(f32::EPSILON);
+    let mut step = distance / param_speed(u + 0.5 * distance / param_speed(u));
+    let length = step / 6.0 * (param_speed(u) + 4.0 * param_speed(u + 0.5 * step) + param_speed(u + step));
+    step -= (length - distance) / param_speed(u + step);
+    let mut param = u + step;
+    let (start, end) = curve.domain();
+    if curve.is_closed() {
+        param = start + (param - start).rem_euclid(end - start);
+    } else if param < start || param > end {
+        return None;
+    }
+
+    let frame = curve.frame(param);
+    let normal = side * frame.normal;
+    let half_width = thick.half_width(param).0;
+    Some(GrindStep {
+        param,
+        speed,
+        position: frame.point + normal * (radius + half_width),
+        velocity: speed * frame.tangent,
+        contact: frame.point + normal * half_width,
+        normal,
+    })
+}
 
-            // let t_old = crate::spines_plugin::get_nearest_spline_point(verlet.position_old, &positions_old);
-            // let l = crate::spines_plugin::find_knot::<4>(t_old, &v);
-            // let point_old = crate::spines_plugin::de_boors::<4>(&positions_old, t_old, &v, &mut temp_buf, l);
-            // let grad_old = crate::spines_plugin::de_boors_derivative::<4>(&positions_old, t_old, &v, &mut temp_buf, l);
+/// Starts [`Grinding`] on the first [`Grindable`] spline a [`Grinder`] touched
+/// this step.
+fn start_grinding(
+    mut commands: Commands,
+    step: Res<PhysicsStep>,
+    mut started: EventWriter<GrindStarted>,
+    mut query: Query<(Entity, &Position, &VerletObject, &Collider, &mut Grinder), FreeBody>,
+    spline_query: Query<(&SplineCurve, &SplineBvh), GrindableSpline>,
+){
+    for (entity, pos, verlet, collider, mut grinder) in &mut query {
+        if grinder.cooldown > 0.0 {
+            grinder.cooldown = (grinder.cooldown - step.dt).max(0.0);
+            continue;
+        }
+        let touched = collider.collisions.iter().find_map(|collision| {
+            let (curve, bvh) = spline_query.get(collision.other).ok()?;
+            Some((collision.other, curve.0.as_ref()?, bvh))
+        });
+        let Some((spline, curve, bvh)) = touched else {
+            continue;
+        };
+        let closest = bvh.fits(curve).then(|| bvh.closest_point(curve, pos.0)).flatten();
+        let param = closest.unwrap_or_else(|| curve.closest_point(pos.0)).param;
+        let speed = verlet.velocity.dot(&curve.tangent(param));
+        commands.entity(entity).insert(Grinding { spline, param, speed });
+        started.write(GrindStarted { body: entity, spline });
+    }
+}
 
-            let mut normal: Vector2<f32> = (pos.0 - point).normalize();
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bevy::ecs::event::Events;
+    use bevy::ecs::system::RunSystemOnce;
+    use spline_grind::intersect::intersections;
+    use spline_grind::offset::ThicknessProfile;
+    use spline_grind::spline::BSpline;
+    use crate::spines_plugin::{update_bvh, update_curve, ControlPoint, Junction, SplineDegree};
+    use super::*;
+
+    #[test]
+    fn fast_body_does_not_pass_through_a_thin_rail() {
+        let mut world = World::new();
+        world.insert_resource(PhysicsStep { dt: 0.002 });
+
+        // flat where the body crosses, rising at the far end
+        let mut points: Vec<_> = (-5..=5).map(|i| Vector2::new(200.0 * i as f32, 0.0)).collect();
+        points[10].y = 400.0;
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        world.spawn((
+            Spline(),
+            SplineBvh(SpanBvh::new(&curve)),
+            SplineCurve(Some(curve)),
+            SplineThickness(ThicknessProfile::constant(2.0)),
+        ));
+
+        // crosses the rail at a shallow angle within one substep
+        let (from, to) = (Vector2::new(-500.0, 10.0), Vector2::new(500.0, -10.0));
+        let body = world
+            .spawn((
+                Position(to),
+                VerletObject { position_old: from, velocity: (to - from) / 0.002, acceleration: Vector2::zeros() },
+                Collider::new(),
+            ))
+            .id();
+
+        world.run_system_once(sweep).unwrap();
+
+        let position = world.get::<Position>(body).unwrap().0;
+        assert!(position.y >= RIDER_RADIUS, "{position:?}");
+    }
 
-            // let normal_old: Vector2<f32> = (verlet.position_old - point_old).normalize();
+    /// Degree of the curve of a zigzag spline asking for `degree`, and where a
+    /// body next to its middle corner touches it.
+    fn touch_zigzag(degree: usize) -> (usize, Vector2<f32>) {
+        let mut world = World::new();
+        world.init_resource::<PhysicsConfig>();
+        let spline = world.spawn((Spline(), SplineDegree(degree), SplineThickness(ThicknessProfile::constant(2.0)))).id();
+        for i in 0..6 {
+            world.spawn((Position(Vector2::new(100.0 * i as f32, 100.0 * (i % 2) as f32)), ControlPoint(spline)));
+        }
+        world.run_system_once(update_curve).unwrap();
+        world.run_system_once(update_bvh).unwrap();
+
+        let position = Vector2::new(200.0, 30.0);
+        let body = world
+            .spawn((
+                Position(position),
+                VerletObject { position_old: position, velocity: Vector2::zeros(), acceleration: Vector2::zeros() },
+                Collider::new(),
+                SplineMemory { spline_intersections: HashMap::new() },
+            ))
+            .id();
+        world.run_system_once(collide).unwrap();
+
+        let curve = world.get::<SplineCurve>(spline).unwrap().0.clone().unwrap();
+        let collisions = &world.get::<Collider>(body).unwrap().collisions;
+        assert_eq!(collisions.len(), 1);
+        (curve.degree(), collisions[0].point)
+    }
 
-            // let cross = cross2d(normal, grad);
-            // let cross_old = cross2d(normal_old, grad_old);
+    #[test]
+    fn splines_collide_with_the_degree_they_ask_for() {
+        let points: Vec<_> = (0..6).map(|i| Vector2::new(100.0 * i as f32, 100.0 * (i % 2) as f32)).collect();
+        let mut touched = Vec::new();
+        // six control points allow at most a quintic
+        for (asked, built) in [(1, 1), (2, 2), (5, 5), (6, 5), (9, 5)] {
+            let (degree, point) = touch_zigzag(asked);
+            assert_eq!(degree, built);
+            let curve = BSpline::clamped_uniform(points.clone(), built).unwrap();
+            let closest = curve.closest_point(Vector2::new(200.0, 30.0));
+            assert!((point - closest.point).norm() < 1.5, "{asked} {point:?} {closest:?}");
+            touched.push(point);
+        }
+        assert!((touched[0] - touched[1]).norm() > 5.0 && (touched[1] - touched[2]).norm() > 5.0, "{touched:?}");
+        assert_eq!(touched[2], touched[3]);
+        assert_eq!(touched[2], touched[4]);
+    }
 
-            // let angle = {
-            //     let delta_x = normal.x * normal_old.x + normal.y * normal_old.y; // cos(Δθ) = dot(a,b)
-            //     let delta_y = normal.y * normal_old.x - normal.x * normal_old.y; // sin(Δθ) = cross(a,b) in 2D
-            //     delta_y.atan2(delta_x) // returns Δθ in [-π, π]
-            // };
+    /// Where a ball thrown onto a sloped rail is after `seconds`, and how fast,
+    /// stepping every fixed step of `timestep` in `substeps` substeps.
+    fn thrown_ball(timestep: f32, substeps: usize, seconds: f32) -> (Vector2<f32>, Vector2<f32>) {
+        let mut app = App::new();
+        app.add_plugins(PhysicsPlugin);
+        app.insert_resource(PhysicsConfig { substeps, ..Default::default() });
+        app.init_resource::<Time<Fixed>>();
+        let world = app.world_mut();
+
+        let points: Vec<_> = (-5..=5).map(|i| Vector2::new(200.0 * i as f32, -40.0 * i as f32)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        world.spawn((
+            Spline(),
+            SplineBvh(SpanBvh::new(&curve)),
+            SplineCurve(Some(curve)),
+            SplineThickness(ThicknessProfile::constant(2.0)),
+        ));
+
+        let start = Vector2::new(-300.0, 200.0);
+        let body = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity: Vector2::new(150.0, 100.0), acceleration: Vector2::zeros() },
+                Gravitate(),
+                Collider::new(),
+                SplineMemory { spline_intersections: HashMap::new() },
+            ))
+            .id();
+
+        simulate(world, timestep, seconds);
+        (world.get::<Position>(body).unwrap().0, world.get::<VerletObject>(body).unwrap().velocity)
+    }
 
-            let mut underground = false;
-            let (inside, count) = point_inside(
-                pos.0,
-                &positions,
-                spline_memory
-                    .spline_intersections
-                    .get(&entity)
-            );
-            if(inside) {
-                underground= true;
-                normal = normal * -1.0;
+    #[test]
+    fn thrown_ball_lands_on_the_rail() {
+        let (position, _) = thrown_ball(0.004, 8, 1.0);
+        // landed and slid, rather than falling through
+        assert!(position.y > 0.0, "{position:?}");
+    }
 
-            }
-            else{
-               spline_memory.spline_intersections.insert(entity, count);
-            }
+    #[test]
+    fn free_flight_is_the_same_for_any_substep_count() {
+        // still in the air, where it follows a parabola
+        let seconds = 0.5;
+        let gravity = PhysicsConfig::default().gravity;
+        let exact = Vector2::new(-300.0, 200.0) + Vector2::new(150.0, 100.0) * seconds + 0.5 * gravity * seconds * seconds;
+        let exact_velocity = Vector2::new(150.0, 100.0) + gravity * seconds;
+        for substeps in [1, 2, 4, 8] {
+            let (position, velocity) = thrown_ball(0.004, substeps, seconds);
+            assert!((position - exact).norm() < 1e-2, "{substeps} {position:?} {exact:?}");
+            assert!((velocity - exact_velocity).norm() < 1e-2, "{substeps} {velocity:?} {exact_velocity:?}");
+        }
+    }
 
-            // if(angle.abs() > (PI/10.0)) {
-            //     underground= true;
-            //     normal = normal * -1.0;
-            // }
-            let overground = point + normal *( 60.0 + line_widht);
+    /// A world with a rail of `material` through the origin, sloping down to the
+    /// right by `slope`, and a body of `material` resting on it with `velocity`.
+    fn body_on_slope(slope: f32, material: PhysicsMaterial, velocity: Vector2<f32>) -> (App, Entity) {
+        let mut app = App::new();
+        app.add_plugins(PhysicsPlugin);
+        app.init_resource::<Time<Fixed>>();
+        let world = app.world_mut();
+
+        let points: Vec<_> = (-5..=5).map(|i| Vector2::new(200.0 * i as f32, -200.0 * slope * i as f32)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        world.spawn((
+            Spline(),
+            SplineBvh(SpanBvh::new(&curve)),
+            SplineCurve(Some(curve)),
+            SplineThickness(ThicknessProfile::constant(2.0)),
+            material,
+        ));
+
+        let normal = Vector2::new(slope, 1.0).normalize();
+        let start = normal * (RIDER_RADIUS + 1.0);
+        let body = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity, acceleration: Vector2::zeros() },
+                material,
+                Gravitate(),
+                Collider::new(),
+                SplineMemory { spline_intersections: HashMap::new() },
+            ))
+            .id();
+        (app, body)
+    }
 
+    /// Runs `seconds` of fixed steps of `timestep`.
+    fn simulate(world: &mut World, timestep: f32, seconds: f32) {
+        for _ in 0..(seconds / timestep).round() as usize {
+            world.resource_mut::<Time<Fixed>>().advance_by(Duration::from_secs_f32(timestep));
+            world.run_system_once(reset_collisions).unwrap();
+            run_physics(world);
+        }
+    }
 
-            let vel = pos.0 - verlet.position_old;
-            let old_offset = ((verlet.position_old + vel - overground).transpose() * normal).x + 0.02;
+    #[test]
+    fn body_keeps_sliding_down_an_icy_slope() {
+        let (mut app, body) = body_on_slope(0.2, PhysicsMaterial::ICE, Vector2::zeros());
+        let world = app.world_mut();
+        simulate(world, 0.002, 0.5);
+        let halfway = world.get::<VerletObject>(body).unwrap().velocity;
+        simulate(world, 0.002, 0.5);
+        let velocity = world.get::<VerletObject>(body).unwrap().velocity;
+        let position = world.get::<Position>(body).unwrap().0;
+        assert!(position.x > 30.0, "{position:?}");
+        assert!(velocity.x > 1.5 * halfway.x && halfway.x > 0.0, "{halfway:?} {velocity:?}");
+    }
 
-            if old_offset < 0.0{
-               verlet.position_old -= normal * old_offset;
-            }
+    #[test]
+    fn body_stops_in_mud() {
+        let tangent = Vector2::new(1.0f32, -0.2).normalize();
+        let (mut app, body) = body_on_slope(0.2, PhysicsMaterial::MUD, 200.0 * tangent);
+        let world = app.world_mut();
+        simulate(world, 0.002, 1.0);
+        let position = world.get::<Position>(body).unwrap().0;
+        simulate(world, 0.002, 0.5);
+        let velocity = world.get::<VerletObject>(body).unwrap().velocity;
+        // no faster than gravity gets it in a step, before the contact stops it again
+        assert!(velocity.norm() <= PhysicsConfig::default().gravity.norm() * 0.002 + 1e-3, "{velocity:?}");
+        assert!((world.get::<Position>(body).unwrap().0 - position).norm() < 0.5, "{position:?}");
+    }
 
-            if ((pos.0 - point).transpose() * normal).x < 60.0 + line_widht {
+    /// Velocity of a body of `restitution` hitting a flat rail of the same
+    /// restitution straight down at `speed`.
+    fn bounce(restitution: f32, speed: f32) -> Vector2<f32> {
+        let mut world = World::new();
+        world.insert_resource(PhysicsConfig::default());
+        let material = PhysicsMaterial { restitution, ..Default::default() };
+        let points: Vec<_> = (-5..=5).map(|i| Vector2::new(200.0 * i as f32, 0.0)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        world.spawn((
+            Spline(),
+            SplineBvh(SpanBvh::new(&curve)),
+            SplineCurve(Some(curve)),
+            SplineThickness(ThicknessProfile::constant(2.0)),
+            material,
+        ));
+        let (from, to) = (Vector2::new(0.0, RIDER_RADIUS + 3.0), Vector2::new(0.0, RIDER_RADIUS - 3.0));
+        let body = world
+            .spawn((
+                Position(to),
+                VerletObject { position_old: from, velocity: Vector2::new(0.0, -speed), acceleration: Vector2::zeros() },
+                material,
+                Collider::new(),
+                SplineMemory { spline_intersections: HashMap::new() },
+            ))
+            .id();
+        world.run_system_once(collide).unwrap();
+        world.get::<VerletObject>(body).unwrap().velocity
+    }
 
-                pos. 0 = overground;
+    #[test]
+    fn no_bounce_without_restitution() {
+        let velocity = bounce(0.0, 500.0);
+        assert!(velocity.norm() < 1e-3, "{velocity:?}");
+    }
 
-                collider.collisions.push(Collision{other: entity, point: point, normal: normal});
+    #[test]
+    fn full_restitution_keeps_the_normal_speed() {
+        let velocity = bounce(1.0, 500.0);
+        assert!((velocity - Vector2::new(0.0, 500.0)).norm() < 1e-2, "{velocity:?}");
+    }
 
-            }
-            // if(underground){
-            //     println!("underground");
-            // }
-            // if(underground){
-            //
-            //     println!("______________");
-            //     println!("{}", normal);
-            //     println!("{}", normal_old);
-            //     println!("{}", angle);
-            // }
-            // if(underground){
-            //     pos.0.y = pos.0.y + 500.0;
-            //     verlet.position_old.y = pos.0.y;
-            //     verlet.position_old.x = pos.0.x;
-            // }
+    #[test]
+    fn massless_body_counts_as_infinitely_heavy() {
+        let mut world = World::new();
+        world.insert_resource(PhysicsConfig::default());
+        world.insert_resource(PhysicsStep { dt: 0.002 });
+        let still = world
+            .spawn((
+                Position(Vector2::zeros()),
+                VerletObject { position_old: Vector2::zeros(), velocity: Vector2::zeros(), acceleration: Vector2::zeros() },
+                Mass(0.0),
+            ))
+            .id();
+        let start = Vector2::new(RIDER_RADIUS, 0.0);
+        let moving = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity: Vector2::new(-100.0, 0.0), acceleration: Vector2::zeros() },
+            ))
+            .id();
+
+        world.run_system_once(collide_bodies).unwrap();
+
+        assert_eq!(world.get::<Position>(still).unwrap().0, Vector2::zeros());
+        assert_eq!(world.get::<VerletObject>(still).unwrap().velocity, Vector2::zeros());
+        let position = world.get::<Position>(moving).unwrap().0;
+        let velocity = world.get::<VerletObject>(moving).unwrap().velocity;
+        assert!((position - Vector2::new(2.0 * RIDER_RADIUS, 0.0)).norm() < 1e-3, "{position:?}");
+        assert!(velocity.x.abs() < 1e-3, "{velocity:?}");
+    }
+
+    /// Gravity of the default configuration.
+    fn gravity() -> Vector2<f32> {
+        PhysicsConfig::default().gravity
+    }
 
+    #[test]
+    fn fast_grinder_flies_off_a_crest() {
+        // a half circle bulging upwards, its normals pointing inwards
+        let curve = BSpline::circular_arc(Vector2::zeros(), 500.0, 0.0, std::f32::consts::PI);
+        let profile = ThicknessProfile::constant(2.0);
+        let thick = ThickSpline::new(&curve, &profile);
+        let (start, end) = curve.domain();
+        let top = 0.5 * (start + end);
+        let position = curve.eval(top) + Vector2::new(0.0, RIDER_RADIUS + 1.0);
+        let ride = |speed: f32| {
+            let grinding = Grinding { spline: Entity::PLACEHOLDER, param: top, speed };
+            grind_step(&thick, RIDER_RADIUS, &grinding, position, gravity(), 0.002)
+        };
+
+        let slow = ride(100.0).unwrap();
+        assert!((slow.position - slow.contact).dot(&Vector2::y()) > 0.0, "{slow:?}");
+        // gravity can't hold it on the outer path at this speed
+        assert!(ride(1000.0).is_none());
+    }
+
+    #[test]
+    fn grinder_advances_by_arc_length() {
+        // straight, but with the parameter running faster towards the end
+        let points = vec![Vector2::new(0.0, 0.0), Vector2::new(10.0, 0.0), Vector2::new(300.0, 0.0), Vector2::new(1000.0, 0.0)];
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let profile = ThicknessProfile::constant(2.0);
+        let thick = ThickSpline::new(&curve, &profile);
+        for param in [0.1, 0.3, 0.5, 0.8] {
+            let position = curve.eval(param) + Vector2::new(0.0, RIDER_RADIUS + 1.0);
+            let grinding = Grinding { spline: Entity::PLACEHOLDER, param, speed: 500.0 };
+            let next = grind_step(&thick, RIDER_RADIUS, &grinding, position, Vector2::zeros(), 0.01).unwrap();
+            let moved = next.position - position;
+            assert!((moved - Vector2::new(5.0, 0.0)).norm() < 1e-2, "{param} {next:?}");
+            assert_eq!(next.speed, 500.0);
         }
+    }
+
+    #[test]
+    fn grinder_comes_off_the_ends_of_an_open_spline() {
+        let points = (0..5).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let profile = ThicknessProfile::constant(2.0);
+        let thick = ThickSpline::new(&curve, &profile);
+        let (start, end) = curve.domain();
+        let lift = Vector2::new(0.0, RIDER_RADIUS + 1.0);
+        let ride = |param: f32, speed: f32| {
+            let grinding = Grinding { spline: Entity::PLACEHOLDER, param, speed };
+            grind_step(&thick, RIDER_RADIUS, &grinding, curve.eval(param) + lift, gravity(), 0.01)
+        };
+
+        assert!(ride(end - 1e-3, 300.0).is_none());
+        assert!(ride(start + 1e-3, -300.0).is_none());
+        // but rides on where there is spline left
+        assert!(ride(end - 1e-3, -300.0).is_some());
+        assert!(ride(start + 1e-3, 300.0).is_some());
+    }
+
+    #[test]
+    fn grinding_sends_start_and_end_events() {
+        let mut world = World::new();
+        world.insert_resource(PhysicsStep { dt: 0.002 });
+        world.init_resource::<Events<GrindStarted>>();
+        world.init_resource::<Events<GrindEnded>>();
+        world.init_resource::<Events<StopGrinding>>();
+
+        let points = (-5..=5).map(|i| Vector2::new(200.0 * i as f32, 0.0)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let spline = world
+            .spawn((
+                Spline(),
+                Grindable,
+                SplineBvh(SpanBvh::new(&curve)),
+                SplineCurve(Some(curve)),
+                SplineThickness(ThicknessProfile::constant(2.0)),
+            ))
+            .id();
+        let start = Vector2::new(0.0, RIDER_RADIUS + 1.0);
+        let mut collider = Collider::new();
+        collider.add_collision(Collision { other: spline, point: Vector2::new(0.0, 1.0), normal: Vector2::y() });
+        let body = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity: Vector2::new(200.0, 0.0), acceleration: Vector2::zeros() },
+                collider,
+                Grinder::default(),
+            ))
+            .id();
+
+        world.run_system_once(start_grinding).unwrap();
+        let grinding = *world.get::<Grinding>(body).unwrap();
+        assert_eq!(grinding.spline, spline);
+        assert!((grinding.speed - 200.0).abs() < 1e-3, "{grinding:?}");
+        let started: Vec<_> = world.resource::<Events<GrindStarted>>().iter_current_update_events().cloned().collect();
+        assert_eq!(started.len(), 1);
+        assert_eq!((started[0].body, started[0].spline), (body, spline));
+
+        world.run_system_once(grind).unwrap();
+        assert!(world.get::<Grinding>(body).is_some());
+        assert!(world.resource::<Events<GrindEnded>>().is_empty());
+
+        world.send_event(StopGrinding { body });
+        world.run_system_once(grind).unwrap();
+        assert!(world.get::<Grinding>(body).is_none());
+        let ended: Vec<_> = world.resource::<Events<GrindEnded>>().iter_current_update_events().cloned().collect();
+        assert_eq!(ended.len(), 1);
+        assert_eq!((ended[0].body, ended[0].spline), (body, spline));
+    }
+
+    /// Spawns a flat rail along the x axis and one rising across it at the
+    /// origin, with their junctions.
+    fn crossing_rails(world: &mut World) -> (Entity, Entity) {
+        let flat = BSpline::clamped_uniform((-5..=5).map(|i| Vector2::new(200.0 * i as f32, 0.0)).collect(), 3).unwrap();
+        let rising = BSpline::clamped_uniform((-5..=5).map(|i| Vector2::new(200.0 * i as f32, 100.0 * i as f32)).collect(), 3).unwrap();
+        let crossings = intersections(&flat, &rising);
+        assert_eq!(crossings.len(), 1);
+        let crossing = crossings[0];
+        let sine = flat.tangent(crossing.param_a).perp(&rising.tangent(crossing.param_b)).abs();
+
+        let mut spawn = |curve: BSpline| {
+            world
+                .spawn((
+                    Spline(),
+                    Grindable,
+                    SplineBvh(SpanBvh::new(&curve)),
+                    SplineCurve(Some(curve)),
+                    SplineThickness(ThicknessProfile::constant(2.0)),
+                ))
+                .id()
+        };
+        let (a, b) = (spawn(flat), spawn(rising));
+        world.entity_mut(a).insert(SplineJunctions(vec![Junction { other: b, param: crossing.param_a, other_param: crossing.param_b, point: crossing.point, sine }]));
+        world.entity_mut(b).insert(SplineJunctions(vec![Junction { other: a, param: crossing.param_b, other_param: crossing.param_a, point: crossing.point, sine }]));
+        (a, b)
+    }
+
+    /// Where a body grinding the flat one of [`crossing_rails`] towards the
+    /// crossing is after `steps` substeps, and what it grinds then.
+    fn grind_across(switch_rails: bool, steps: usize) -> (Entity, Entity, Vector2<f32>, Grinding) {
+        let mut world = World::new();
+        world.insert_resource(PhysicsStep { dt: 0.002 });
+        world.init_resource::<Events<GrindStarted>>();
+        world.init_resource::<Events<GrindEnded>>();
+        world.init_resource::<Events<StopGrinding>>();
+        let (flat, rising) = crossing_rails(&mut world);
+
+        let param = world.get::<SplineCurve>(flat).unwrap().0.as_ref().unwrap().closest_point(Vector2::new(-20.0, 0.0)).param;
+        let start = Vector2::new(-20.0, RIDER_RADIUS + 1.0);
+        let body = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity: Vector2::new(500.0, 0.0), acceleration: Vector2::zeros() },
+                Collider::new(),
+                Grinder { switch_rails, ..Default::default() },
+                Grinding { spline: flat, param, speed: 500.0 },
+            ))
+            .id();
+        for _ in 0..steps {
+            world.run_system_once(grind).unwrap();
+        }
+        let position = world.get::<Position>(body).unwrap().0;
+        (flat, rising, position, *world.get::<Grinding>(body).unwrap())
+    }
+
+    #[test]
+    fn grinder_switches_rails_at_a_crossing() {
+        let (_, rising, position, grinding) = grind_across(true, 100);
+        assert_eq!(grinding.spline, rising);
+        // what there was of the speed along the new rail
+        let along = 500.0 * Vector2::new(2.0f32, 1.0).normalize().x;
+        assert!((grinding.speed - along).abs() < 1.0, "{grinding:?}");
+        assert!(position.y > 0.5 * position.x + RIDER_RADIUS, "{position:?}");
+    }
+
+    #[test]
+    fn grinder_rides_through_a_crossing() {
+        let (flat, _, position, grinding) = grind_across(false, 100);
+        assert_eq!(grinding.spline, flat);
+        assert!((position - Vector2::new(80.0, RIDER_RADIUS + 1.0)).norm() < 1.0, "{position:?}");
+    }
+
+    /// Where a body rolling along the flat one of [`crossing_rails`] across
+    /// the crossing is after a second.
+    fn roll_across(switch_rails: bool) -> Vector2<f32> {
+        let mut app = App::new();
+        app.add_plugins(PhysicsPlugin);
+        app.init_resource::<Time<Fixed>>();
+        let world = app.world_mut();
+        let (flat, rising) = crossing_rails(world);
+        for spline in [flat, rising] {
+            world.entity_mut(spline).remove::<Grindable>().insert(PhysicsMaterial::ICE);
+        }
+
+        let start = Vector2::new(-300.0, RIDER_RADIUS + 1.0);
+        let body = world
+            .spawn((
+                Position(start),
+                VerletObject { position_old: start, velocity: Vector2::new(600.0, 0.0), acceleration: Vector2::zeros() },
+                PhysicsMaterial::ICE,
+                Gravitate(),
+                Collider::new(),
+                SplineMemory { spline_intersections: HashMap::new() },
+                Grinder { switch_rails, ..Default::default() },
+            ))
+            .id();
+        simulate(world, 0.002, 1.0);
+        world.get::<Position>(body).unwrap().0
+    }
+
+    #[test]
+    fn body_rolls_through_a_crossing() {
+        let position = roll_across(false);
+        assert!(position.x > 250.0 && (position.y - RIDER_RADIUS).abs() < 2.0, "{position:?}");
+    }
 
+    #[test]
+    fn body_switching_rails_rolls_up_the_other_one() {
+        let position = roll_across(true);
+        assert!(position.y > 0.5 * position.x + RIDER_RADIUS - 2.0 && position.y > RIDER_RADIUS + 20.0, "{position:?}");
     }
 }
@@ -1,14 +1,11 @@
 use std::collections::HashMap;
 use std::f64::consts::PI;
-use bevy::asset::ron::Map;
 use bevy::math::ops::atan2;
 use bevy::prelude::*;
-use bevy::transform;
-use bevy_asset_loader::prelude::*;
-use nalgebra::{Normed, Vector2};
+use nalgebra::Vector2;
 use crate::assets_plugin::{GameState, PlayerAssets};
 use crate::controls_plugin::Follower;
-use crate::physics_plugin::{Collider, Gravitate, SplineMemory, VerletObject};
+use crate::physics_plugin::{Collider, Gravitate, Grinder, Grinding, SplineMemory, StopGrinding, VerletObject};
 use crate::spines_plugin::Position;
 
 pub struct PlayerPlugin;
@@ -17,11 +14,13 @@ impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::InGame), spawn_player);
         app.add_systems(Update, anime_player);
+        app.add_systems(Update, jump_off_rail);
+        app.add_systems(Update, switch_rails);
 
     }
 }
 
-fn spawn_player(mut commands: Commands, mut player_assets: Res<PlayerAssets>) {
+fn spawn_player(mut commands: Commands, player_assets: Res<PlayerAssets>) {
 
     let player = commands.spawn((Position(Vector2::new(100.0, 300.0)),
                                  Transform::from_scale(
@@ -37,13 +36,14 @@ fn spawn_player(mut commands: Commands, mut player_assets: Res<PlayerAssets>) {
                                  SplineMemory{spline_intersections: HashMap::new()},
                                  Gravitate(),
                                  Collider::new(),
-                                 VerletObject { position_old: Vector2::new(100.0, 300.0), acceleration: Vector2::new(0.0, 0.0) }
+                                 Grinder::default(),
+                                 VerletObject { position_old: Vector2::new(100.0, 300.0), velocity: Vector2::new(0.0, 0.0), acceleration: Vector2::new(0.0, 0.0) }
     )).id();
-    let cameraWidth = 2400.0;
+    let camera_width = 2400.0;
     commands.spawn((Camera2d,
                     Projection::from(OrthographicProjection {
                         scaling_mode: bevy::render::camera::ScalingMode::FixedHorizontal {
-                            viewport_width: cameraWidth
+                            viewport_width: camera_width
                         },
                         ..OrthographicProjection::default_2d()
                     }),
@@ -51,17 +51,45 @@ fn spawn_player(mut commands: Commands, mut player_assets: Res<PlayerAssets>) {
 
 }
 
+/// Space jumps off the spline being ground.
+fn jump_off_rail(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut stop: EventWriter<StopGrinding>,
+    query: Query<Entity, (With<Grinding>, With<Sprite>)>,
+){
+    if keys.just_pressed(KeyCode::Space) {
+        for body in &query {
+            stop.write(StopGrinding { body });
+        }
+    }
+}
+
+/// Holding shift takes the other rail where two cross.
+fn switch_rails(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut query: Query<&mut Grinder, With<Sprite>>,
+){
+    for mut grinder in &mut query {
+        grinder.switch_rails = keys.pressed(KeyCode::ShiftLeft);
+    }
+}
+
+/// Speeds along the ground, in units per second, above which the player
+/// runs and below which it stands.
+const RUN_SPEED: f32 = 224.0;
+const STAND_SPEED: f32 = 158.0;
+
 fn cross2d(a: Vector2<f32>, b: Vector2<f32>) -> f32 {
     a.x * b.y - a.y * b.x
 }
 
 fn anime_player(
-    mut query: Query<(&Position, &VerletObject, &mut Sprite, &mut Transform, &Collider)>
+    mut query: Query<(&VerletObject, &mut Sprite, &mut Transform, &Collider)>
 ){
 
-    for (position, verlet_object, mut sprite ,mut transform, collider) in &mut query{
+    for (verlet_object, mut sprite ,mut transform, collider) in &mut query{
 
-        if(!collider.collisions_old.is_empty()){
+        if !collider.collisions_old.is_empty() {
             let normal = collider.collisions_old[collider.collisions_old.len()-1].normal;
             let angle = atan2(normal.y, normal.x);
             let target = Quat::from_rotation_z(angle-PI as f32/2.0);
@@ -71,7 +99,7 @@ fn anime_player(
 
         }
 
-        let speed: Vector2<f32> = position.0 - verlet_object.position_old;
+        let speed: Vector2<f32> = verlet_object.velocity;
 
         let bevy_v = Vec3::new(0.0, 1.0, 0.0);
 
@@ -84,31 +112,56 @@ fn anime_player(
 
         let hor_speed = speed - normal * (speed.transpose() * normal);
 
-        let cross = -1.0 * cross2d(normal, hor_speed);
-        if(collider.collisions_old.is_empty()){
-            let angle = if(hor_speed.norm_squared() > 0.1) {atan2(speed.y, speed.x) + cross.signum() *  PI as f32/ 2.0} else {PI as f32/2.0};
+        let cross = -cross2d(normal, hor_speed);
+        if collider.collisions_old.is_empty() {
+            let angle = if hor_speed.norm() > STAND_SPEED {atan2(speed.y, speed.x) + cross.signum() *  PI as f32/ 2.0} else {PI as f32/2.0};
             let target = Quat::from_rotation_z(angle-PI as f32/2.0);
             transform.rotation = transform.rotation.slerp(target, 0.1);
         }
 
 
-        let val = hor_speed.norm_squared();
+        let val = hor_speed.norm();
         if let Some(atlas) = &mut sprite.texture_atlas {
 
-            if(val >  0.2){
+            if val > RUN_SPEED {
 
                 atlas.index = 1;
             }
-            else if(val <0.1){
+            else if val < STAND_SPEED {
 
                 atlas.index = 0;
             }
         }
 
 
-        if(val > 0.2){
+        if val > RUN_SPEED {
             transform.scale.x = transform.scale.x.abs() * cross.signum();
         }
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+    use super::*;
+
+    #[test]
+    fn holding_shift_switches_rails() {
+        let mut world = World::new();
+        world.init_resource::<ButtonInput<KeyCode>>();
+        let player = world.spawn((Sprite::default(), Grinder::default())).id();
+        let other = world.spawn(Grinder::default()).id();
+        let switching = |world: &World, entity| world.get::<Grinder>(entity).unwrap().switch_rails;
+
+        world.resource_mut::<ButtonInput<KeyCode>>().press(KeyCode::ShiftLeft);
+        world.run_system_once(switch_rails).unwrap();
+        assert!(switching(&world, player));
+        // only the player listens to the keyboard
+        assert!(!switching(&world, other));
+
+        world.resource_mut::<ButtonInput<KeyCode>>().release(KeyCode::ShiftLeft);
+        world.run_system_once(switch_rails).unwrap();
+        assert!(!switching(&world, player));
+    }
+}
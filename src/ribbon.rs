@@ -0,0 +1,245 @@
+//! Triangle strips along thick splines, for drawing each spline as one mesh.
+//!
+//! A ribbon is a row of cross sections of a [`ThickSpline`], each from its
+//! surface on the left to its surface on the right. An interval between two
+//! sections is split while the center line or either edge strays from its chord
+//! by more than the tolerance, so tight bends get many sections and straight
+//! stretches few. Sections that end up on the chord between their neighbours
+//! are dropped again, except at the keys of the thickness profile.
+
+use nalgebra::Vector2;
+
+use crate::offset::{Side, ThickSpline};
+use crate::spline::{distance_to_segment, ArcLengthTable};
+
+/// Intervals per knot span that [`Ribbon::new`] starts from, so bends whose
+/// middle happens to lie on the chord aren't missed.
+const RIBBON_SAMPLES: usize = 2;
+
+/// Subdivisions of a starting interval after which it is used as it is.
+const MAX_RIBBON_DEPTH: usize = 8;
+
+/// Part of the tolerance [`Ribbon::new`] spends on dropping sections that lie
+/// almost on the chord between their neighbours.
+const MERGE_SHARE: f32 = 0.05;
+
+/// Cross sections of a thick spline in order along the curve. Closed splines
+/// end with a copy of their first section.
+#[derive(Debug, Clone, Default)]
+pub struct Ribbon {
+    pub params: Vec<f32>,
+    pub left: Vec<Vector2<f32>>,
+    pub right: Vec<Vector2<f32>>,
+    /// Distance along the center line to each section, for texture coordinates.
+    pub lengths: Vec<f32>,
+}
+
+impl Ribbon {
+    /// Sections of `thick` no farther than `tolerance` from the exact curve and
+    /// surfaces in between, with one at every key of its thickness profile.
+    /// `arc_length` has to be the table of its curve.
+    pub fn new(thick: &ThickSpline, arc_length: &ArcLengthTable, tolerance: f32) -> Self {
+        let curve = thick.curve;
+        let (start, end) = curve.domain();
+        let keys: Vec<f32> = thick
+            .profile
+            .keys()
+            .iter()
+            .map(|&(fraction, _)| start + fraction.rem_euclid(1.0) * (end - start))
+            .filter(|&u| start < u && u < end)
+            .collect();
+        let mut breaks: Vec<f32> = curve.knots()[curve.degree()..curve.control_points().len() + 1].to_vec();
+        breaks.extend(&keys);
+        breaks.sort_by(f32::total_cmp);
+        breaks.dedup();
+
+        let mut ribbon = Ribbon::default();
+        ribbon.push(thick, arc_length, start);
+        let subdivide_tolerance = (1.0 - MERGE_SHARE) * tolerance;
+        for w in breaks.windows(2) {
+            let step = (w[1] - w[0]) / RIBBON_SAMPLES as f32;
+            for i in 0..RIBBON_SAMPLES {
+                let u0 = w[0] + i as f32 * step;
+                let u1 = if i + 1 == RIBBON_SAMPLES { w[1] } else { u0 + step };
+                ribbon.subdivide(thick, arc_length, u0, u1, subdivide_tolerance, 0);
+            }
+        }
+        ribbon.merge(thick, &keys, MERGE_SHARE * tolerance);
+        ribbon
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// Appends the sections in `(u0, u1]`, the one at `u0` being the last so far.
+    fn subdivide(&mut self, thick: &ThickSpline, arc_length: &ArcLengthTable, u0: f32, u1: f32, tolerance: f32, depth: usize) {
+        let mid = 0.5 * (u0 + u1);
+        let i = self.len() - 1;
+        let start = [thick.curve.eval(u0), self.left[i], self.right[i]];
+        let (middle, end) = (section(thick, mid), section(thick, u1));
+        let flat = (0..3).all(|k| distance_to_segment(middle[k], start[k], end[k]) <= tolerance);
+        if flat || depth >= MAX_RIBBON_DEPTH {
+            self.push(thick, arc_length, u1);
+            return;
+        }
+        self.subdivide(thick, arc_length, u0, mid, tolerance, depth + 1);
+        self.subdivide(thick, arc_length, mid, u1, tolerance, depth + 1);
+    }
+
+    /// Drops the sections, other than those at `keys`, that lie within
+    /// `tolerance` of the chord between the sections kept on either side, as
+    /// the starting intervals put some even on straight stretches.
+    fn merge(&mut self, thick: &ThickSpline, keys: &[f32], tolerance: f32) {
+        let rails = |ribbon: &Ribbon, i: usize| [thick.curve.eval(ribbon.params[i]), ribbon.left[i], ribbon.right[i]];
+        let mut kept = vec![0];
+        for k in 1..self.len().saturating_sub(1) {
+            let anchor = kept[kept.len() - 1];
+            let (from, to) = (rails(self, anchor), rails(self, k + 1));
+            let on_chord = (anchor + 1..=k).all(|j| {
+                let between = rails(self, j);
+                (0..3).all(|r| distance_to_segment(between[r], from[r], to[r]) <= tolerance)
+            });
+            if !on_chord || keys.contains(&self.params[k]) {
+                kept.push(k);
+            }
+        }
+        kept.push(self.len() - 1);
+
+        let mut keep = vec![false; self.len()];
+        for i in kept {
+            keep[i] = true;
+        }
+
+        retain_kept(&mut self.params, &keep);
+        retain_kept(&mut self.left, &keep);
+        retain_kept(&mut self.right, &keep);
+        retain_kept(&mut self.lengths, &keep);
+    }
+
+    fn push(&mut self, thick: &ThickSpline, arc_length: &ArcLengthTable, u: f32) {
+        let [_, left, right] = section(thick, u);
+        // the end of a loop is all the way around, not back at its start
+        let length = if u >= thick.curve.domain().1 {
+            arc_length.total_length()
+        } else {
+            arc_length.length_at_param(u)
+        };
+        self.params.push(u);
+        self.left.push(left);
+        self.right.push(right);
+        self.lengths.push(length);
+    }
+}
+
+fn retain_kept<T>(values: &mut Vec<T>, keep: &[bool]) {
+    let mut kept = keep.iter();
+    values.retain(|_| *kept.next().expect("one flag per section"));
+}
+
+/// Center, left and right point of the section at `u`. Where the thickness
+/// changes too fast for the surface to touch the disc at `u`, the disc's own
+/// edge stands in.
+fn section(thick: &ThickSpline, u: f32) -> [Vector2<f32>; 3] {
+    let frame = thick.curve.frame(u);
+    let (w, _) = thick.half_width(u);
+    [
+        frame.point,
+        thick.offset(u, Side::Left).unwrap_or(frame.point + w * frame.normal),
+        thick.offset(u, Side::Right).unwrap_or(frame.point - w * frame.normal),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::offset::ThicknessProfile;
+    use crate::spline::BSpline;
+
+    fn ribbon(curve: &BSpline, profile: &ThicknessProfile, tolerance: f32) -> Ribbon {
+        Ribbon::new(&ThickSpline::new(curve, profile), &ArcLengthTable::new(curve, 16), tolerance)
+    }
+
+    /// Largest distance of the center line and the surfaces between two
+    /// sections from the chords between them.
+    fn worst_deviation(curve: &BSpline, profile: &ThicknessProfile, ribbon: &Ribbon) -> f32 {
+        let thick = ThickSpline::new(curve, profile);
+        let mut worst: f32 = 0.0;
+        for i in 0..ribbon.len() - 1 {
+            let (from, to) = (section(&thick, ribbon.params[i]), section(&thick, ribbon.params[i + 1]));
+            for k in 1..32 {
+                let u = ribbon.params[i] + (ribbon.params[i + 1] - ribbon.params[i]) * k as f32 / 32.0;
+                let between = section(&thick, u);
+                for r in 0..3 {
+                    worst = worst.max(distance_to_segment(between[r], from[r], to[r]));
+                }
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn straight_curve_gets_two_sections() {
+        let points = (0..8).map(|i| Vector2::new(100.0 * i as f32, 50.0 * i as f32)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let ribbon = ribbon(&curve, &ThicknessProfile::constant(10.0), 0.5);
+        let (start, end) = curve.domain();
+        assert_eq!(ribbon.params, vec![start, end], "{ribbon:?}");
+    }
+
+    #[test]
+    fn sections_get_denser_as_the_curve_bends_tighter() {
+        let profile = ThicknessProfile::constant(10.0);
+        let density = |radius: f32| {
+            // a quarter circle
+            let curve = BSpline::circular_arc(Vector2::zeros(), radius, 0.0, std::f32::consts::FRAC_PI_2);
+            let ribbon = ribbon(&curve, &profile, 0.5);
+            assert!(worst_deviation(&curve, &profile, &ribbon) <= 0.5, "{radius} {ribbon:?}");
+            (ribbon.len() - 1) as f32 / ribbon.lengths[ribbon.len() - 1]
+        };
+        let densities = [2000.0, 500.0, 100.0, 30.0].map(density);
+        assert!(densities.windows(2).all(|w| w[0] < w[1]), "{densities:?}");
+    }
+
+    #[test]
+    fn lengths_are_distances_along_the_curve() {
+        let points = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(200.0, 300.0),
+            Vector2::new(400.0, -100.0),
+            Vector2::new(700.0, 50.0),
+        ];
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let ribbon = ribbon(&curve, &ThicknessProfile::constant(10.0), 0.5);
+        for (&u, &length) in ribbon.params.iter().zip(&ribbon.lengths) {
+            // the length of a fine polyline along the curve up to u
+            let exact: f32 = (0..4000)
+                .map(|k| (curve.eval(u * (k + 1) as f32 / 4000.0) - curve.eval(u * k as f32 / 4000.0)).norm())
+                .sum();
+            assert!((length - exact).abs() < 1e-2 * exact.max(1.0), "{u} {length} {exact}");
+        }
+    }
+
+    #[test]
+    fn every_width_change_gets_one_section() {
+        let points = (0..8).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let keys = [(0.25, 10.0), (0.5, 30.0), (0.75, 30.0), (0.9, 4.0)];
+        let profile = ThicknessProfile::new(keys.to_vec()).unwrap();
+        let ribbon = ribbon(&curve, &profile, 0.5);
+        let (start, end) = curve.domain();
+        let param = |fraction: f32| start + fraction * (end - start);
+        for (fraction, _) in keys {
+            let sections = ribbon.params.iter().filter(|&&u| u == param(fraction)).count();
+            assert_eq!(sections, 1, "{fraction} {:?}", ribbon.params);
+        }
+        assert!(worst_deviation(&curve, &profile, &ribbon) <= 0.5, "{ribbon:?}");
+        // between the two keys of the same width the ribbon is straight
+        let between = ribbon.params.iter().filter(|&&u| param(0.5) < u && u < param(0.75)).count();
+        assert_eq!(between, 0, "{:?}", ribbon.params);
+    }
+}
@@ -1,14 +1,28 @@
-use bevy::math::ops::{atan2, sin};
-use bevy::{
-    dev_tools::fps_overlay::{FpsOverlayConfig, FpsOverlayPlugin},
-    prelude::*,
-    text::FontSmoothing,
-};
-use bevy::render::render_resource::encase::private::RuntimeSizedArray;
-use nalgebra::{Vector, Vector2, Vector3};
-use rand::Rng;
-use roots::{find_root_newton_raphson, SimpleConvergency};
-use crate::physics_plugin::{PhySched, SplineColliderInfo, SplineMemory, VerletObject};
+use crate::physics_plugin::Grindable;
+use bevy::asset::RenderAssetUsages;
+use bevy::ecs::query::{QueryData, QueryFilter};
+use bevy::ecs::relationship::{OrderedRelationshipSourceCollection, RelationshipTarget};
+use bevy::ecs::system::SystemParam;
+use bevy::math::ops::sin;
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+use nalgebra::Vector2;
+use spline_grind::bvh::{CastHit, SpanBvh};
+use spline_grind::fill::{outline, triangulate};
+use spline_grind::fit::fit_stroke;
+use spline_grind::intersect::{intersections, self_intersections, self_intersections_within, Intersection};
+use spline_grind::kinds::{bspline_to_bezier, from_bezier, to_bezier, ControlPolygon, CurveKind};
+use spline_grind::offset::{ThickSpline, ThicknessProfile};
+use spline_grind::ribbon::Ribbon;
+use spline_grind::spline::{Aabb, ArcLengthTable, BSpline, MAX_DEGREE};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// Distance up to which a `Pusher` moves control points.
+pub const PUSH_RADIUS: f32 = 190.0;
+
+/// Distance from which a control point jumps onto its `Target`.
+const SNAP_DISTANCE: f32 = 0.1;
 
 pub struct SplinePlugin;
 
@@ -16,10 +30,39 @@ pub struct SplinePlugin;
 pub struct SplineSet;
 impl Plugin for SplinePlugin {
     fn build(&self, app: &mut App) {
-
-        app.add_systems(Update, (render_spline, render_gradient, update_position ));
-        app.add_systems(FixedUpdate, (update_old_pos, move_points, go_to_target,  push, ).chain().in_set(SplineSet));
-        app.add_systems(PostUpdate, (follow_mouse.after(TransformSystem::TransformPropagate)));
+        app.add_systems(
+            Update,
+            (
+                update_arc_length.before(update_ribbons),
+                update_ribbons,
+                update_fills,
+                update_position,
+                draw_splines,
+            ),
+        );
+        app.add_event::<InsertKnot>();
+        app.add_event::<RemoveKnot>();
+        app.add_event::<RemoveRedundantKnots>();
+        app.add_event::<ConvertSpline>();
+        app.add_systems(
+            FixedUpdate,
+            (
+                update_old_pos,
+                move_points,
+                go_to_target,
+                push,
+                refine_under_pusher,
+                coarsen_settled,
+                edit_knots,
+                convert_splines,
+                update_curve,
+                update_bvh,
+                update_junctions,
+            )
+                .chain()
+                .in_set(SplineSet),
+        );
+        app.add_systems(PostUpdate, follow_mouse.after(TransformSystem::TransformPropagate));
     }
 }
 
@@ -39,21 +82,282 @@ pub struct FollowMouse();
 pub struct Target(pub Vector2<f32>);
 
 #[derive(Component)]
-pub struct Movable{
-    pub default_position: Vector2<f32>
+pub struct Movable {
+    pub default_position: Vector2<f32>,
 }
 
 #[derive(Component)]
 pub struct Pusher();
 
+/// Makes a `Pusher` insert knots into the splines it pushes until no knot span
+/// under it is longer than `max_span_length`, so deformation has more detail.
+/// Once the pusher has left and the control points are back at their `Target`,
+/// the knots that aren't needed anymore are removed again.
+#[derive(Component)]
+pub struct RefineSplines {
+    pub max_span_length: f32,
+}
+
+/// A spline a `RefineSplines` pusher inserted knots into since it last settled.
+#[derive(Component)]
+struct Refined {
+    /// The inserted knots, in the order they were inserted.
+    knots: Vec<f32>,
+}
+
+/// How far removing the refined knots of a settled spline may move it.
+const COARSEN_TOLERANCE: f32 = 0.01;
+
+/// Lets a `FollowMouse` entity draw splines: while the left mouse button is
+/// held its positions are recorded, and on release a spline is fitted to them
+/// that no recorded point is farther than `tolerance` from, unless the stroke
+/// has more detail than a spline with few control points can follow, see
+/// [`fit_stroke`]. The new spline is drawn as a [`SplineRibbon`] of `material`
+/// and can be ground on, see [`spawn_spline`].
+#[derive(Component)]
+#[require(Stroke)]
+pub struct DrawSplines {
+    pub tolerance: f32,
+    pub material: Handle<ColorMaterial>,
+}
+
+/// Positions recorded so far by a `DrawSplines` entity.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Stroke(pub Vec<Vector2<f32>>);
+
+/// Distance the mouse has to move before a `Stroke` records another position.
+const STROKE_SPACING: f32 = 2.0;
 
 #[derive(Component)]
+#[require(
+    SplineKind,
+    SplineDegree,
+    SplineThickness,
+    SplineCurve,
+    SplineCrossings,
+    SplineArcLength,
+    SplineBvh,
+    SplineJunctions
+)]
 pub struct Spline();
 
+/// Where other splines cross a `Spline`, found with [`intersections`] and
+/// ordered along its curve. Riders pass through the other spline there, or
+/// switch onto it, see `Grinder::switch_rails`.
+#[derive(Component, Debug, Clone, Default)]
+pub struct SplineJunctions(pub Vec<Junction>);
+
+/// A point where `other` crosses a spline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Junction {
+    pub other: Entity,
+    /// Parameter of the crossing on the spline's curve.
+    pub param: f32,
+    /// Parameter of the crossing on the curve of `other`.
+    pub other_param: f32,
+    pub point: Vector2<f32>,
+    /// Sine of the angle the curves cross at, 0 where they run along each other.
+    pub sine: f32,
+}
+
+/// How the control points of a `Spline` describe its curve. Every kind is built
+/// into a [`BSpline`], so evaluation, collision and rendering work the same for
+/// all of them. See [`ConvertSpline`] for switching kinds.
+#[derive(Component, Debug, Clone, Copy, Default, Deref)]
+pub struct SplineKind(pub CurveKind);
+
+/// Polynomial degree of a B-spline `Spline`'s curve, 1 for straight segments up
+/// to [`MAX_DEGREE`]. Splines are cubic unless told otherwise, other kinds
+/// always are. The curve is built with at most one less than the number of
+/// control points, so a spline of three points is at most quadratic until
+/// more are added; the component keeps the degree asked for.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SplineDegree(pub usize);
+
+impl Default for SplineDegree {
+    fn default() -> Self {
+        SplineDegree(3)
+    }
+}
+
+/// Knot vector of a B-spline `Spline`, `degree + 1` longer than its control
+/// points. Without it, or while it does not fit the control points, knots are
+/// clamped and uniformly spaced.
+#[derive(Component, Debug, Clone)]
+pub struct SplineKnots(pub Vec<f32>);
+
+/// Curve of a `Spline`, rebuilt in [`SplineSet`] whenever one of its control
+/// points moves or the spline itself changes, so systems can share it instead of
+/// each building their own. `None` while there are too few control points.
+#[derive(Component, Debug, Clone, Default, Deref)]
+pub struct SplineCurve(pub Option<BSpline>);
+
+/// Where a `Spline`'s curve crosses itself, see [`self_intersections`]. Kept
+/// with its [`SplineCurve`], only searching again where the curve moved, so
+/// deformations can be checked against it cheaply, see `push`.
+#[derive(Component, Debug, Clone, Default)]
+pub struct SplineCrossings(pub Vec<Intersection>);
+
+/// Arc length table of a `Spline`'s curve, rebuilt whenever its control points
+/// move. Converts between curve parameters and distances along the spline.
+#[derive(Component, Debug, Clone, Default, Deref)]
+pub struct SplineArcLength(pub ArcLengthTable);
+
+/// Bounding volume hierarchy over the knot spans of a `Spline`'s curve, refitted
+/// every fixed step to the control points that moved.
+#[derive(Component, Debug, Clone, Default, Deref)]
+pub struct SplineBvh(pub SpanBvh);
+
+/// Thickness of a `Spline` along its curve. Collision and rendering both go by
+/// the surface of the thick spline, see [`ThickSpline`]. Splines are 10 units
+/// thick unless told otherwise.
+#[derive(Component, Debug, Clone, Deref)]
+pub struct SplineThickness(pub ThicknessProfile);
+
+impl Default for SplineThickness {
+    fn default() -> Self {
+        SplineThickness(ThicknessProfile::constant(10.0))
+    }
+}
+
+/// Draws a `Spline` as a single triangle strip mesh as wide as its thickness,
+/// rebuilt whenever the curve changes. No edge of the mesh strays farther than
+/// `tolerance` from the exact surface, see [`Ribbon`], and the texture repeats
+/// every `texture_length` along the curve.
+#[derive(Component, Debug, Clone)]
+pub struct SplineRibbon {
+    pub material: Handle<ColorMaterial>,
+    pub tolerance: f32,
+    pub texture_length: f32,
+}
+
+impl SplineRibbon {
+    pub fn new(material: Handle<ColorMaterial>) -> Self {
+        Self {
+            material,
+            tolerance: 0.5,
+            texture_length: 100.0,
+        }
+    }
+}
+
+/// Fills the area a `Spline` encloses with `material`: the inside of a loop, or
+/// below an open spline down to its hidden control points, in order from the
+/// spline's end back to its start. Without hidden control points the area goes
+/// straight down to `floor`. The mesh lives on a [`FillOf`] entity behind the
+/// spline and has its texture repeat every `texture_size` in either direction.
+/// It is rebuilt when the curve changes, but at most every `rebuild_interval`
+/// seconds while the curve keeps changing, as triangulating a long outline is
+/// slow, and once more when it stops.
+#[derive(Component, Debug, Clone)]
+pub struct SplineFill {
+    pub material: Handle<ColorMaterial>,
+    pub floor: f32,
+    pub tolerance: f32,
+    pub texture_size: f32,
+    pub rebuild_interval: f32,
+}
+
+impl SplineFill {
+    pub fn new(material: Handle<ColorMaterial>, floor: f32) -> Self {
+        Self {
+            material,
+            floor,
+            tolerance: 1.0,
+            texture_size: 100.0,
+            rebuild_interval: 0.1,
+        }
+    }
+}
+
+/// When the [`SplineFill`] mesh of a spline was last built, in elapsed
+/// seconds, and whether the spline has changed since.
+#[derive(Component, Debug, Clone, Copy)]
+struct FillBuild {
+    built_at: f32,
+    stale: bool,
+}
+
+/// Depth of [`SplineFill`] meshes, behind the spline's own.
+const FILL_DEPTH: f32 = -1.0;
+
+/// Samples per knot span of the arc length tables.
+const ARC_LENGTH_SAMPLES: usize = 4;
+
+/// Makes a `Spline` a periodic loop through its control points, e.g. for
+/// floating islands. Closed splines ignore `SplineKnots` and their knots can't be
+/// edited.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct SplineClosed;
+
+/// Everything needed to build the curve of a `Spline` entity, see [`build_spline`].
+#[derive(QueryData)]
+pub struct SplineShape {
+    pub entity: Entity,
+    pub controlled_by: &'static ControlledBy,
+    pub degree: &'static SplineDegree,
+    pub knots: Option<&'static SplineKnots>,
+    pub closed: Has<SplineClosed>,
+    pub kind: &'static SplineKind,
+}
+
+/// Inserts the knot `param` into `spline`, adding a control point without
+/// changing the shape of the curve.
+#[derive(Event, Debug, Clone)]
+pub struct InsertKnot {
+    pub spline: Entity,
+    pub param: f32,
+}
+
+/// Turns `spline` into one of `kind` describing the same curve, replacing its
+/// control points. Nothing happens if `kind` can't describe the curve exactly,
+/// e.g. for rational splines, B-splines of a degree above 3, or Bézier curves
+/// with kinks as a Hermite spline.
+#[derive(Event, Debug, Clone)]
+pub struct ConvertSpline {
+    pub spline: Entity,
+    pub kind: CurveKind,
+}
+
+/// Removes one occurrence of the knot `param` from `spline`, and with it a
+/// control point, if that moves the curve by at most `tolerance`.
+#[derive(Event, Debug, Clone)]
+pub struct RemoveKnot {
+    pub spline: Entity,
+    pub param: f32,
+    pub tolerance: f32,
+}
+
+/// Removes every knot of `spline`, and with it a control point, that can go
+/// without moving the curve by more than `tolerance`.
+#[derive(Event, Debug, Clone)]
+pub struct RemoveRedundantKnots {
+    pub spline: Entity,
+    pub tolerance: f32,
+}
+
 #[derive(Component)]
 #[relationship(relationship_target = ControlledBy)]
 pub struct ControlPoint(pub Entity);
 
+/// Weight of a control point. Splines with weighted control points are rational
+/// (NURBS) and can describe exact circles. Control points without one weigh 1.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Weight(pub f32);
+
+/// Tangent of a control point of a Hermite spline, per unit of the parameter.
+/// Control points without one get a Catmull-Rom tangent.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Tangent(pub Vector2<f32>);
+
+/// Per control point part of a spline's curve, see [`build_spline`].
+#[derive(QueryData)]
+pub struct ControlPointShape {
+    pub position: &'static Position,
+    pub weight: Option<&'static Weight>,
+    pub tangent: Option<&'static Tangent>,
+}
+
 #[derive(Component, Deref)]
 #[relationship_target(relationship = ControlPoint)]
 pub struct ControlledBy(Vec<Entity>);
@@ -66,69 +370,154 @@ pub struct HiddenControlPoint(pub Entity);
 #[relationship_target(relationship = HiddenControlPoint)]
 pub struct HiddenControlledBy(Vec<Entity>);
 
+/// The entity drawing the [`SplineFill`] of a spline.
 #[derive(Component)]
-#[relationship(relationship_target = VisualizedBy)]
-pub struct Visualization(pub Entity);
+#[relationship(relationship_target = FilledBy)]
+pub struct FillOf(pub Entity);
 
 #[derive(Component, Deref)]
-#[relationship_target(relationship = Visualization)]
-pub struct VisualizedBy(Vec<Entity>);
-
-#[derive(Component)]
-#[relationship(relationship_target = GradientVisualizedBy)]
-pub struct VisualizationGradient(pub Entity);
-
-#[derive(Component, Deref)]
-#[relationship_target(relationship = VisualizationGradient)]
-pub struct GradientVisualizedBy(Vec<Entity>);
+#[relationship_target(relationship = FillOf, linked_spawn)]
+pub struct FilledBy(Vec<Entity>);
 
+fn update_old_pos(mut query: Query<(&Position, &mut OldPosition)>) {
+    for (new, mut old) in &mut query {
+        old.0 = new.0;
+    }
+}
 
+/// What [`push`] needs of the control points a `Pusher` moves.
+type PushedPoint = (Entity, &'static mut Position, Option<&'static ControlPoint>);
+/// Control points a `Pusher` moves.
+type Pushable = (With<Target>, With<Movable>, Without<Pusher>);
 
-fn update_old_pos(
+fn push(
+    mut points: ParamSet<(Query<PushedPoint, Pushable>, Query<ControlPointShape>)>,
+    pusher_query: Query<&Position, With<Pusher>>,
+    splines: Query<(SplineShape, &SplineCurve, &SplineCrossings), With<Spline>>,
+) {
+    let pushers: Vec<Vector2<f32>> = pusher_query.iter().map(|p| p.0).collect();
+    let mut pushed = HashMap::new();
+    let mut deformed = HashSet::new();
+    for (entity, pos, control_point) in &points.p0() {
+        let next = pushers.iter().fold(pos.0, |p, &pusher| pushed_away(p, pusher));
+        if next != pos.0 {
+            pushed.insert(entity, next);
+            deformed.extend(control_point.map(|c| c.0));
+        }
+    }
 
-    mut query: Query<(&Position, &mut OldPosition)>
-){
+    // a push that folds a spline over itself is undone as a whole
+    let control_points = points.p1();
+    for (shape, curve, crossings) in deformed.into_iter().filter_map(|e| splines.get(e).ok()) {
+        let new = build_moved_spline(&shape, &control_points, &pushed);
+        if new.is_some_and(|new| folds(curve.0.as_ref(), &crossings.0, &new)) {
+            for entity in shape.controlled_by.iter() {
+                pushed.remove(&entity);
+            }
+        }
+    }
 
-    for (new, mut old) in &mut query{
-        old.0 = new.0;
+    let mut pushed_query = points.p0();
+    for (entity, next) in pushed {
+        if let Ok((_, mut pos, _)) = pushed_query.get_mut(entity) {
+            pos.0 = next;
+        }
     }
 }
 
+/// Where a `Pusher` at `pusher` moves a control point at `point`, closer ones
+/// move further towards `PUSH_RADIUS`.
+fn pushed_away(point: Vector2<f32>, pusher: Vector2<f32>) -> Vector2<f32> {
+    let norm = (point - pusher).norm();
+    if norm > PUSH_RADIUS || norm == 0.0 {
+        return point;
+    }
+    let force = 0.5 * (1.0 - norm / PUSH_RADIUS);
+    point * (1.0 - force) + (pusher + (point - pusher) / norm * PUSH_RADIUS) * force
+}
 
-fn push(
-    mut pushed_query: Query<(&mut Position, &Target, &Movable), Without<Pusher>>,
-    pusher_query: Query<(&Position, &Pusher)>
-){
-    for (push_pos, pusher) in &pusher_query{
+/// Distance beyond how far the control points moved that a crossing of a
+/// spline with itself may move and still count as the same crossing.
+const CROSSING_SLACK: f32 = 1.0;
+
+/// Whether `new`, the curve of a spline after a deformation, crosses itself
+/// anywhere `old` didn't, where `crossings` are those of `old`. Only the part
+/// of the curve that moved is searched, and a crossing there is one of the old
+/// ones if it moved no further than the control points did. Folded ground
+/// would have no inside and outside.
+fn folds(old: Option<&BSpline>, crossings: &[Intersection], new: &BSpline) -> bool {
+    match old.map_or(Some((new.domain(), f32::INFINITY)), |old| deformation(old, new)) {
+        Some((range, moved)) => crosses_anew(crossings, new, range, moved),
+        None => false,
+    }
+}
 
-        for (mut pushed_pos, mut target, mov) in &mut pushed_query{
-            let norm = (pushed_pos.0 - push_pos.0).norm();
-            if(norm <= 190.0){
-                let force = 0.5 * (1.0 - norm/190.0);
-                pushed_pos.0 =pushed_pos.0 * (1.0 - force) +  (push_pos.0 + (pushed_pos.0 - push_pos.0).normalize() * 190.0) * (force);
-                // pushed_pos.0 =(push_pos.0 + (pushed_pos.0 - push_pos.0).normalize() * 450.0);
+/// Whether `new` crosses itself within `range` anywhere but near the old
+/// `crossings`, where near is no further than the curve `moved`.
+fn crosses_anew(crossings: &[Intersection], new: &BSpline, range: (f32, f32), moved: f32) -> bool {
+    let within = |u: f32| range.0 <= u && u <= range.1;
+    let mut old: Vec<Vector2<f32>> = crossings
+        .iter()
+        .filter(|x| within(x.param_a) || within(x.param_b))
+        .map(|x| x.point)
+        .collect();
+    self_intersections_within(new, range).iter().any(|crossing| {
+        match old.iter().position(|point| (point - crossing.point).norm() <= moved + CROSSING_SLACK) {
+            Some(i) => {
+                old.swap_remove(i);
+                false
             }
-            // else{
-            //     target.0 = mov.default_position
-            // }
+            None => true,
         }
+    })
+}
 
+/// Parameters of the part of `new` that differs from `old`, and how far its
+/// control points moved at most. The whole curve if they don't share their
+/// knots and weights, `None` if nothing moved.
+fn deformation(old: &BSpline, new: &BSpline) -> Option<((f32, f32), f32)> {
+    let comparable = old.knots() == new.knots() && old.weights() == new.weights() && old.control_points().len() == new.control_points().len();
+    // the first control points of a loop shape its end too
+    if !comparable || new.is_closed() {
+        return Some((new.domain(), f32::INFINITY));
     }
+    let mut range: Option<(f32, f32)> = None;
+    let mut moved = 0.0f32;
+    for (i, (p, q)) in old.control_points().iter().zip(new.control_points()).enumerate() {
+        if p != q {
+            let (start, end) = new.support(i);
+            range = Some(range.map_or((start, end), |(low, high)| (low.min(start), high.max(end))));
+            moved = moved.max((q - p).norm());
+        }
+    }
+    range.map(|range| (range, moved))
 }
 
-fn go_to_target(
-    mut target_query: Query<(&mut Position, &Target)>,
-){
+/// Where `new` crosses itself, from `crossings` of `old` outside the part that
+/// moved and searching again inside it.
+fn moved_crossings(old: &BSpline, crossings: &[Intersection], new: &BSpline) -> Vec<Intersection> {
+    let Some((range, _)) = deformation(old, new) else {
+        return crossings.to_vec();
+    };
+    let within = |u: f32| range.0 <= u && u <= range.1;
+    let mut moved: Vec<Intersection> = crossings.iter().filter(|x| !within(x.param_a) && !within(x.param_b)).copied().collect();
+    moved.extend(self_intersections_within(new, range));
+    moved.sort_by(|x, y| x.param_a.total_cmp(&y.param_a));
+    moved
+}
 
-    for (mut pos, target) in &mut target_query{
-        pos.0 = pos.0 * 0.98 + target.0 * 0.02;
-        // pos.0 =  target.0 ;
+fn go_to_target(mut target_query: Query<(&mut Position, &Target)>) {
+    for (mut pos, target) in &mut target_query {
+        // settled points stay unchanged, so their splines aren't rebuilt. Close
+        // ones snap onto their target, rounding would keep them moving forever
+        let next = pos.0 * 0.98 + target.0 * 0.02;
+        let next = if (next - target.0).norm() <= SNAP_DISTANCE { target.0 } else { next };
+        if pos.0 != next {
+            pos.0 = next;
+        }
     }
-
 }
-fn update_position(
-    mut query: Query<(&Position, &mut Transform)>
-){
+fn update_position(mut query: Query<(&Position, &mut Transform)>) {
     for (pos, mut transform) in query.iter_mut() {
         transform.translation.x = pos.0.x;
         transform.translation.y = pos.0.y;
@@ -137,7 +526,7 @@ fn update_position(
 
 fn follow_mouse(
     camera_query: Single<(&Camera, &GlobalTransform)>,
-    mut follower_query: Query<(&mut Position, &mut FollowMouse)>,
+    mut follower_query: Query<&mut Position, With<FollowMouse>>,
     window: Query<&Window>,
 ) {
     let (camera, camera_transform) = *camera_query;
@@ -154,550 +543,1097 @@ fn follow_mouse(
         return;
     };
 
-    for (mut pos, mut follower_mouse) in follower_query.iter_mut() {
+    for mut pos in follower_query.iter_mut() {
         pos.0.x = world_pos.x;
         pos.0.y = world_pos.y;
     }
-
 }
 
-fn move_points(
-    time: Res<Time>,
-    mut position_query: Query<(&mut Position, &mut Transform, &Moving)>,
-){
-    for (mut pos,  mut trans, mov) in &mut position_query{
-        pos.0.y += 0.2 * sin(time.elapsed_secs()+ pos.0.x);
-        trans.translation.y = pos.0.y;
+fn draw_splines(
+    mut commands: Commands,
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut drawers: Query<(&Position, &DrawSplines, &mut Stroke), With<FollowMouse>>,
+) {
+    for (pos, draw, mut stroke) in &mut drawers {
+        if buttons.pressed(MouseButton::Left) {
+            if stroke.0.last().is_none_or(|last| (pos.0 - last).norm() >= STROKE_SPACING) {
+                stroke.0.push(pos.0);
+            }
+            continue;
+        }
+        if stroke.0.is_empty() {
+            continue;
+        }
+        let points = std::mem::take(&mut stroke.0);
+        let Some(fit) = fit_stroke(&points, SplineDegree::default().0, draw.tolerance) else {
+            continue;
+        };
+        if fit.error > draw.tolerance {
+            warn!(
+                "stroke too detailed to fit within {}, the spline is up to {} off",
+                draw.tolerance, fit.error
+            );
+        }
+        let spline = spawn_spline(&mut commands, &fit.curve);
+        commands.entity(spline).insert((SplineRibbon::new(draw.material.clone()), Grindable));
     }
-
 }
 
-fn de_casteljau(mut control_points: Vec<Vector2<f32>>, t: f32) -> Vector2<f32> {
-    let mut r: i32 = 1;
-    while r < control_points.len() as i32 {
-        let n = control_points.len() as i32 - r;
-        for i in 0..n {
-            control_points[i as usize] = t * control_points[i as usize + 1] + (1.0 -t) * control_points[i as usize];
-        }
-        r += 1;
+/// Spawns a `Spline` with the degree and knots of `curve` and a movable
+/// control point entity for each of its control points, at rest where they
+/// are. Weights are left out, so `curve` should be polynomial.
+pub fn spawn_spline(commands: &mut Commands, curve: &BSpline) -> Entity {
+    let spline = commands
+        .spawn((Spline(), SplineDegree(curve.degree()), SplineKnots(curve.knots().to_vec())))
+        .id();
+    for &p in curve.control_points() {
+        commands.spawn((
+            Position(p),
+            Target(p),
+            OldPosition(p),
+            Movable { default_position: p },
+            ControlPoint(spline),
+        ));
     }
-
-    return control_points[0];
+    spline
 }
 
-#[inline(always)]
-pub fn de_boors<const LEN: usize>(control_points: &Vec<Vector2<f32>>, t: f32, t_vec: &Vec<f32>,temp: &mut [Vector2<f32>; LEN], l: usize) -> Vector2<f32> {
-    //assume uniform knots
-    let N: usize = LEN - 1;
-
-    let t_scale = t * t_vec[t_vec.len() - 1];
-
-
-    let base = l - N;
-
-    temp.copy_from_slice(&control_points[base..=l]);
-
+fn move_points(time: Res<Time>, mut position_query: Query<(&mut Position, &mut Transform), With<Moving>>) {
+    for (mut pos, mut trans) in &mut position_query {
+        pos.0.y += 0.2 * sin(time.elapsed_secs() + pos.0.x);
+        trans.translation.y = pos.0.y;
+    }
+}
 
-    for r in 1..=N {
-        let ir_base = r + base;        // i+r+(l-n) base
-        let in1_base = N + 1 + base;   // i+n+1+(l-n) base
+fn refine_under_pusher(
+    mut commands: Commands,
+    pushers: Query<(&Position, &RefineSplines), With<Pusher>>,
+    splines: Query<(SplineShape, &SplineCurve), With<Spline>>,
+    mut inserts: EventWriter<InsertKnot>,
+) {
+    for (pusher_pos, refine) in &pushers {
+        for (shape, curve) in splines.iter().filter(|(shape, _)| !shape.closed && shape.kind.0 == CurveKind::BSpline) {
+            let Some(curve) = &curve.0 else {
+                continue;
+            };
+
+            let closest = curve.closest_point(pusher_pos.0);
+            if closest.distance > PUSH_RADIUS {
+                continue;
+            }
 
-        for i in 0..=N - r {
-            let t_thing = (t_scale - t_vec[i + ir_base])
-                / (t_vec[i + in1_base] - t_vec[i + ir_base]);
-            temp[i] = t_thing * temp[i + 1] + (1.0 - t_thing) * temp[i];
+            // split the span under the pusher in half if it is too coarse
+            let l = closest.span;
+            let (a, b) = (curve.knots()[l], curve.knots()[l + 1]);
+            if (curve.eval(b) - curve.eval(a)).norm() > refine.max_span_length {
+                let param = 0.5 * (a + b);
+                inserts.write(InsertKnot { spline: shape.entity, param });
+                commands
+                    .entity(shape.entity)
+                    .entry::<Refined>()
+                    .and_modify(move |mut refined| refined.knots.push(param))
+                    .or_insert(Refined { knots: vec![param] });
+            }
         }
     }
-
-    return temp[0];
 }
-fn cubic_bspline(u: f32, ti: f32, ti1: f32, ti2: f32, ti3: f32, ti4: f32, ti5: f32, ti6:f32,
-                 di: Vector2<f32>, di1: Vector2<f32>, di2: Vector2<f32>, di3: Vector2<f32>) -> Vector2<f32> {
-
-
-    return  (
-        ((ti1 - ti4)*(ti2 - ti4)*(ti3 - u)*((ti2 - ti5)*(ti3 - u)*(di2*(-ti6 + u) + di3*(ti3 - u)) + (ti3 - ti6)*(-ti5 + u)*(di1*(-ti5 + u) + di2*(ti2 - u))) + (ti3 - ti5)*(ti3 - ti6)*(-ti4 + u)*((ti1 - ti4)*(ti2 - u)*(di1*(-ti5 + u) + di2*(ti2 - u)) + (ti2 - ti5)*(-ti4 + u)*(di*(-ti4 + u) + di1*(ti1 - u))))/((ti1 - ti4)*(ti2 - ti4)*(ti2 - ti5)*(ti3 - ti4)*(ti3 - ti5)*(ti3 - ti6))
-    );
 
+/// Removes the knots a pusher inserted into [`Refined`] splines again once no
+/// pusher is over them and their control points have settled at their targets,
+/// see [`go_to_target`]. Knots the spline had before are kept.
+fn coarsen_settled(
+    mut commands: Commands,
+    pushers: Query<&Position, (With<Pusher>, With<RefineSplines>)>,
+    splines: Query<(Entity, &ControlledBy, &SplineCurve, &Refined)>,
+    control_points: Query<Ref<Position>, With<Target>>,
+    mut removals: EventWriter<RemoveKnot>,
+) {
+    for (spline, controlled_by, curve, refined) in &splines {
+        let Some(curve) = &curve.0 else {
+            continue;
+        };
+        if pushers.iter().any(|pusher| curve.closest_point(pusher.0).distance <= PUSH_RADIUS) {
+            continue;
+        }
+        let settled = controlled_by
+            .iter()
+            .filter_map(|e| control_points.get(e).ok())
+            .all(|pos| !pos.is_changed());
+        if settled {
+            // latest first, so knots that split a refined span go before it
+            for &param in refined.knots.iter().rev() {
+                removals.write(RemoveKnot {
+                    spline,
+                    param,
+                    tolerance: COARSEN_TOLERANCE,
+                });
+            }
+            commands.entity(spline).remove::<Refined>();
+        }
+    }
 }
 
-#[inline(always)]
-pub fn not_de_boors<const LEN: usize>(control_points: &Vec<Vector2<f32>>, t: f32, t_vec: &Vec<f32>,temp: &mut [Vector2<f32>; LEN], l: usize) -> Vector2<f32> {
-    //assume uniform knots
-    let N: usize = LEN - 1;
-    let base = l - N;
-
-
-    let u = t * t_vec[t_vec.len() - 1];
-
-
-    let ti = t_vec[base];
-    let ti1 = t_vec[base+1];
-    let ti2 = t_vec[base+2];
-    let ti3 = t_vec[base+3];
-    let ti4 = t_vec[base+4];
-    let ti5 = t_vec[base+5];
-    let ti6 = t_vec[base+6];
-
-
-    let di = control_points[base];
-    let di1 = control_points[base+1];
-    let di2 = control_points[base+2];
-    let di3 = control_points[base+3];
-
-
-    return cubic_bspline(u, ti, ti1, ti2, ti3, ti4, ti5, ti6, di, di1, di2, di3);
+/// Control point components that have to follow the curve when knots change.
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct ControlPointData {
+    position: &'static mut Position,
+    target: Option<&'static mut Target>,
+    old_position: Option<&'static mut OldPosition>,
+    movable: Option<&'static mut Movable>,
+    weight: Option<&'static mut Weight>,
 }
 
-#[inline(always)]
-pub fn find_knot<const LEN: usize>(t: f32, t_vec: &Vec<f32>) -> usize {
-    //assume uniform knots
-
-    let t_scale = t * t_vec[t_vec.len() - 1];
-    let l = t_vec
-        .partition_point(|&knot| knot <= t_scale)
-        .saturating_sub(1).max(LEN-1). min(t_vec.len() - LEN - 1);
-    return l;
+/// Control point data of one spline while its knots are edited. Knot insertion
+/// and removal are linear in the control points, so targets and rest positions
+/// go through the same edit as the positions and keep describing the same curve.
+struct KnotEdit {
+    /// `None` for control points that still have to be spawned.
+    entities: Vec<Option<Entity>>,
+    despawned: Vec<Entity>,
+    /// The spline itself, through the positions of its control points.
+    curve: BSpline,
+    /// `curve` before the edit.
+    original: BSpline,
+    /// Parameters of the part of `curve` that knot removals moved, and how far
+    /// they moved it at most.
+    moved: Option<((f32, f32), f32)>,
+    targets: Option<BSpline>,
+    old_positions: Option<BSpline>,
+    rest_positions: Option<BSpline>,
 }
 
-#[inline(always)] pub fn de_boors_derivative<const LEN: usize>(
-    control_points: &Vec<Vector2<f32>>,
-    t: f32,
-    t_vec: &Vec<f32>,
-    temp: &mut [Vector2<f32>; LEN],
-    l: usize) -> Vector2<f32> {
+impl KnotEdit {
+    fn new(shape: &SplineShapeItem, control_points: &Query<ControlPointData, With<ControlPoint>>) -> Option<Self> {
+        // knots only exist for B-splines, and loops can't be refined, see
+        // `BSpline::insert_knot`
+        if shape.closed || shape.kind.0 != CurveKind::BSpline {
+            return None;
+        }
+        let entities: Vec<Entity> = shape.controlled_by.iter().collect();
+        let first = control_points.get(*entities.first()?).ok()?;
+        let mut positions = Vec::with_capacity(entities.len());
+        let mut targets = first.target.is_some().then(Vec::new);
+        let mut old_positions = first.old_position.is_some().then(Vec::new);
+        let mut rest_positions = first.movable.is_some().then(Vec::new);
+        let mut weights = Vec::with_capacity(entities.len());
+        for &entity in &entities {
+            let point = control_points.get(entity).ok()?;
+            let pos = point.position.0;
+            positions.push(pos);
+            weights.push(point.weight.map(|w| w.0));
+            if let Some(targets) = &mut targets {
+                targets.push(point.target.map_or(pos, |t| t.0));
+            }
+            if let Some(old_positions) = &mut old_positions {
+                old_positions.push(point.old_position.map_or(pos, |o| o.0));
+            }
+            if let Some(rest_positions) = &mut rest_positions {
+                rest_positions.push(point.movable.map_or(pos, |m| m.default_position));
+            }
+        }
 
-    let p = LEN -1;
-    let N: usize = LEN - 2;
-    let t_scale = t * t_vec[t_vec.len() - 1];
-    let base = l - p;
-    for i in 0 ..=N{
-        let dt = t_vec[base + i + p + 1] - t_vec[base + i + 1];
-        temp[i] = (control_points[i + base + 1] - control_points[i + base]) * (p as f32/ dt)
+        let curve = spline_from_points(shape, positions, spline_weights(&weights), &[])?;
+        // the same knots and weights over other control points
+        let column = |points: Vec<Vector2<f32>>| {
+            let column = BSpline::new(points, curve.degree(), curve.knots().to_vec()).ok()?;
+            match curve.weights() {
+                Some(weights) => column.with_weights(weights.to_vec()).ok(),
+                None => Some(column),
+            }
+        };
+        Some(KnotEdit {
+            entities: entities.into_iter().map(Some).collect(),
+            despawned: Vec::new(),
+            targets: targets.and_then(column),
+            old_positions: old_positions.and_then(column),
+            rest_positions: rest_positions.and_then(column),
+            original: curve.clone(),
+            moved: None,
+            curve,
+        })
     }
 
-    for r in 1..=N {
-
-        let ir_base = r + base + 1;
-        let in1_base = N + 1 + base + 1; // i+n+1+(l-n) base
+    /// The curves through the other per control point positions.
+    fn columns(&mut self) -> impl Iterator<Item = &mut BSpline> {
+        self.targets
+            .as_mut()
+            .into_iter()
+            .chain(self.old_positions.as_mut())
+            .chain(self.rest_positions.as_mut())
+    }
 
-        for i in 0..=N - r {
-            let t_thing = (t_scale - t_vec[i + ir_base]) / (t_vec[i + in1_base] - t_vec[i + ir_base]);
-            temp[i] = t_thing * temp[i + 1] + (1.0 - t_thing) * temp[i];
+    fn insert(&mut self, u: f32) {
+        let Some(index) = self.curve.insert_knot(u) else {
+            return;
+        };
+        for column in self.columns() {
+            column.insert_knot(u);
         }
+        self.entities.insert(index, None);
     }
-    return temp[0];
-}
-
-#[inline(always)]
-fn de_boors_second_derivative<const LEN: usize>(control_points: &Vec<Vector2<f32>>, t: f32, t_vec: &Vec<f32>,temp: &mut [Vector2<f32>; LEN], l: usize) -> Vector2<f32> {
-
-    let p = LEN - 1;
-    let n1 = LEN - 3; // degree reduced by 2
-    let t_scale = t * t_vec[t_vec.len() - 1];
-    let base = l - p;
 
-
-    for i in 0..=n1 {
-        let dt1 = t_vec[base + i + p  + 1] - t_vec[base + i + 2];
-        let dt0 = t_vec[base + i + p + 1] - t_vec[base + i + 1];
-        let cprime_i = (control_points[i + base + 1] - control_points[i + base]) * (p as f32 / dt0);
-        let cprime_next = (control_points[i + base + 2] - control_points[i + base + 1]) * (p as f32 / dt0);
-        temp[i] = (cprime_next - cprime_i) * ((p - 1) as f32 / dt1);
+    /// Removes `u` if the spline moves by at most `tolerance`, the other
+    /// columns follow whatever that costs them.
+    fn remove(&mut self, u: f32, tolerance: f32) {
+        // the control points around the knot change, and with them the spans
+        // they shape
+        let l = self.curve.find_span(u);
+        let (start, end) = (self.curve.support(l.saturating_sub(self.curve.degree() + 1)).0, self.curve.support(l).1);
+        let Some(index) = self.curve.remove_knot(u, tolerance) else {
+            return;
+        };
+        self.moved = Some(match self.moved {
+            Some(((low, high), moved)) => ((low.min(start), high.max(end)), moved + tolerance),
+            None => ((start, end), tolerance),
+        });
+        for column in self.columns() {
+            column.remove_knot(u, f32::INFINITY);
+        }
+        if let Some(entity) = self.entities.remove(index) {
+            self.despawned.push(entity);
+        }
     }
 
-    for r in 1..=n1 {
-        let ir_base = r + base + 2;        // i+r+(l-n) base
-        let in1_base = n1 + 1 + base + 2;   // i+n+1+(l-n) base
-
-        for i in 0..=n1 - r {
-            let t_thing = (t_scale - t_vec[i + ir_base])
-                / (t_vec[i + in1_base] - t_vec[i + ir_base]);
-            temp[i] = t_thing * temp[i + 1] + (1.0 - t_thing) * temp[i];
+    fn remove_redundant(&mut self, tolerance: f32) {
+        let degree = self.curve.degree();
+        let knots = self.curve.knots().to_vec();
+        // last occurrences of the interior knots, from the end so the rest keep
+        // their place
+        for r in (degree + 1..knots.len() - degree - 1).rev() {
+            if knots[r] != knots[r + 1] {
+                self.remove(knots[r], tolerance);
+            }
         }
     }
 
-    return temp[0];
-}
-
-fn render_spline(query: Query<(&Spline, &ControlledBy, &VisualizedBy)>,
-                 position_query: Query<&Position>,
-                 mut transforms: Query<&mut Transform>,
-){
-
-    let dim = 3;
-    let mut temp_buf: [Vector2<f32>; 4] = [Vector2::new(0.0, 0.0); 4];
-    for (spline, controlled_by, visualized_by) in &query {
-        let control_points = controlled_by.as_slice();
-
-        let positions: Vec<Vector2<f32>> = control_points
-            .iter()
-            .filter_map(|e| position_query.get(*e).ok())
-            .map(|p| p.0)
-            .collect();
-
-        let visual_points = visualized_by.as_slice();
-
-        let n = visual_points.len();
-        let step = 1.0 / n as f32;
-        let mut t = 0.0;
-
-
-        let mut v:Vec<f32> = Vec::with_capacity(positions.len() + dim + 1);
-        v.extend(std::iter::repeat(0.0).take(dim + 1)); // first n zeros
-        for i in 1..(positions.len() - dim ) {
-            v.push(i as f32);
+    fn apply(self, spline: Entity, commands: &mut Commands, control_points: &mut Query<ControlPointData, With<ControlPoint>>) {
+        for entity in &self.despawned {
+            commands.entity(*entity).despawn();
         }
-        v.extend(std::iter::repeat((positions.len() - dim) as f32).take(dim + 1)); // last n zeros
-
-
-
-
 
-        for i in 0..n {
-            // let point = de_casteljau(positions.clone(), t);
-
-            let l = find_knot::<4>(t, &v);
-            let point = de_boors::<4>(&positions, t, &v, &mut temp_buf, l);
-            if let Ok(mut transform) = transforms.get_mut(visual_points[i as usize]) {
-                transform.translation.x = point.x;
-                transform.translation.y = point.y;
+        let mut spawned = Vec::new();
+        for (i, entity) in self.entities.iter().enumerate() {
+            let position = self.curve.control_points()[i];
+            let target = self.targets.as_ref().map(|targets| targets.control_points()[i]);
+            let old = self.old_positions.as_ref().map(|old_positions| old_positions.control_points()[i]);
+            let rest = self.rest_positions.as_ref().map(|rest_positions| rest_positions.control_points()[i]);
+            let weight = self.curve.weights().map(|weights| weights[i]);
+
+            match entity {
+                Some(entity) => {
+                    let Ok(mut point) = control_points.get_mut(*entity) else {
+                        continue;
+                    };
+                    point.position.0 = position;
+                    if let (Some(mut t), Some(target)) = (point.target, target) {
+                        t.0 = target;
+                    }
+                    if let (Some(mut o), Some(old)) = (point.old_position, old) {
+                        o.0 = old;
+                    }
+                    if let (Some(mut m), Some(rest)) = (point.movable, rest) {
+                        m.default_position = rest;
+                    }
+                    match (point.weight, weight) {
+                        (Some(mut w), Some(weight)) => w.0 = weight,
+                        (None, Some(weight)) => {
+                            commands.entity(*entity).insert(Weight(weight));
+                        }
+                        _ => {}
+                    }
+                }
+                None => {
+                    let mut new = commands.spawn((Position(position), ControlPoint(spline)));
+                    if let Some(target) = target {
+                        new.insert(Target(target));
+                    }
+                    if let Some(old) = old {
+                        new.insert(OldPosition(old));
+                    }
+                    if let Some(rest) = rest {
+                        new.insert(Movable { default_position: rest });
+                    }
+                    if let Some(weight) = weight {
+                        new.insert(Weight(weight));
+                    }
+                    spawned.push((i, new.id()));
+                }
             }
-            t = t+step;
-
-
         }
 
+        commands.entity(spline).insert(SplineKnots(self.curve.knots().to_vec()));
+
+        // new control points are appended to the relationship, move them into place
+        if !spawned.is_empty() {
+            commands.queue(move |world: &mut World| {
+                if let Some(mut controlled_by) = world.get_mut::<ControlledBy>(spline) {
+                    let collection = controlled_by.collection_mut_risky();
+                    for (index, entity) in spawned {
+                        collection.place(entity, index);
+                    }
+                }
+            });
+        }
     }
 }
 
-fn render_intersections(query: Query<(&Spline, &ControlledBy, &GradientVisualizedBy)>,
-                   position_query: Query<&Position, Without<VerletObject>>,
-                   mut object_query: Query<(&mut Position), (With<VerletObject>)>,
-                   mut transforms: Query<&mut Transform>,
+fn edit_knots(
+    mut commands: Commands,
+    mut inserts: EventReader<InsertKnot>,
+    mut removals: EventReader<RemoveKnot>,
+    mut redundant: EventReader<RemoveRedundantKnots>,
+    splines: Query<SplineShape, With<Spline>>,
+    mut control_points: Query<ControlPointData, With<ControlPoint>>,
 ) {
+    let mut edits: HashMap<Entity, KnotEdit> = HashMap::new();
 
-    let dim = 3;
-    let mut temp_buf: [Vector2<f32>; 4] = [Vector2::new(0.0, 0.0); 4];
-
-    let Ok(object) = object_query.single() else {
-    return;
-    };
-    for (spline, controlled_by, visualized_by) in &query {
-        let control_points = controlled_by.as_slice();
-
-        let positions: Vec<Vector2<f32>> = control_points
-        .iter()
-        .filter_map(|e| position_query.get(*e).ok())
-        .map(|p| p.0)
-        .collect();
-
-        let visual_points = visualized_by.as_slice();
-
-        let n = visual_points.len();
-        let step = 1.0 / n as f32;
-
-
-        let mut v:Vec<f32> = Vec::with_capacity(positions.len() + dim + 1);
-        v.extend(std::iter::repeat(0.0).take(dim + 1)); // first n zeros
-        for i in 1..(positions.len() - dim ) {
-        v.push(i as f32);
+    for insert in inserts.read() {
+        if let Some(edit) = knot_edit(&mut edits, insert.spline, &splines, &control_points) {
+            edit.insert(insert.param);
         }
-        v.extend(std::iter::repeat((positions.len() - dim) as f32).take(dim + 1)); // last n zeros
-
-
-
-        let mut t = get_nearest_spline_point(object.0, &positions);
-
-
-        for i in 0..n {
-        let l = find_knot::<4>(t, &v);
-        let point = de_boors::<4>(&positions, t, &v, &mut temp_buf, l);
-        let grad = de_boors_derivative::<4>(&positions, t, &v, &mut temp_buf, l);
-        // let dist_grad = (2.0 * (point - mouse.0).transpose() * grad);
-        if let Ok(mut transform) = transforms.get_mut(visual_points[i as usize]) {
-        transform.translation.x = point.x;
-        transform.translation.y = point.y;
-        // transform.scale = Vec3::splat(1.0) * (dist_grad.x * 0.001);
-
-        let angle = atan2(grad.y, grad.x);
-        transform.rotation = Quat::from_rotation_z(angle);
+    }
+    for removal in removals.read() {
+        if let Some(edit) = knot_edit(&mut edits, removal.spline, &splines, &control_points) {
+            edit.remove(removal.param, removal.tolerance);
         }
-        // t = t+step;
-
-
+    }
+    for removal in redundant.read() {
+        if let Some(edit) = knot_edit(&mut edits, removal.spline, &splines, &control_points) {
+            edit.remove_redundant(removal.tolerance);
         }
+    }
 
+    for (spline, edit) in edits {
+        // removing a knot can move the curve, never let it fold
+        if let Some((range, moved)) = edit.moved {
+            if crosses_anew(&self_intersections_within(&edit.original, range), &edit.curve, range, moved) {
+                continue;
+            }
+        }
+        edit.apply(spline, &mut commands, &mut control_points);
     }
 }
 
-fn render_gradient(query: Query<(&Spline, &ControlledBy, &GradientVisualizedBy)>,
-                 position_query: Query<&Position, Without<VerletObject>>,
-                   mut object_query: Query<(&mut Position), (With<VerletObject>)>,
-                 mut transforms: Query<&mut Transform>,
-){
-
-    let dim = 3;
-    let mut temp_buf: [Vector2<f32>; 4] = [Vector2::new(0.0, 0.0); 4];
-
-    let Ok(object) = object_query.single() else {
-        return;
-    };
-    for (spline, controlled_by, visualized_by) in &query {
-        let control_points = controlled_by.as_slice();
-
-        let positions: Vec<Vector2<f32>> = control_points
-            .iter()
-            .filter_map(|e| position_query.get(*e).ok())
-            .map(|p| p.0)
-            .collect();
-
-        let visual_points = visualized_by.as_slice();
-
-        let n = visual_points.len();
-        let step = 1.0 / n as f32;
+fn knot_edit<'a>(
+    edits: &'a mut HashMap<Entity, KnotEdit>,
+    spline: Entity,
+    splines: &Query<SplineShape, With<Spline>>,
+    control_points: &Query<ControlPointData, With<ControlPoint>>,
+) -> Option<&'a mut KnotEdit> {
+    if let Entry::Vacant(entry) = edits.entry(spline) {
+        let shape = splines.get(spline).ok()?;
+        entry.insert(KnotEdit::new(&shape, control_points)?);
+    }
+    edits.get_mut(&spline)
+}
 
+fn convert_splines(
+    mut commands: Commands,
+    mut conversions: EventReader<ConvertSpline>,
+    splines: Query<SplineShape, With<Spline>>,
+    control_points: Query<(ControlPointData, Option<&Tangent>), With<ControlPoint>>,
+) {
+    // later conversions of the same spline would see the old control points
+    let mut converted = HashSet::new();
 
-        let mut v:Vec<f32> = Vec::with_capacity(positions.len() + dim + 1);
-        v.extend(std::iter::repeat(0.0).take(dim + 1)); // first n zeros
-        for i in 1..(positions.len() - dim ) {
-            v.push(i as f32);
+    for conversion in conversions.read() {
+        let Ok(shape) = splines.get(conversion.spline) else {
+            continue;
+        };
+        if shape.kind.0 == conversion.kind || converted.contains(&shape.entity) {
+            continue;
         }
-        v.extend(std::iter::repeat((positions.len() - dim) as f32).take(dim + 1)); // last n zeros
-
-
-
-        let mut t = get_nearest_spline_point(object.0, &positions);
-
-
-        for i in 0..n {
-            let l = find_knot::<4>(t, &v);
-            let point = de_boors::<4>(&positions, t, &v, &mut temp_buf, l);
-            let grad = de_boors_derivative::<4>(&positions, t, &v, &mut temp_buf, l);
-            // let dist_grad = (2.0 * (point - mouse.0).transpose() * grad);
-            if let Ok(mut transform) = transforms.get_mut(visual_points[i as usize]) {
-                transform.translation.x = point.x;
-                transform.translation.y = point.y;
-                // transform.scale = Vec3::splat(1.0) * (dist_grad.x * 0.001);
-
-                let angle = atan2(grad.y, grad.x);
-                transform.rotation = Quat::from_rotation_z(angle);
+        let entities: Vec<Entity> = shape.controlled_by.iter().collect();
+        let Ok(points) = entities.iter().map(|&e| control_points.get(e)).collect::<Result<Vec<_>, _>>() else {
+            continue;
+        };
+
+        let weights = spline_weights(&points.iter().map(|(point, _)| point.weight.map(|w| w.0)).collect::<Vec<_>>());
+        let tangents: Vec<Option<Vector2<f32>>> = points.iter().map(|(_, tangent)| tangent.map(|t| t.0)).collect();
+        let convert = |column: Vec<Vector2<f32>>| -> Option<ControlPolygon> {
+            let curve = spline_from_points(&shape, column, weights.clone(), &tangents)?;
+            from_bezier(conversion.kind, &bspline_to_bezier(&curve)?, shape.closed)
+        };
+        // targets and rest positions describe curves of their own, every one has to convert
+        let column = |value: fn(&ControlPointDataReadOnlyItem) -> Option<Vector2<f32>>| -> Result<Option<ControlPolygon>, ()> {
+            match points.iter().map(|(point, _)| value(point)).collect::<Option<Vec<_>>>() {
+                Some(values) => convert(values).map(Some).ok_or(()),
+                None => Ok(None),
             }
-            // t = t+step;
-
+        };
+        let (Ok(Some(positions)), Ok(targets), Ok(old_positions), Ok(rest_positions)) = (
+            column(|point| Some(point.position.0)),
+            column(|point| point.target.map(|t| t.0)),
+            column(|point| point.old_position.map(|o| o.0)),
+            column(|point| point.movable.map(|m| m.default_position)),
+        ) else {
+            continue;
+        };
 
+        for entity in entities {
+            commands.entity(entity).despawn();
+        }
+        for (i, &position) in positions.points.iter().enumerate() {
+            let mut new = commands.spawn((Position(position), ControlPoint(shape.entity)));
+            if let Some(targets) = &targets {
+                new.insert(Target(targets.points[i]));
+            }
+            if let Some(old_positions) = &old_positions {
+                new.insert(OldPosition(old_positions.points[i]));
+            }
+            if let Some(rest_positions) = &rest_positions {
+                new.insert(Movable {
+                    default_position: rest_positions.points[i],
+                });
+            }
+            if let Some(&tangent) = positions.tangents.get(i) {
+                new.insert(Tangent(tangent));
+            }
         }
-        //
-        // let mut t = get_nearest_spline_point(object.0, &positions);
-        // let mut t = 0.0;
-        //
-        //
-        // for i in 0..n {
-        //     let l = find_knot::<4>(t, &v);
-        //
-        //     let f = de_boors::<4>(&positions, t, &v, &mut temp_buf, l);
-        //     let _df = de_boors_derivative::<4>(&positions, t, &v, &mut temp_buf, l);
-        //     let cached_ddf = de_boors_second_derivative::<4>(&positions, t, &v, &mut temp_buf, l);
-        //
-        //     // compute gradient and hessian (1D Newton)
-        //     let f_val = (f-object.0).norm_squared();
-        //     let df_val = (2.0 * (f - object.0).transpose() * _df).x;
-        //     let ddf_val = (2.0 * (_df.transpose() * _df + (f - object.0).transpose() * cached_ddf)).x;
-        //     // let dist_grad = (2.0 * (point - mouse.0).transpose() * grad);
-        //     if let Ok(mut transform) = transforms.get_mut(visual_points[i as usize]) {
-        //         transform.translation.x = f.x;
-        //         transform.translation.y = f.y;
-        //         // transform.scale = Vec3::splat(1.0) * (dist_grad.x * 0.001);
-        //
-        //         let angle = atan2(_df.y, _df.x);
-        //         transform.rotation = Quat::from_rotation_z(angle);
-        //     }
-        // t = t+step;
-        //
-        //
-        // }
 
+        let mut spline = commands.entity(shape.entity);
+        spline.insert(SplineKind(conversion.kind));
+        match positions.knots {
+            Some(knots) => {
+                spline.insert((SplineKnots(knots), SplineDegree(3)));
+            }
+            None => {
+                spline.remove::<SplineKnots>();
+            }
+        }
+        converted.insert(shape.entity);
     }
 }
 
-pub fn get_nearest_spline_point(
-    point: Vector2<f32>,
-    positions: &Vec<Vector2<f32>>
-) -> f32{
-    let dim = 3;
-    let mut temp1: [Vector2<f32>; 4] = [Vector2::new(0.0, 0.0); 4];
-    let mut temp2 = [Vector2::zeros(); 4];
+/// Builds the curve described by the control points of a spline entity.
+/// The degree is lowered while there are too few control points for it, and
+/// `None` is returned if there are not even two.
+pub fn build_spline<F: QueryFilter>(shape: &SplineShapeItem, control_point_query: &Query<ControlPointShape, F>) -> Option<BSpline> {
+    build_moved_spline(shape, control_point_query, &HashMap::new())
+}
+
+/// Same as [`build_spline`] with the control points in `moved` at new positions.
+fn build_moved_spline<F: QueryFilter>(
+    shape: &SplineShapeItem,
+    control_point_query: &Query<ControlPointShape, F>,
+    moved: &HashMap<Entity, Vector2<f32>>,
+) -> Option<BSpline> {
+    let mut positions = Vec::with_capacity(shape.controlled_by.len());
+    let mut weights = Vec::with_capacity(shape.controlled_by.len());
+    let mut tangents = Vec::with_capacity(shape.controlled_by.len());
+    for (entity, point) in shape.controlled_by.iter().filter_map(|e| Some((e, control_point_query.get(e).ok()?))) {
+        positions.push(moved.get(&entity).copied().unwrap_or(point.position.0));
+        weights.push(point.weight.map(|w| w.0));
+        tangents.push(point.tangent.map(|t| t.0));
+    }
 
+    spline_from_points(shape, positions, spline_weights(&weights), &tangents)
+}
 
-        let mut v:Vec<f32> = Vec::with_capacity(positions.len() + dim + 1);
-        v.extend(std::iter::repeat(0.0).take(dim + 1)); // first n zeros
-        for i in 1..(positions.len() - dim ) {
-            v.push(i as f32);
+/// Same as [`build_spline`] for control points that were already looked up.
+/// `tangents` are only used by Hermite splines, and weights only by kinds whose
+/// control points are those of the B-spline.
+pub fn spline_from_points(
+    shape: &SplineShapeItem,
+    positions: Vec<Vector2<f32>>,
+    weights: Option<Vec<f32>>,
+    tangents: &[Option<Vector2<f32>>],
+) -> Option<BSpline> {
+    let kind = shape.kind.0;
+    let curve = match kind {
+        CurveKind::BSpline => {
+            let degree = shape.degree.0.clamp(1, MAX_DEGREE).min(positions.len().saturating_sub(1));
+            if shape.closed {
+                BSpline::periodic(positions, degree).ok()?
+            } else {
+                shape
+                    .knots
+                    .filter(|knots| knots.0.len() == positions.len() + degree + 1)
+                    .and_then(|knots| BSpline::new(positions.clone(), degree, knots.0.clone()).ok())
+                    .or_else(|| BSpline::clamped_uniform(positions, degree).ok())?
+            }
         }
-        v.extend(std::iter::repeat((positions.len() - dim) as f32).take(dim + 1)); // last n zeros
-
+        kind => BSpline::piecewise_bezier(to_bezier(kind, &positions, tangents, shape.closed)?, shape.closed).ok()?,
+    };
 
+    match weights {
+        Some(mut weights) if kind.maps_control_points() => {
+            // bezier points after the last full segment aren't part of the curve
+            weights.truncate(curve.control_points().len() - curve.wrapped());
+            curve.with_weights(weights).ok()
+        }
+        _ => Some(curve),
+    }
+}
 
-   let mut l = 0usize;
-let mut f = Vector2::zeros();
-let mut _df = Vector2::zeros();
-let mut cached_ddf = Vector2::zeros();
+/// Weights of a spline from the optional [`Weight`]s of its control points,
+/// `None` if the spline is not rational.
+pub fn spline_weights(weights: &[Option<f32>]) -> Option<Vec<f32>> {
+    weights
+        .iter()
+        .any(Option::is_some)
+        .then(|| weights.iter().map(|w| w.unwrap_or(1.0)).collect())
+}
 
-let mut t :f32= 0.5; // initial guess
-let tol = 1e-10;
-let max_iter = 10;
-    let mut rng = rand::thread_rng();
+/// Where a cast against the splines hit, see [`SplineCast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplineHit {
+    pub entity: Entity,
+    pub param: f32,
+    pub point: Vector2<f32>,
+    /// Unit normal of the spline at `point`, facing the cast.
+    pub normal: Vector2<f32>,
+    pub distance: f32,
+}
 
-let mut min_dist = f32::INFINITY;
-let mut min_t : f32 = 0.5;
-let scale = 1.0 /v[v.len() - 1];
-for i in 0..positions.len() {
+/// Ray, segment and circle casts against every `Spline`, e.g. for landing
+/// prediction or camera occlusion. Uses the splines' curves and BVHs, which are
+/// updated in [`SplineSet`], so results are exact from there on.
+#[derive(SystemParam)]
+pub struct SplineCast<'w, 's> {
+    splines: Query<'w, 's, (Entity, &'static SplineCurve, &'static SplineBvh), With<Spline>>,
+}
 
-    // t = (1.0 / 200.0) * i as f32;
-    // l = find_knot::<4>(t, &v);
-    // f = de_boors::<4>(&positions, t, &v, &mut temp1, l);
-    let dist = (positions[i]-point).norm_squared();
+impl SplineCast<'_, '_> {
+    /// First spline hit by the ray from `origin` along `direction`, within
+    /// `max_distance`.
+    pub fn ray(&self, origin: Vector2<f32>, direction: Vector2<f32>, max_distance: f32) -> Option<SplineHit> {
+        let direction = direction.try_normalize(f32::EPSILON)?;
+        self.nearest_hit(
+            |bounds| bounds.ray_interval(origin, direction, max_distance).is_some(),
+            |bvh, curve| bvh.raycast(curve, origin, direction, max_distance),
+        )
+    }
 
-    if(dist < min_dist){
-        min_dist = dist;
-        min_t = v[i + 3] * scale;
+    /// First spline hit on the way from `start` to `end`.
+    pub fn segment(&self, start: Vector2<f32>, end: Vector2<f32>) -> Option<SplineHit> {
+        self.ray(start, end - start, (end - start).norm())
     }
 
+    /// First spline touched by a circle of `radius` moving from `origin` along
+    /// `direction`, within `max_distance`. `point` is where it touches.
+    pub fn circle(&self, origin: Vector2<f32>, radius: f32, direction: Vector2<f32>, max_distance: f32) -> Option<SplineHit> {
+        let direction = direction.try_normalize(f32::EPSILON)?;
+        self.nearest_hit(
+            |bounds| bounds.expanded(radius).ray_interval(origin, direction, max_distance).is_some(),
+            |bvh, curve| bvh.circle_cast(curve, origin, radius, direction, max_distance),
+        )
+    }
 
+    fn nearest_hit(&self, reaches: impl Fn(&Aabb) -> bool, cast: impl Fn(&SpanBvh, &BSpline) -> Option<CastHit>) -> Option<SplineHit> {
+        let mut nearest: Option<SplineHit> = None;
+        for (entity, curve, bvh) in &self.splines {
+            if bvh.bounds().is_some_and(|bounds| !reaches(&bounds)) {
+                continue;
+            }
+            let Some(curve) = &curve.0 else {
+                continue;
+            };
+            // splines spawned since the last refit
+            let rebuilt;
+            let bvh = if bvh.fits(curve) {
+                &bvh.0
+            } else {
+                rebuilt = SpanBvh::new(curve);
+                &rebuilt
+            };
+
+            if let Some(hit) = cast(bvh, curve) {
+                if nearest.is_none_or(|nearest| hit.distance < nearest.distance) {
+                    nearest = Some(SplineHit {
+                        entity,
+                        param: hit.param,
+                        point: hit.point,
+                        normal: hit.normal,
+                        distance: hit.distance,
+                    });
+                }
+            }
+        }
+        nearest
+    }
 }
-    let con_t = min_t;
 
-    let con_scale = 1.0 / positions.len() as f32;
-    min_dist = f32::INFINITY;
-
-for i in (-20i32)..=20 {
-
-    t = con_t + i as f32 * con_scale / 10.0;
-    l = find_knot::<4>(t, &v);
-    f = de_boors::<4>(&positions, t, &v, &mut temp1, l);
-
-    let dist = ( f - point).norm_squared();
+/// Splines whose curve changed shape without any control point moving.
+type SplineChanged = Or<(
+    Changed<ControlledBy>,
+    Changed<SplineKind>,
+    Changed<SplineDegree>,
+    Changed<SplineKnots>,
+    Changed<SplineClosed>,
+)>;
+type ControlPointMoved = Or<(Changed<Position>, Changed<Weight>, Changed<Tangent>)>;
+/// Splines whose cached curve was rebuilt, see [`SplineCurve`].
+type CurveRebuilt = (With<Spline>, Changed<SplineCurve>);
+/// Splines whose ribbon mesh is out of date, see [`SplineRibbon`].
+type RibbonChanged = (With<Spline>, Or<(Changed<SplineCurve>, Changed<SplineThickness>, Changed<SplineRibbon>)>);
+
+/// Rebuilds the curve of splines whose control points or shape changed.
+pub fn update_curve(
+    mut query: Query<(SplineShape, &mut SplineCurve, &mut SplineCrossings), With<Spline>>,
+    changed_splines: Query<(), SplineChanged>,
+    moved_points: Query<(), ControlPointMoved>,
+    control_point_query: Query<ControlPointShape>,
+) {
+    for (shape, mut curve, mut crossings) in &mut query {
+        let moved = changed_splines.contains(shape.entity) || shape.controlled_by.iter().any(|e| moved_points.contains(e));
+        if moved || curve.is_none() {
+            let new = build_spline(&shape, &control_point_query);
+            crossings.0 = match (&curve.0, &new) {
+                (Some(old), Some(new)) => moved_crossings(old, &crossings.0, new),
+                (None, Some(new)) => self_intersections(new),
+                (_, None) => Vec::new(),
+            };
+            curve.0 = new;
+        }
+    }
+}
 
-    if(dist < min_dist){
-        min_dist = dist;
-        min_t = t;
+/// Finds the junctions of splines whose curve was rebuilt, the others keep
+/// theirs. Only pairs with a rebuilt spline are searched, and only if the
+/// bounds of their [`SplineBvh`]s overlap.
+fn update_junctions(
+    curves: Query<(Entity, Ref<SplineCurve>, &SplineBvh), With<Spline>>,
+    mut junctions: Query<(Entity, &mut SplineJunctions), With<Spline>>,
+) {
+    let rebuilt: HashSet<Entity> = curves
+        .iter()
+        .filter(|(_, curve, _)| curve.is_changed())
+        .map(|(entity, ..)| entity)
+        .collect();
+    if rebuilt.is_empty() {
+        return;
     }
+    let splines: Vec<(Entity, &BSpline, Aabb<f32>)> = curves
+        .iter()
+        .filter_map(|(entity, curve, bvh)| {
+            let curve = curve.into_inner().0.as_ref()?;
+            // the tree is refitted right before, unless the curve has no spans
+            let bounds = bvh.fits(curve).then(|| bvh.bounds()).flatten().unwrap_or_else(|| curve.bounds());
+            Some((entity, curve, bounds))
+        })
+        .collect();
 
+    let mut found: HashMap<Entity, Vec<Junction>> = HashMap::new();
+    for &(a, curve_a, bounds_a) in splines.iter().filter(|(entity, ..)| rebuilt.contains(entity)) {
+        for &(b, curve_b, bounds_b) in &splines {
+            // pairs of rebuilt splines only once
+            if a == b || (rebuilt.contains(&b) && b < a) || !bounds_a.intersects(&bounds_b) {
+                continue;
+            }
+            for crossing in intersections(curve_a, curve_b) {
+                let sine = curve_a.tangent(crossing.param_a).perp(&curve_b.tangent(crossing.param_b)).abs();
+                found.entry(a).or_default().push(Junction {
+                    other: b,
+                    param: crossing.param_a,
+                    other_param: crossing.param_b,
+                    point: crossing.point,
+                    sine,
+                });
+                found.entry(b).or_default().push(Junction {
+                    other: a,
+                    param: crossing.param_b,
+                    other_param: crossing.param_a,
+                    point: crossing.point,
+                    sine,
+                });
+            }
+        }
+    }
 
+    for (entity, mut junctions) in &mut junctions {
+        if rebuilt.contains(&entity) {
+            junctions.0.clear();
+        } else {
+            junctions.0.retain(|junction| !rebuilt.contains(&junction.other));
+        }
+        junctions.0.extend(found.remove(&entity).unwrap_or_default());
+        junctions.0.sort_by(|a, b| a.param.total_cmp(&b.param));
+    }
 }
-    t = min_t;
 
-for _ in 0..max_iter {
-    t = t.min(1.0).max(0.0);
-    // compute everything once per t
-    l = find_knot::<4>(t, &v);
-
-    f = de_boors::<4>(&positions, t, &v, &mut temp1, l);
-    _df = de_boors_derivative::<4>(&positions, t, &v, &mut temp1, l);
-    cached_ddf = de_boors_second_derivative::<4>(&positions, t, &v, &mut temp2, l);
-
-    // compute gradient and hessian (1D Newton)
-    let df_val = (2.0 * (f - point).transpose() * _df).x;
-    let ddf_val = (2.0 * (_df.transpose() * _df + (f - point).transpose() * cached_ddf)).x;
-    if ddf_val.abs() < 1e-12 {
-        break; // avoid division by zero
+fn update_arc_length(mut query: Query<(&SplineCurve, &mut SplineArcLength), CurveRebuilt>) {
+    for (curve, mut arc_length) in &mut query {
+        if let Some(curve) = &curve.0 {
+            arc_length.0 = ArcLengthTable::new(curve, ARC_LENGTH_SAMPLES);
+        }
     }
+}
 
-    let step = df_val / ddf_val;
-    t -= step / v[v.len() - 1];
+/// Refits the BVH of splines where their curve moved, see [`update_curve`].
+pub fn update_bvh(
+    mut query: Query<(SplineShape, &SplineCurve, &mut SplineBvh), With<Spline>>,
+    changed_splines: Query<(), SplineChanged>,
+    moved_points: Query<(), ControlPointMoved>,
+) {
+    for (shape, curve, mut bvh) in &mut query {
+        let count = shape.controlled_by.len();
+        let mut moved: Vec<usize> = shape
+            .controlled_by
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| moved_points.contains(*e))
+            .map(|(i, _)| i)
+            .collect();
+        if moved.is_empty() && !changed_splines.contains(shape.entity) && bvh.bounds().is_some() {
+            continue;
+        }
+        let Some(curve) = &curve.0 else {
+            continue;
+        };
 
-    if step.abs() < tol {
-        if((f - point).norm() > 100.0){
-            let t: f32 = rng.gen_range(0.0..1.0);
+        // a control point of an interpolating spline moves several of the curve's
+        if changed_splines.contains(shape.entity) || !bvh.fits(curve) || !shape.kind.maps_control_points() {
+            bvh.0 = SpanBvh::new(curve);
             continue;
         }
-        break;
+        // the first control points of a loop are repeated at its end
+        if curve.is_closed() {
+            let wrapped: Vec<usize> = moved.iter().filter(|&&i| i < curve.wrapped()).map(|&i| i + count).collect();
+            moved.extend(wrapped);
+        }
+        bvh.0.refit(curve, moved);
     }
 }
-    // fallback if something goes wrong
-    if (f-point).norm_squared() > min_dist {
-        t = min_t;
-    }
-
-    return t.min(1.0).max(0.0);;
-
-
 
+/// What [`update_ribbons`] needs of a spline.
+#[derive(QueryData)]
+struct RibbonSource {
+    entity: Entity,
+    curve: &'static SplineCurve,
+    thickness: &'static SplineThickness,
+    arc_length: &'static SplineArcLength,
+    ribbon: Ref<'static, SplineRibbon>,
+    mesh: Option<&'static Mesh2d>,
 }
 
-fn goes_through_line(
-    p1: Vector2<f32>,
-    p2: Vector2<f32>,
-    point: Vector2<f32>,
-) -> bool {
-
-    let (bigger, smaller) = if p1.x > p2.x {
-        (p1, p2)
-    } else {
-        (p2, p1)
-    };
-    if(smaller.x == bigger.x){
-        return false;
+fn update_ribbons(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, query: Query<RibbonSource, RibbonChanged>) {
+    for source in &query {
+        let Some(curve) = &source.curve.0 else {
+            continue;
+        };
+        let ribbon = Ribbon::new(&ThickSpline::new(curve, source.thickness), source.arc_length, source.ribbon.tolerance);
+        let ribbon_mesh = ribbon_mesh(&ribbon, source.ribbon.texture_length);
+
+        match source.mesh.and_then(|mesh| meshes.get_mut(&mesh.0)) {
+            Some(mesh) => *mesh = ribbon_mesh,
+            None => {
+                commands.entity(source.entity).insert(Mesh2d(meshes.add(ribbon_mesh)));
+            }
+        }
+        if source.ribbon.is_changed() {
+            commands.entity(source.entity).insert(MeshMaterial2d(source.ribbon.material.clone()));
+        }
     }
-    let f = (point.x - smaller.x)/ (bigger.x - smaller.x);
+}
 
-    return (((1.0 - f)* smaller.y + f * bigger.y) > point.y) && f>0.0 && f<= 1.0;
+/// Triangle strip zigzagging from the left to the right edge of each section.
+/// Texture coordinates run along the curve in `x` and across it in `y`.
+fn ribbon_mesh(ribbon: &Ribbon, texture_length: f32) -> Mesh {
+    let mut positions = Vec::with_capacity(2 * ribbon.len());
+    let mut uvs = Vec::with_capacity(2 * ribbon.len());
+    for i in 0..ribbon.len() {
+        let x = ribbon.lengths[i] / texture_length;
+        positions.push([ribbon.left[i].x, ribbon.left[i].y, 0.0]);
+        positions.push([ribbon.right[i].x, ribbon.right[i].y, 0.0]);
+        uvs.extend([[x, 0.0], [x, 1.0]]);
+    }
+    let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
 
+    Mesh::new(PrimitiveTopology::TriangleStrip, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
 }
 
-pub fn point_inside(
-    point: Vector2<f32>,
-    positions: &Vec<Vector2<f32>>,
-    spline_info: Option<&SplineColliderInfo>
-) -> (bool, SplineColliderInfo) {
+/// What [`update_fills`] needs of a spline.
+#[derive(QueryData)]
+#[query_data(mutable)]
+struct FillSource {
+    entity: Entity,
+    curve: Ref<'static, SplineCurve>,
+    fill: Ref<'static, SplineFill>,
+    hidden: Option<Ref<'static, HiddenControlledBy>>,
+    filled_by: Option<&'static FilledBy>,
+    build: Option<&'static mut FillBuild>,
+}
 
-    let mut last: &Vector2<f32> = &positions[0];
-    let mut count = 0;
+fn update_fills(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<FillSource, With<Spline>>,
+    hidden_points: Query<Ref<Position>>,
+    fill_meshes: Query<&Mesh2d, With<FillOf>>,
+) {
+    let now = time.elapsed_secs();
+    for mut source in &mut query {
+        let Some(curve) = &source.curve.0 else {
+            continue;
+        };
+        let fill_entity = source.filled_by.and_then(|filled_by| filled_by.first().copied());
+        let hidden_moved = source
+            .hidden
+            .as_ref()
+            .is_some_and(|hidden| hidden.is_changed() || hidden.iter().any(|e| hidden_points.get(e).is_ok_and(|pos| pos.is_changed())));
+        let changed = fill_entity.is_none() || source.curve.is_changed() || source.fill.is_changed() || hidden_moved;
+
+        match source.build.as_deref_mut() {
+            Some(build) => {
+                build.stale |= changed;
+                if !build.stale || (!source.fill.is_changed() && now - build.built_at < source.fill.rebuild_interval) {
+                    continue;
+                }
+                *build = FillBuild { built_at: now, stale: false };
+            }
+            None => {
+                commands.entity(source.entity).insert(FillBuild { built_at: now, stale: false });
+            }
+        }
 
-    for pos in &positions[1..] {
-       count += goes_through_line(*last, *pos, point) as i32;
-        last = pos;
+        let hidden: Vec<Vector2<f32>> = source
+            .hidden
+            .iter()
+            .flat_map(|hidden| hidden.iter())
+            .filter_map(|e| hidden_points.get(e).ok().map(|pos| pos.0))
+            .collect();
+        let below = if hidden.is_empty() {
+            let (start, end) = curve.domain();
+            let (start, end) = (curve.eval(start), curve.eval(end));
+            vec![Vector2::new(end.x, source.fill.floor), Vector2::new(start.x, source.fill.floor)]
+        } else {
+            hidden
+        };
+        let fill_mesh = fill_mesh(&outline(curve, source.fill.tolerance, &below), source.fill.texture_size);
+
+        match fill_entity {
+            Some(fill_entity) => {
+                match fill_meshes.get(fill_entity).ok().and_then(|mesh| meshes.get_mut(&mesh.0)) {
+                    Some(mesh) => *mesh = fill_mesh,
+                    None => {
+                        commands.entity(fill_entity).insert(Mesh2d(meshes.add(fill_mesh)));
+                    }
+                }
+                if source.fill.is_changed() {
+                    commands.entity(fill_entity).insert(MeshMaterial2d(source.fill.material.clone()));
+                }
+            }
+            None => {
+                commands.spawn((
+                    FillOf(source.entity),
+                    Mesh2d(meshes.add(fill_mesh)),
+                    MeshMaterial2d(source.fill.material.clone()),
+                    Transform::from_xyz(0.0, 0.0, FILL_DEPTH),
+                ));
+            }
+        }
     }
+}
 
-    let start_x = positions[0].x < point.x;
-    let start_y = positions[0].y < point.y;
-    let end_x = positions[positions.len()-1].x < point.x;
-    let end_y = positions[positions.len()-1].y < point.y;
-
-    let splinco = SplineColliderInfo{intersections: count, start_sector: start_x as i32 + start_y as i32 * 2, end_sector: end_x as i32 + end_y as i32 * 2 };
+/// Triangles of the polygon `outline`, with texture coordinates in world units
+/// divided by `texture_size`.
+fn fill_mesh(outline: &[Vector2<f32>], texture_size: f32) -> Mesh {
+    let positions: Vec<[f32; 3]> = outline.iter().map(|p| [p.x, p.y, 0.0]).collect();
+    let uvs: Vec<[f32; 2]> = outline.iter().map(|p| [p.x / texture_size, -p.y / texture_size]).collect();
+    let normals = vec![[0.0, 0.0, 1.0]; positions.len()];
+    let indices = triangulate(outline).into_iter().flatten().collect();
+
+    Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::default())
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U32(indices))
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::render::mesh::VertexAttributeValues;
+
+    #[test]
+    fn ribbon_texture_runs_along_the_arc_length() {
+        let points = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(200.0, 300.0),
+            Vector2::new(400.0, -100.0),
+            Vector2::new(700.0, 50.0),
+        ];
+        let curve = BSpline::clamped_uniform(points, 3).unwrap();
+        let profile = ThicknessProfile::constant(10.0);
+        let arc_length = ArcLengthTable::new(&curve, ARC_LENGTH_SAMPLES);
+        let ribbon = Ribbon::new(&ThickSpline::new(&curve, &profile), &arc_length, 0.5);
+
+        let mesh = ribbon_mesh(&ribbon, 100.0);
+        let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute(Mesh::ATTRIBUTE_UV_0) else {
+            panic!("ribbon mesh without texture coordinates");
+        };
+        assert_eq!(uvs.len(), 2 * ribbon.len());
+        for (i, &u) in ribbon.params.iter().enumerate() {
+            let expected = arc_length.length_at_param(u) / 100.0;
+            assert!((uvs[2 * i][0] - expected).abs() < 1e-4, "{u} {:?} {expected}", uvs[2 * i]);
+            assert_eq!(uvs[2 * i][0], uvs[2 * i + 1][0]);
+        }
+    }
 
-    // for pos in hidden {
-    //     count += goes_through_line(*last, *pos, point) as i32;
-    //     last = pos;
-    // }
+    #[test]
+    fn curve_is_only_rebuilt_when_a_control_point_moves() {
+        let mut app = App::new();
+        app.add_systems(Update, update_curve);
+        let world = app.world_mut();
+        let spline = world.spawn(Spline()).id();
+        let points: Vec<Entity> = (0..5)
+            .map(|i| world.spawn((Position(Vector2::new(100.0 * i as f32, 0.0)), ControlPoint(spline))).id())
+            .collect();
+        let rebuilt = |app: &App| app.world().entity(spline).get_ref::<SplineCurve>().unwrap().last_changed();
+
+        app.update();
+        let built = rebuilt(&app);
+        assert!(app.world().get::<SplineCurve>(spline).unwrap().is_some());
+        app.update();
+        app.update();
+        assert_eq!(rebuilt(&app), built);
+
+        app.world_mut().get_mut::<Position>(points[2]).unwrap().0.y = 50.0;
+        app.update();
+        assert_ne!(rebuilt(&app), built);
+        let curve = app.world().get::<SplineCurve>(spline).unwrap().0.clone().unwrap();
+        assert_eq!(curve.control_points()[2], Vector2::new(200.0, 50.0));
+    }
 
+    fn pushed_ground(pusher: Vector2<f32>) -> (Vec<Vector2<f32>>, Vec<Vector2<f32>>) {
+        // ground with an overhang, pushing from the right folds it
+        let points = vec![
+            Vector2::new(62.0, -1.0),
+            Vector2::new(249.0, 71.0),
+            Vector2::new(78.0, 149.0),
+            Vector2::new(292.0, 54.0),
+            Vector2::new(386.0, 46.0),
+            Vector2::new(537.0, 15.0),
+        ];
+        let mut world = World::new();
+        let spline = world.spawn(Spline()).id();
+        let entities: Vec<Entity> = points
+            .iter()
+            .map(|&p| {
+                world
+                    .spawn((Position(p), Target(p), Movable { default_position: p }, ControlPoint(spline)))
+                    .id()
+            })
+            .collect();
+        world.run_system_once(update_curve).unwrap();
+        world.spawn((Position(pusher), Pusher()));
+        world.run_system_once(push).unwrap();
 
-    // count += goes_through_line(*last, positions[0], point) as i32;
+        let pushed = entities.iter().map(|&e| world.get::<Position>(e).unwrap().0).collect();
+        (points, pushed)
+    }
 
+    #[test]
+    fn push_that_would_fold_the_ground_leaves_it_unchanged() {
+        let pusher = Vector2::new(400.0, 81.0);
+        let (points, pushed) = pushed_ground(pusher);
+
+        let old = BSpline::clamped_uniform(points.clone(), 3).unwrap();
+        let folded: Vec<Vector2<f32>> = points.iter().map(|&p| pushed_away(p, pusher)).collect();
+        let folded = BSpline::clamped_uniform(folded, 3).unwrap();
+        assert!(self_intersections(&old).is_empty());
+        assert!(folds(Some(&old), &[], &folded));
+        assert_eq!(pushed, points);
+    }
 
+    /// Clamped cubic spline through `points`, scaled by 100.
+    fn scaled(points: &[(f32, f32)]) -> BSpline {
+        BSpline::clamped_uniform(points.iter().map(|&(x, y)| 100.0 * Vector2::new(x, y)).collect(), 3).unwrap()
+    }
 
+    /// A straight stretch with a loop at its start, at its end or at both.
+    fn loops(start: bool, end: bool) -> BSpline {
+        let curl = |x: f32, curled: bool| {
+            if curled {
+                [(x, 0.0), (x + 3.0, 2.0), (x - 1.0, 2.0), (x + 2.0, 0.0)]
+            } else {
+                [(x, 0.0), (x + 0.7, 0.0), (x + 1.3, 0.0), (x + 2.0, 0.0)]
+            }
+        };
+        let mut points = curl(0.0, start).to_vec();
+        points.extend([(4.0, 0.0), (6.0, 0.0)]);
+        points.extend(curl(8.0, end));
+        scaled(&points)
+    }
 
-    if let Some(spl) = spline_info{
-        let mut parity_modifier = 0;
-        if(spl.start_sector == 0 && splinco.start_sector == 1 || spl.start_sector == 1 && splinco.start_sector == 0){
-           parity_modifier += 1;
-        }
+    #[test]
+    fn moving_a_loop_elsewhere_folds() {
+        let (left, right) = (loops(true, false), loops(false, true));
+        let crossings = self_intersections(&left);
+        assert_eq!(crossings.len(), 1);
+        assert_eq!(self_intersections(&right).len(), 1);
+
+        // as many crossings as before, but not where they were
+        assert!(folds(Some(&left), &crossings, &right));
+        // the loop itself moving a little is no fold
+        let nudged = BSpline::clamped_uniform(left.control_points().iter().map(|p| p + Vector2::new(3.0, -2.0)).collect(), 3).unwrap();
+        assert!(!folds(Some(&left), &crossings, &nudged));
+        // nor is undoing it
+        assert!(!folds(Some(&left), &crossings, &loops(false, false)));
+    }
 
-        if(spl.end_sector == 0 && splinco.end_sector == 1 || spl.end_sector == 1 && splinco.end_sector == 0){
-            parity_modifier += 1;
+    #[test]
+    fn crossings_follow_the_curve_where_it_moved() {
+        let mut crossings = self_intersections(&loops(false, false));
+        assert!(crossings.is_empty());
+        for (old, new) in [
+            ((false, false), (true, false)),
+            ((true, false), (true, true)),
+            ((true, true), (false, true)),
+        ] {
+            let (old, new) = (loops(old.0, old.1), loops(new.0, new.1));
+            crossings = moved_crossings(&old, &crossings, &new);
+            let expected = self_intersections(&new);
+            assert_eq!(crossings.len(), expected.len(), "{crossings:?}");
+            for (x, y) in crossings.iter().zip(&expected) {
+                assert!((x.point - y.point).norm() < 1e-3, "{x:?} {y:?}");
+            }
         }
+    }
 
-        // println!("{}, {}", spl.start_sector, splinco.start_sector);
-        // println!("{}, {}", spl.end_sector, splinco.end_sector);
-
-        let inters_old = spl.intersections;
-        let inters_new = splinco.intersections;
+    #[test]
+    fn push_that_keeps_the_ground_apart_moves_it() {
+        let pusher = Vector2::new(400.0, -120.0);
+        let (points, pushed) = pushed_ground(pusher);
 
-        return (inters_old%2 != (parity_modifier + inters_new)%2, splinco);
+        let expected: Vec<Vector2<f32>> = points.iter().map(|&p| pushed_away(p, pusher)).collect();
+        assert_ne!(pushed, points);
+        assert_eq!(pushed, expected);
     }
-    return (false, splinco);
 
+    #[test]
+    fn crossing_splines_get_junctions() {
+        let line = |from: Vector2<f32>, to: Vector2<f32>| {
+            let points = (0..=4).map(|i| from.lerp(&to, i as f32 / 4.0)).collect();
+            SplineCurve(BSpline::clamped_uniform(points, 3).ok())
+        };
+        let mut world = World::new();
+        let a = world.spawn((Spline(), line(Vector2::new(-100.0, 0.0), Vector2::new(100.0, 0.0)))).id();
+        let b = world.spawn((Spline(), line(Vector2::new(0.0, -100.0), Vector2::new(0.0, 100.0)))).id();
+        let apart = world.spawn((Spline(), line(Vector2::new(500.0, 0.0), Vector2::new(600.0, 0.0)))).id();
+        world.run_system_once(update_junctions).unwrap();
+
+        let junctions = |world: &World, spline: Entity| world.get::<SplineJunctions>(spline).unwrap().0.clone();
+        let (on_a, on_b) = (junctions(&world, a), junctions(&world, b));
+        assert_eq!(on_a.len(), 1);
+        assert_eq!(on_b.len(), 1);
+        assert!(junctions(&world, apart).is_empty());
+        assert_eq!((on_a[0].other, on_b[0].other), (b, a));
+        assert_eq!((on_a[0].param, on_a[0].other_param), (on_b[0].other_param, on_b[0].param));
+        assert!(on_a[0].point.norm() < 1e-3, "{on_a:?}");
+        assert!((on_a[0].sine - 1.0).abs() < 1e-3, "{on_a:?}");
+
+        // moving one of them away drops the junction on the other
+        world.get_mut::<SplineCurve>(b).unwrap().0 = line(Vector2::new(300.0, -100.0), Vector2::new(300.0, 100.0)).0;
+        world.run_system_once(update_junctions).unwrap();
+        assert!(junctions(&world, a).is_empty());
+        assert!(junctions(&world, b).is_empty());
+    }
 }
-
-
@@ -0,0 +1,1601 @@
+//! B-spline math without any Bevy dependency.
+//!
+//! Everything in here works on plain nalgebra vectors so it can be reused by
+//! tools and evaluated outside of the ECS. The plugins build a [`BSpline`]
+//! from their control point entities and query it.
+//!
+//! Curves are generic over their [`Real`] scalar and default to `f32`, which
+//! is what the plugins and rendering use. Far from the origin `f32` runs out of
+//! digits, there [`BSpline::cast`] gives the same curve in `f64`.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use nalgebra::{RealField, SVector, Vector2, Vector3};
+
+/// Largest degree the evaluator supports. The scratch buffers used by de Boor's
+/// algorithm live on the stack and are sized by this.
+pub const MAX_DEGREE: usize = 7;
+
+/// Scalars curves can be evaluated in, `f32` and `f64`.
+pub trait Real: RealField + Copy {}
+
+impl<T: RealField + Copy> Real for T {}
+
+/// `x` as a `T`, for constants in generic code.
+pub(crate) fn real<T: Real>(x: f64) -> T {
+    nalgebra::convert(x)
+}
+
+fn to_f64<T: Real>(x: T) -> f64 {
+    x.to_subset_unchecked()
+}
+
+/// `x` in another scalar type, by way of `f64`.
+fn cast<T: Real, U: Real>(x: T) -> U {
+    real(to_f64(x))
+}
+
+/// `x` modulo `m` in `0..m`.
+fn rem_euclid<T: Real>(x: T, m: T) -> T {
+    x - (x / m).floor() * m
+}
+
+fn compare<T: Real>(a: &T, b: &T) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SplineError {
+    /// The degree is zero or larger than [`MAX_DEGREE`].
+    InvalidDegree(usize),
+    /// A spline of degree `p` needs at least `p + 1` control points.
+    NotEnoughControlPoints { degree: usize, count: usize },
+    /// The knot vector must contain `control points + degree + 1` knots.
+    KnotCountMismatch { expected: usize, found: usize },
+    /// Knots have to be non-decreasing.
+    DecreasingKnots,
+    /// A rational spline needs one weight per control point.
+    WeightCountMismatch { expected: usize, found: usize },
+    /// Weights have to be positive for the curve to stay inside its control polygon.
+    NonPositiveWeight,
+    /// A chain of cubic Bézier curves needs `3k + 1` control points, or `3k` for a loop.
+    BezierPointCount { count: usize, closed: bool },
+}
+
+impl fmt::Display for SplineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplineError::InvalidDegree(degree) => {
+                write!(f, "degree {degree} is outside of 1..={MAX_DEGREE}")
+            }
+            SplineError::NotEnoughControlPoints { degree, count } => {
+                write!(f, "degree {degree} needs at least {} control points, got {count}", degree + 1)
+            }
+            SplineError::KnotCountMismatch { expected, found } => {
+                write!(f, "expected {expected} knots, got {found}")
+            }
+            SplineError::DecreasingKnots => write!(f, "knots are not non-decreasing"),
+            SplineError::WeightCountMismatch { expected, found } => {
+                write!(f, "expected {expected} weights, got {found}")
+            }
+            SplineError::NonPositiveWeight => write!(f, "weights have to be positive"),
+            SplineError::BezierPointCount { count, closed } => {
+                let shape = if *closed { "a loop of" } else { "a chain of" };
+                write!(f, "{count} control points don't make {shape} cubic Bézier curves")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplineError {}
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb<T: Real = f32> {
+    pub min: Vector2<T>,
+    pub max: Vector2<T>,
+}
+
+impl<T: Real> Aabb<T> {
+    /// Smallest box containing all `points`. Returns `None` for an empty iterator.
+    pub fn from_points<I: IntoIterator<Item = Vector2<T>>>(points: I) -> Option<Self> {
+        let mut iter = points.into_iter();
+        let first = iter.next()?;
+        let mut aabb = Aabb { min: first, max: first };
+        for p in iter {
+            aabb.min = aabb.min.inf(&p);
+            aabb.max = aabb.max.sup(&p);
+        }
+        Some(aabb)
+    }
+
+    pub fn union(&self, other: &Aabb<T>) -> Aabb<T> {
+        Aabb {
+            min: self.min.inf(&other.min),
+            max: self.max.sup(&other.max),
+        }
+    }
+
+    pub fn contains(&self, point: Vector2<T>) -> bool {
+        point.x >= self.min.x && point.x <= self.max.x && point.y >= self.min.y && point.y <= self.max.y
+    }
+
+    pub fn intersects(&self, other: &Aabb<T>) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x && self.min.y <= other.max.y && self.max.y >= other.min.y
+    }
+
+    /// Box grown by `margin` on every side.
+    pub fn expanded(&self, margin: T) -> Aabb<T> {
+        let margin = Vector2::repeat(margin);
+        Aabb {
+            min: self.min - margin,
+            max: self.max + margin,
+        }
+    }
+
+    /// Range of `t` in `0..=max_t` for which `origin + t * direction` is inside
+    /// the box, `None` if the ray misses it.
+    pub fn ray_interval(&self, origin: Vector2<T>, direction: Vector2<T>, max_t: T) -> Option<(T, T)> {
+        let (mut enter, mut exit) = (T::zero(), max_t);
+        for axis in 0..2 {
+            if direction[axis] == T::zero() {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[axis] - origin[axis]) / direction[axis];
+            let b = (self.max[axis] - origin[axis]) / direction[axis];
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+        (enter <= exit).then_some((enter, exit))
+    }
+
+    /// Squared distance from `point` to the box, zero if the point is inside.
+    pub fn distance_squared(&self, point: Vector2<T>) -> T {
+        let clamped = point.sup(&self.min).inf(&self.max);
+        (point - clamped).norm_squared()
+    }
+}
+
+/// A B-spline curve in the plane, rational (a NURBS) if it has weights.
+///
+/// The curve is parameterised over its knot domain, see [`BSpline::domain`].
+/// Closed splines wrap around at the ends of the domain.
+#[derive(Debug, Clone)]
+pub struct BSpline<T: Real = f32> {
+    control_points: Vec<Vector2<T>>,
+    weights: Option<Vec<T>>,
+    degree: usize,
+    knots: Vec<T>,
+    /// How many of the first control points are repeated at the end, zero for
+    /// open splines.
+    wrapped: usize,
+}
+
+/// Distance below which [`BSpline::closest_point`] treats the curve as straight.
+pub const CLOSEST_POINT_TOLERANCE: f32 = 1e-3;
+
+/// Result of [`BSpline::closest_point`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosestPoint<T: Real = f32> {
+    pub param: T,
+    pub point: Vector2<T>,
+    pub distance: T,
+    /// Knot span that contains `param`, see [`BSpline::find_span`].
+    pub span: usize,
+}
+
+/// Frenet frame of a curve at one parameter, see [`BSpline::frame`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame<T: Real = f32> {
+    pub point: Vector2<T>,
+    /// Unit tangent in the direction of increasing parameter.
+    pub tangent: Vector2<T>,
+    /// Unit normal, the tangent rotated a quarter turn counter-clockwise.
+    pub normal: Vector2<T>,
+    /// Signed curvature, positive where the curve bends towards `normal`.
+    pub curvature: T,
+}
+
+impl<T: Real> Frame<T> {
+    /// Radius of the osculating circle, infinite where the curve is straight.
+    pub fn radius_of_curvature(&self) -> T {
+        T::one() / self.curvature.abs()
+    }
+
+    /// Center and radius of the circle that best fits the curve at this point,
+    /// `None` where the curve is straight.
+    pub fn osculating_circle(&self) -> Option<(Vector2<T>, T)> {
+        (self.curvature != T::zero()).then(|| (self.point + self.normal / self.curvature, self.radius_of_curvature()))
+    }
+}
+
+/// One polynomial piece of a spline in Bézier form, see [`BSpline::bezier_segments`].
+#[derive(Debug, Clone)]
+pub struct BezierSegment<T: Real = f32> {
+    /// Knot span of the spline the piece comes from.
+    pub span: usize,
+    /// Parameter range of the piece on the spline.
+    pub range: (T, T),
+    /// Control points in homogeneous coordinates, see [`to_homogeneous`].
+    pub points: Vec<Vector3<T>>,
+}
+
+impl<T: Real> BSpline<T> {
+    pub fn new(control_points: Vec<Vector2<T>>, degree: usize, knots: Vec<T>) -> Result<Self, SplineError> {
+        if degree == 0 || degree > MAX_DEGREE {
+            return Err(SplineError::InvalidDegree(degree));
+        }
+        if control_points.len() < degree + 1 {
+            return Err(SplineError::NotEnoughControlPoints {
+                degree,
+                count: control_points.len(),
+            });
+        }
+        let expected = control_points.len() + degree + 1;
+        if knots.len() != expected {
+            return Err(SplineError::KnotCountMismatch {
+                expected,
+                found: knots.len(),
+            });
+        }
+        if knots.windows(2).any(|w| w[1] < w[0]) {
+            return Err(SplineError::DecreasingKnots);
+        }
+        Ok(Self {
+            control_points,
+            weights: None,
+            degree,
+            knots,
+            wrapped: 0,
+        })
+    }
+
+    /// Closed spline through the loop of `control_points`. The first `degree`
+    /// control points are repeated at the end and the knots are spaced one apart
+    /// without clamping, so the curve joins itself smoothly. The domain is
+    /// `0..control_points.len()`.
+    pub fn periodic(mut control_points: Vec<Vector2<T>>, degree: usize) -> Result<Self, SplineError> {
+        if degree == 0 || degree > MAX_DEGREE {
+            return Err(SplineError::InvalidDegree(degree));
+        }
+        let count = control_points.len();
+        if count < degree + 1 {
+            return Err(SplineError::NotEnoughControlPoints { degree, count });
+        }
+        control_points.extend_from_within(..degree);
+        let knots = (0..count + 2 * degree + 1).map(|i| real(i as f64 - degree as f64)).collect();
+        Ok(Self {
+            control_points,
+            weights: None,
+            degree,
+            knots,
+            wrapped: degree,
+        })
+    }
+
+    /// Chain of cubic Bézier curves, every third control point on the curve with
+    /// two handles in between. Open chains need `3k + 1` control points, loops
+    /// `3k` and end back at the first one. Knots are tripled, so segment `i`
+    /// covers the parameters `i..i + 1`.
+    pub fn piecewise_bezier(mut control_points: Vec<Vector2<T>>, closed: bool) -> Result<Self, SplineError> {
+        let count = control_points.len();
+        let segments = count / 3;
+        if segments == 0 || count % 3 != if closed { 0 } else { 1 } {
+            return Err(SplineError::BezierPointCount { count, closed });
+        }
+        if closed {
+            control_points.push(control_points[0]);
+        }
+        let mut knots = vec![T::zero(); 4];
+        for i in 1..segments {
+            knots.extend([real::<T>(i as f64); 3]);
+        }
+        knots.extend([real::<T>(segments as f64); 4]);
+        let wrapped = usize::from(closed);
+        Ok(Self {
+            control_points,
+            weights: None,
+            degree: 3,
+            knots,
+            wrapped,
+        })
+    }
+
+    /// Turns the spline into a rational one with a weight per control point.
+    /// Larger weights pull the curve towards their control point. Closed splines
+    /// take one weight per control point of the loop they were built from.
+    pub fn with_weights(mut self, mut weights: Vec<T>) -> Result<Self, SplineError> {
+        let expected = self.control_points.len() - self.wrapped;
+        if weights.len() != expected {
+            return Err(SplineError::WeightCountMismatch {
+                expected,
+                found: weights.len(),
+            });
+        }
+        if weights.iter().any(|&w| w <= T::zero()) {
+            return Err(SplineError::NonPositiveWeight);
+        }
+        weights.extend_from_within(..self.wrapped);
+        self.weights = Some(weights);
+        Ok(self)
+    }
+
+    /// Exact circular arc of `radius` around `center`, starting at angle `start`
+    /// and sweeping counter-clockwise by `sweep` radians (negative goes clockwise).
+    /// Built from quadratic rational segments of at most 90 degrees each.
+    pub fn circular_arc(center: Vector2<T>, radius: T, start: T, sweep: T) -> Self {
+        let segments = to_f64((sweep.abs() / T::frac_pi_2()).ceil().max(T::one())) as usize;
+        let step = sweep / real(segments as f64);
+        let half_step = step / real(2.0);
+        let middle_weight = half_step.cos();
+        let on_circle = |angle: T| center + Vector2::new(angle.cos(), angle.sin()) * radius;
+
+        let mut control_points = vec![on_circle(start)];
+        let mut weights = vec![T::one()];
+        let mut knots = vec![T::zero(); 3];
+        for i in 0..segments {
+            let angle = start + step * real(i as f64);
+            // corner where the tangents at both ends of the segment meet
+            control_points.push(center + Vector2::new((angle + half_step).cos(), (angle + half_step).sin()) * (radius / middle_weight));
+            control_points.push(on_circle(angle + step));
+            weights.extend([middle_weight, T::one()]);
+            knots.extend([real::<T>((i + 1) as f64); 2]);
+        }
+        knots.push(real(segments as f64));
+
+        Self {
+            control_points,
+            weights: Some(weights),
+            degree: 2,
+            knots,
+            wrapped: 0,
+        }
+    }
+
+    /// Spline whose knots are spaced one apart and repeated `degree + 1` times at
+    /// both ends, so the curve starts and ends at the first and last control point.
+    pub fn clamped_uniform(control_points: Vec<Vector2<T>>, degree: usize) -> Result<Self, SplineError> {
+        let knots = clamped_uniform_knots(control_points.len(), degree);
+        Self::new(control_points, degree, knots)
+    }
+
+    /// Control points of the curve. Closed splines end with copies of their first
+    /// [`BSpline::wrapped`] control points.
+    pub fn control_points(&self) -> &[Vector2<T>] {
+        &self.control_points
+    }
+
+    /// Weights of a rational spline, `None` if all of them are one.
+    pub fn weights(&self) -> Option<&[T]> {
+        self.weights.as_deref()
+    }
+
+    pub fn degree(&self) -> usize {
+        self.degree
+    }
+
+    pub fn knots(&self) -> &[T] {
+        &self.knots
+    }
+
+    /// Same curve in another scalar type, e.g. `f64` for queries far from the
+    /// origin and back to `f32` for rendering the results.
+    pub fn cast<U: Real>(&self) -> BSpline<U> {
+        BSpline {
+            control_points: self.control_points.iter().map(|c| c.map(cast)).collect(),
+            weights: self.weights.as_ref().map(|weights| weights.iter().map(|&w| cast(w)).collect()),
+            degree: self.degree,
+            knots: self.knots.iter().map(|&knot| cast(knot)).collect(),
+            wrapped: self.wrapped,
+        }
+    }
+
+    /// Whether the spline was built as a loop, by [`BSpline::periodic`] or
+    /// [`BSpline::piecewise_bezier`].
+    pub fn is_closed(&self) -> bool {
+        self.wrapped > 0
+    }
+
+    /// How many of the first control points a closed spline repeats at its end,
+    /// `degree` for periodic splines and one for piecewise Bézier loops.
+    pub fn wrapped(&self) -> usize {
+        self.wrapped
+    }
+
+    /// Range of valid parameters.
+    pub fn domain(&self) -> (T, T) {
+        (self.knots[self.degree], self.knots[self.control_points.len()])
+    }
+
+    /// Parameters of the part of the curve that control point `i` shapes, the
+    /// spans `i..=i + degree` within the domain. The first control points of a
+    /// loop shape its end too, see [`BSpline::wrapped`].
+    pub fn support(&self, i: usize) -> (T, T) {
+        let (start, end) = self.domain();
+        let last = self.knots.len() - 1;
+        (self.knots[i.min(last)].max(start), self.knots[(i + self.degree + 1).min(last)].min(end))
+    }
+
+    /// Moves `u` into the domain, wrapping around for closed splines.
+    pub fn clamp_param(&self, u: T) -> T {
+        let (start, end) = self.domain();
+        if self.is_closed() {
+            start + rem_euclid(u - start, end - start)
+        } else {
+            u.clamp(start, end)
+        }
+    }
+
+    /// Index `l` of the knot span `knots[l] <= u < knots[l + 1]` that contains `u`,
+    /// clamped to the spans that carry the curve.
+    pub fn find_span(&self, u: T) -> usize {
+        let p = self.degree;
+        let n = self.control_points.len();
+        self.knots.partition_point(|&knot| knot <= u).saturating_sub(1).clamp(p, n - 1)
+    }
+
+    /// Point on the curve at `u`.
+    pub fn eval(&self, u: T) -> Vector2<T> {
+        self.derivative(u, 0)
+    }
+
+    /// `order`-th derivative with respect to `u`. Order zero is the curve itself.
+    pub fn derivative(&self, u: T, order: usize) -> Vector2<T> {
+        let l = self.find_span(u);
+        self.derivative_in_span(u, order, l)
+    }
+
+    /// Same as [`BSpline::derivative`] with a precomputed span from [`BSpline::find_span`].
+    pub fn derivative_in_span(&self, u: T, order: usize, l: usize) -> Vector2<T> {
+        let p = self.degree;
+        let base = l - p;
+        let Some(weights) = &self.weights else {
+            return local_derivative(&self.control_points[base..=l], &self.knots, u, order, l);
+        };
+
+        // derivatives of the curve in homogeneous coordinates (w * x, w * y, w)
+        let local = to_homogeneous(&self.control_points[base..=l], &weights[base..=l]);
+        let homogeneous: Vec<Vector3<T>> = (0..=order).map(|k| local_derivative(&local, &self.knots, u, k, l)).collect();
+
+        // quotient rule, C^(k) = (A^(k) - sum_i binom(k, i) w^(i) C^(k - i)) / w
+        let w = homogeneous[0].z;
+        let mut ders: Vec<Vector2<T>> = Vec::with_capacity(order + 1);
+        for k in 0..=order {
+            let mut der = homogeneous[k].xy();
+            for i in 1..=k {
+                der -= ders[k - i] * (binomial::<T>(k, i) * homogeneous[i].z);
+            }
+            ders.push(der / w);
+        }
+        ders[order]
+    }
+
+    /// Derivatives of orders `0..=degree` at `u` in homogeneous coordinates
+    /// `(w * x, w * y, w)`, using the polynomial of span `l`. Together they
+    /// describe the whole span, see [`crate::batch::SplineSampler`].
+    pub fn homogeneous_derivatives(&self, u: T, l: usize) -> [Vector3<T>; MAX_DEGREE + 1] {
+        let p = self.degree;
+        let mut local = [Vector3::zeros(); MAX_DEGREE + 1];
+        for (j, c) in self.control_points[l - p..=l].iter().enumerate() {
+            let w = self.weights.as_ref().map_or(T::one(), |weights| weights[l - p + j]);
+            local[j] = Vector3::new(c.x * w, c.y * w, w);
+        }
+        let mut ders = [Vector3::zeros(); MAX_DEGREE + 1];
+        for (k, der) in ders.iter_mut().enumerate().take(p + 1) {
+            *der = local_derivative(&local[..=p], &self.knots, u, k, l);
+        }
+        ders
+    }
+
+    /// Point, tangent, normal and curvature at `u`. Where the curve stops, e.g.
+    /// at a doubled control point, tangent and normal are zero.
+    pub fn frame(&self, u: T) -> Frame<T> {
+        let l = self.find_span(u);
+        let point = self.derivative_in_span(u, 0, l);
+        let d1 = self.derivative_in_span(u, 1, l);
+        let d2 = self.derivative_in_span(u, 2, l);
+
+        let speed = d1.norm();
+        if speed <= T::default_epsilon() {
+            return Frame {
+                point,
+                tangent: Vector2::zeros(),
+                normal: Vector2::zeros(),
+                curvature: T::zero(),
+            };
+        }
+        let tangent = d1 / speed;
+        // where both derivatives line up to within rounding the curve is
+        // straight, not bending ever so slightly
+        let bend = d1.perp(&d2);
+        let straight = bend.abs() <= T::default_epsilon().sqrt() * speed * d2.norm();
+        Frame {
+            point,
+            tangent,
+            normal: Vector2::new(-tangent.y, tangent.x),
+            curvature: if straight { T::zero() } else { bend / (speed * speed * speed) },
+        }
+    }
+
+    /// Unit tangent at `u`, see [`BSpline::frame`].
+    pub fn tangent(&self, u: T) -> Vector2<T> {
+        self.frame(u).tangent
+    }
+
+    /// Unit normal at `u`, the tangent rotated counter-clockwise.
+    pub fn normal(&self, u: T) -> Vector2<T> {
+        self.frame(u).normal
+    }
+
+    /// Signed curvature at `u`, positive where the curve turns counter-clockwise.
+    pub fn curvature(&self, u: T) -> T {
+        self.frame(u).curvature
+    }
+
+    /// Radius of curvature at `u`, infinite where the curve is straight.
+    pub fn radius_of_curvature(&self, u: T) -> T {
+        self.frame(u).radius_of_curvature()
+    }
+
+    /// Parameter of the point on the curve closest to `point`, see
+    /// [`BSpline::closest_point`].
+    pub fn nearest_param(&self, point: Vector2<T>) -> T {
+        self.closest_point(point).param
+    }
+
+    /// Point on the curve closest to `target`, the global minimum up to
+    /// [`CLOSEST_POINT_TOLERANCE`].
+    ///
+    /// Knot spans are visited nearest bounding box first and split into Bézier
+    /// pieces, which are subdivided until their control point boxes are either
+    /// farther away than the best point so far or smaller than the tolerance. A
+    /// few Newton steps polish the result. Deterministic, with no sampling.
+    pub fn closest_point(&self, target: Vector2<T>) -> ClosestPoint<T> {
+        let p = self.degree;
+        let mut spans: Vec<(T, usize)> = (p..self.control_points.len())
+            .filter(|&l| self.knots[l] < self.knots[l + 1])
+            .map(|l| (self.span_bounds(l).distance_squared(target), l))
+            .collect();
+        spans.sort_by(|a, b| compare(&a.0, &b.0));
+
+        let distance = T::max_value().expect("real numbers are bounded");
+        let mut best = ClosestPoint {
+            param: T::zero(),
+            point: Vector2::zeros(),
+            distance,
+            span: p,
+        };
+        for (lower_bound, l) in spans {
+            if lower_bound.sqrt() >= best.distance {
+                break;
+            }
+            self.closest_in_span(l, target, &mut best);
+        }
+        self.polish_closest(target, best)
+    }
+
+    /// Improves `best` with the closest point of knot span `l` if that is nearer.
+    pub(crate) fn closest_in_span(&self, l: usize, target: Vector2<T>, best: &mut ClosestPoint<T>) {
+        let segment = self.bezier_segment(l);
+        closest_in_bezier(&segment.points, segment.range, l, target, best, 24);
+    }
+
+    /// A few Newton steps on the squared distance, inside the span of `best`.
+    pub(crate) fn polish_closest(&self, target: Vector2<T>, mut best: ClosestPoint<T>) -> ClosestPoint<T> {
+        let (a, b) = (self.knots[best.span], self.knots[best.span + 1]);
+        let mut u = best.param;
+        for _ in 0..8 {
+            let diff = self.derivative_in_span(u, 0, best.span) - target;
+            let d1 = self.derivative_in_span(u, 1, best.span);
+            let d2 = self.derivative_in_span(u, 2, best.span);
+            let slope = diff.dot(&d1);
+            let curvature = d1.dot(&d1) + diff.dot(&d2);
+            if curvature <= T::default_epsilon() {
+                break;
+            }
+            u = (u - slope / curvature).clamp(a, b);
+        }
+        let point = self.derivative_in_span(u, 0, best.span);
+        let distance = (point - target).norm();
+        if distance < best.distance {
+            best = ClosestPoint {
+                param: u,
+                point,
+                distance,
+                span: best.span,
+            };
+        }
+        best
+    }
+
+    /// Bounding box of the part of the curve in knot span `l`.
+    pub fn span_bounds(&self, l: usize) -> Aabb<T> {
+        Aabb::from_points(self.control_points[l - self.degree..=l].iter().copied()).expect("span has control points")
+    }
+
+    /// Bounding box of the curve. By the convex hull property it is enough to
+    /// bound the control points.
+    pub fn bounds(&self) -> Aabb<T> {
+        Aabb::from_points(self.control_points.iter().copied()).expect("spline has control points")
+    }
+
+    /// Splits the curve into its polynomial pieces, one per non-empty knot span
+    /// of the domain.
+    pub fn bezier_segments(&self) -> Vec<BezierSegment<T>> {
+        (self.degree..self.control_points.len())
+            .filter(|&l| self.knots[l] < self.knots[l + 1])
+            .map(|l| self.bezier_segment(l))
+            .collect()
+    }
+
+    /// Bézier form of the knot span `l`, found by raising both of its knots to
+    /// multiplicity `degree` in a local copy of the `2 * degree + 2` knots that
+    /// affect it.
+    pub fn bezier_segment(&self, l: usize) -> BezierSegment<T> {
+        let p = self.degree;
+        let local = &self.control_points[l - p..=l];
+        let mut points: Vec<Vector3<T>> = match &self.weights {
+            Some(weights) => to_homogeneous(local, &weights[l - p..=l]),
+            None => local.iter().map(|c| c.push(T::one())).collect(),
+        };
+        let mut knots = self.knots[l - p..=l + p + 1].to_vec();
+        let (a, b) = (self.knots[l], self.knots[l + 1]);
+
+        for u in [a, b] {
+            while knots.iter().filter(|&&knot| knot == u).count() < p {
+                let insertion = KnotInsertion::new(&knots, p, u);
+                points = insertion.apply(&points);
+                knots = insertion.apply_knots(&knots);
+            }
+        }
+
+        // last occurrence of a, the span runs up to the first b
+        let first = knots.partition_point(|&knot| knot <= a) - 1 - p;
+        BezierSegment {
+            span: l,
+            range: (a, b),
+            points: points[first..=first + p].to_vec(),
+        }
+    }
+
+    /// How often the curve winds counter-clockwise around `point`. Open splines
+    /// are closed with a straight line from their end back to their start.
+    pub fn winding_number(&self, point: Vector2<T>) -> i32 {
+        let mut winding = 0;
+        let mut f = [T::zero(); MAX_DEGREE + 1];
+        let mut g = [T::zero(); MAX_DEGREE + 1];
+        for segment in self.bezier_segments() {
+            let k = segment.points.len();
+            for (i, c) in segment.points.iter().enumerate() {
+                f[i] = c.y - point.y * c.z;
+                g[i] = c.x - point.x * c.z;
+            }
+            winding += bezier_winding(&mut f[..k], &mut g[..k], 24);
+        }
+
+        if !self.is_closed() {
+            let (start, end) = self.domain();
+            let (a, b) = (self.eval(end) - point, self.eval(start) - point);
+            winding += bezier_winding(&mut [a.y, b.y], &mut [a.x, b.x], 24);
+        }
+        winding
+    }
+
+    /// Whether `point` lies inside the area enclosed by the curve, see
+    /// [`BSpline::winding_number`].
+    pub fn contains(&self, point: Vector2<T>) -> bool {
+        self.winding_number(point) != 0
+    }
+
+    /// Inserts the knot `u` without changing the shape of the curve and returns
+    /// the index of the control point that was added. Closed splines are left
+    /// alone and return `None`, as their last control points have to keep
+    /// repeating the first ones.
+    pub fn insert_knot(&mut self, u: T) -> Option<usize> {
+        if self.is_closed() {
+            return None;
+        }
+        let (start, end) = self.domain();
+        let insertion = KnotInsertion::new(&self.knots, self.degree, u.clamp(start, end));
+        match &self.weights {
+            Some(weights) => {
+                let refined = insertion.apply(&to_homogeneous(&self.control_points, weights));
+                let (points, weights) = from_homogeneous(&refined);
+                self.control_points = points;
+                self.weights = Some(weights);
+            }
+            None => self.control_points = insertion.apply(&self.control_points),
+        }
+        self.knots = insertion.apply_knots(&self.knots);
+        Some(insertion.span)
+    }
+
+    /// Removes one occurrence of the interior knot `u` if that moves the curve by
+    /// at most `tolerance`, and returns the index of the control point that was
+    /// dropped. Closed splines keep their knots, see [`BSpline::insert_knot`].
+    pub fn remove_knot(&mut self, u: T, tolerance: T) -> Option<usize> {
+        // last occurrence of u
+        let r = self.knots.partition_point(|&knot| knot <= u).checked_sub(1)?;
+        if self.knots[r] != u {
+            return None;
+        }
+        self.remove_knot_at(r, tolerance)
+    }
+
+    /// Removes every interior knot that can go without moving the curve by more
+    /// than `tolerance`, e.g. after an edit made a refined region flat again.
+    /// Returns how many knots were removed.
+    pub fn remove_redundant_knots(&mut self, tolerance: T) -> usize {
+        let mut removed = 0;
+        let mut r = self.control_points.len() - 1;
+        while r > self.degree {
+            if self.knots[r] != self.knots[r + 1] && self.remove_knot_at(r, tolerance).is_some() {
+                removed += 1;
+            }
+            r -= 1;
+        }
+        removed
+    }
+
+    fn remove_knot_at(&mut self, r: usize, tolerance: T) -> Option<usize> {
+        if self.is_closed() {
+            return None;
+        }
+        let removed = match &self.weights {
+            Some(weights) => {
+                let homogeneous = to_homogeneous(&self.control_points, weights);
+                let removal = KnotRemoval::new(&homogeneous, &self.knots, self.degree, r).filter(|removal| removal.error <= tolerance)?;
+                let (points, weights) = from_homogeneous(&removal.points);
+                self.control_points = points;
+                self.weights = Some(weights);
+                removal.removed
+            }
+            None => {
+                let removal = KnotRemoval::new(&self.control_points, &self.knots, self.degree, r).filter(|removal| removal.error <= tolerance)?;
+                self.control_points = removal.points;
+                removal.removed
+            }
+        };
+        self.knots.remove(r);
+        Some(removed)
+    }
+}
+
+/// Cumulative arc length of a curve at sampled parameters, for converting
+/// between parameters and distances along the curve. In between samples both
+/// are interpolated with cubic Hermite polynomials matching the curve's speed.
+#[derive(Debug, Clone)]
+pub struct ArcLengthTable<T: Real = f32> {
+    params: Vec<T>,
+    lengths: Vec<T>,
+    /// Speed `|C'(u)|` at each sample.
+    speeds: Vec<T>,
+    closed: bool,
+}
+
+impl<T: Real> Default for ArcLengthTable<T> {
+    fn default() -> Self {
+        Self {
+            params: Vec::new(),
+            lengths: Vec::new(),
+            speeds: Vec::new(),
+            closed: false,
+        }
+    }
+}
+
+impl<T: Real> ArcLengthTable<T> {
+    /// Samples every knot span of `curve` `samples_per_span` times and integrates
+    /// the speed in between with three point Gauss-Legendre quadrature.
+    pub fn new(curve: &BSpline<T>, samples_per_span: usize) -> Self {
+        const NODES: [(f64, f64); 3] = [
+            (-0.774_596_669_241_483_4, 5.0 / 9.0),
+            (0.0, 8.0 / 9.0),
+            (0.774_596_669_241_483_4, 5.0 / 9.0),
+        ];
+        let p = curve.degree();
+        let knots = curve.knots();
+        let samples = samples_per_span.max(1);
+        let half: T = real(0.5);
+
+        let mut table = Self {
+            closed: curve.is_closed(),
+            ..Self::default()
+        };
+        let mut length = T::zero();
+        for l in p..curve.control_points().len() {
+            let (a, b) = (knots[l], knots[l + 1]);
+            if a == b {
+                continue;
+            }
+            // every span starts with a sample of its own, the speed jumps at kinks
+            table.params.push(a);
+            table.lengths.push(length);
+            table.speeds.push(curve.derivative_in_span(a, 1, l).norm());
+            let step = (b - a) / real(samples as f64);
+            for i in 0..samples {
+                let mid = a + real::<T>(i as f64 + 0.5) * step;
+                let sum = NODES.iter().fold(T::zero(), |sum, &(x, w)| {
+                    sum + real::<T>(w) * curve.derivative_in_span(mid + half * step * real(x), 1, l).norm()
+                });
+                length += sum * half * step;
+                let u = a + real::<T>((i + 1) as f64) * step;
+                table.params.push(u);
+                table.lengths.push(length);
+                table.speeds.push(curve.derivative_in_span(u, 1, l).norm());
+            }
+        }
+        table
+    }
+
+    pub fn total_length(&self) -> T {
+        self.lengths.last().copied().unwrap_or(T::zero())
+    }
+
+    /// Distance along the curve from its start to the parameter `u`.
+    pub fn length_at_param(&self, u: T) -> T {
+        let (Some(&start), Some(&end)) = (self.params.first(), self.params.last()) else {
+            return T::zero();
+        };
+        let u = if self.closed && end > start {
+            start + rem_euclid(u - start, end - start)
+        } else {
+            u.clamp(start, end)
+        };
+        hermite(&self.params, &self.lengths, |i| self.speeds[i], u)
+    }
+
+    /// Parameter at distance `length` along the curve from its start. Closed
+    /// curves wrap around, open ones stop at their ends.
+    pub fn param_at_length(&self, length: T) -> T {
+        if self.params.is_empty() {
+            return T::zero();
+        }
+        let total = self.total_length();
+        let length = if self.closed && total > T::zero() {
+            rem_euclid(length, total)
+        } else {
+            length.clamp(T::zero(), total)
+        };
+        hermite(&self.lengths, &self.params, |i| T::one() / self.speeds[i], length)
+    }
+}
+
+/// Cubic Hermite interpolation through `(xs[i], ys[i])` with slopes `slope(i)`
+/// at `x`. `xs` and `ys` have to be non-decreasing and `xs` non-empty. Slopes are
+/// limited so the result stays monotone.
+fn hermite<T: Real>(xs: &[T], ys: &[T], slope: impl Fn(usize) -> T, x: T) -> T {
+    if xs.len() < 2 {
+        return ys[0];
+    }
+    let i = xs.partition_point(|&v| v <= x).clamp(1, xs.len() - 1);
+    let (x0, x1) = (xs[i - 1], xs[i]);
+    let (y0, y1) = (ys[i - 1], ys[i]);
+    let h = x1 - x0;
+    if h <= T::zero() {
+        return y0;
+    }
+    let t = ((x - x0) / h).clamp(T::zero(), T::one());
+    // Fritsch-Carlson, slopes of more than three times the secant overshoot
+    let (two, three): (T, T) = (real(2.0), real(3.0));
+    let limit = three * (y1 - y0);
+    let m0 = (slope(i - 1) * h).min(limit);
+    let m1 = (slope(i) * h).min(limit);
+    let (t2, t3) = (t * t, t * t * t);
+    (two * t3 - three * t2 + T::one()) * y0 + (t3 - two * t2 + t) * m0 + (three * t2 - two * t3) * y1 + (t3 - t2) * m1
+}
+
+/// Boehm's knot insertion. The new control points are blends of the old ones,
+/// so the same blend can be applied to any other per control point quantity
+/// (targets, rest positions, ..) to keep it in step with the curve.
+#[derive(Debug, Clone)]
+pub struct KnotInsertion<T: Real = f32> {
+    /// Span `knots[span] <= u < knots[span + 1]` the knot goes into. The new
+    /// control point ends up at this index.
+    pub span: usize,
+    pub knot: T,
+    degree: usize,
+    alphas: Vec<T>,
+}
+
+impl<T: Real> KnotInsertion<T> {
+    pub fn new(knots: &[T], degree: usize, u: T) -> Self {
+        let p = degree;
+        let n = knots.len() - p - 1;
+        let span = knots.partition_point(|&knot| knot <= u).saturating_sub(1).clamp(p, n - 1);
+        let alphas = (span + 1 - p..=span)
+            .map(|i| {
+                let denom = knots[i + p] - knots[i];
+                if denom > T::zero() {
+                    (u - knots[i]) / denom
+                } else {
+                    T::zero()
+                }
+            })
+            .collect();
+        Self {
+            span,
+            knot: u,
+            degree,
+            alphas,
+        }
+    }
+
+    /// Control points of the refined curve, one more than `points`. Rational
+    /// curves have to be refined in homogeneous coordinates, see [`to_homogeneous`].
+    pub fn apply<const D: usize>(&self, points: &[SVector<T, D>]) -> Vec<SVector<T, D>> {
+        let first = self.span + 1 - self.degree;
+        let mut refined = Vec::with_capacity(points.len() + 1);
+        refined.extend_from_slice(&points[..first]);
+        for (j, &alpha) in self.alphas.iter().enumerate() {
+            let i = first + j;
+            refined.push(points[i - 1] * (T::one() - alpha) + points[i] * alpha);
+        }
+        refined.extend_from_slice(&points[self.span..]);
+        refined
+    }
+
+    pub fn apply_knots(&self, knots: &[T]) -> Vec<T> {
+        let mut refined = knots.to_vec();
+        refined.insert(self.span + 1, self.knot);
+        refined
+    }
+}
+
+/// Tiller's removal of one occurrence of the knot `knots[r]`, which has to be the
+/// last of its repeats. Removal is only exact if the curve did not need the knot,
+/// `error` tells how far the control points had to move otherwise.
+#[derive(Debug, Clone)]
+pub struct KnotRemoval<const D: usize, T: Real = f32> {
+    pub points: Vec<SVector<T, D>>,
+    /// Index of the control point that was dropped.
+    pub removed: usize,
+    pub error: T,
+}
+
+impl<const D: usize, T: Real> KnotRemoval<D, T> {
+    /// Returns `None` if `knots[r]` is not an interior knot.
+    pub fn new(points: &[SVector<T, D>], knots: &[T], degree: usize, r: usize) -> Option<Self> {
+        let p = degree;
+        let n = points.len() - 1;
+        if r <= p || r > n || knots[r] == knots[r + 1] {
+            return None;
+        }
+        let u = knots[r];
+        let s = knots[..=r].iter().rev().take_while(|&&knot| knot == u).count();
+        if s > p {
+            return None;
+        }
+
+        let first = r - p;
+        let last = r - s;
+        let off = first - 1;
+        let mut temp = vec![SVector::zeros(); last + 2 - off];
+        temp[0] = points[off];
+        temp[last + 1 - off] = points[last + 1];
+
+        let alpha = |i: usize| (u - knots[i]) / (knots[i + p + 1] - knots[i]);
+
+        // solve for the new control points from both ends towards the middle
+        let (mut i, mut j) = (first, last);
+        let (mut ii, mut jj) = (1, last - off);
+        while j > i {
+            let alpha_i = alpha(i);
+            let alpha_j = alpha(j);
+            temp[ii] = (points[i] - temp[ii - 1] * (T::one() - alpha_i)) / alpha_i;
+            temp[jj] = (points[j] - temp[jj + 1] * alpha_j) / (T::one() - alpha_j);
+            i += 1;
+            ii += 1;
+            j -= 1;
+            jj -= 1;
+        }
+
+        // both ends have to agree where they meet
+        let error = if j < i {
+            (temp[ii - 1] - temp[jj + 1]).norm()
+        } else {
+            let alpha_i = alpha(i);
+            (points[i] - (temp[ii + 1] * alpha_i + temp[ii - 1] * (T::one() - alpha_i))).norm()
+        };
+
+        let mut new_points = points.to_vec();
+        let (mut i, mut j) = (first, last);
+        while j > i {
+            new_points[i] = temp[i - off];
+            new_points[j] = temp[j - off];
+            i += 1;
+            j -= 1;
+        }
+        let removed = (2 * r - s - p) / 2;
+        new_points.remove(removed);
+
+        Some(Self {
+            points: new_points,
+            removed,
+            error,
+        })
+    }
+}
+
+/// Knot vector `0, .., 0, 1, 2, .., n - p, .., n - p` with `degree + 1` repeated
+/// knots at each end for `count` control points.
+pub fn clamped_uniform_knots<T: Real>(count: usize, degree: usize) -> Vec<T> {
+    let inner = count.saturating_sub(degree);
+    let mut knots = Vec::with_capacity(count + degree + 1);
+    knots.extend(std::iter::repeat_n(T::zero(), degree + 1));
+    knots.extend((1..inner).map(|i| real::<T>(i as f64)));
+    knots.extend(std::iter::repeat_n(real::<T>(inner as f64), degree + 1));
+    knots
+}
+
+/// Values of the `degree + 1` basis functions that are non-zero at `u` in the
+/// knot span `l`, starting with `N_{l - degree}`. Points on the curve blend the
+/// span's control points with these.
+pub fn basis_functions<T: Real>(knots: &[T], degree: usize, u: T, l: usize) -> [T; MAX_DEGREE + 1] {
+    let mut n = [T::zero(); MAX_DEGREE + 1];
+    let mut left = [T::zero(); MAX_DEGREE + 1];
+    let mut right = [T::zero(); MAX_DEGREE + 1];
+    n[0] = T::one();
+    for j in 1..=degree {
+        left[j] = u - knots[l + 1 - j];
+        right[j] = knots[l + j] - u;
+        let mut saved = T::zero();
+        for r in 0..j {
+            let denominator = right[r + 1] + left[j - r];
+            let temp = if denominator != T::zero() { n[r] / denominator } else { T::zero() };
+            n[r] = saved + right[r + 1] * temp;
+            saved = left[j - r] * temp;
+        }
+        n[j] = saved;
+    }
+    n
+}
+
+/// Distance from `point` to the segment from `a` to `b`.
+pub fn distance_to_segment<T: Real>(point: Vector2<T>, a: Vector2<T>, b: Vector2<T>) -> T {
+    let ab = b - a;
+    let t = if ab.norm_squared() > T::zero() {
+        ((point - a).dot(&ab) / ab.norm_squared()).clamp(T::zero(), T::one())
+    } else {
+        T::zero()
+    };
+    (a + ab * t - point).norm()
+}
+
+/// Lifts control points into homogeneous coordinates `(w * x, w * y, w)`, where a
+/// rational curve is an ordinary B-spline.
+pub fn to_homogeneous<T: Real>(points: &[Vector2<T>], weights: &[T]) -> Vec<Vector3<T>> {
+    points.iter().zip(weights).map(|(p, &w)| Vector3::new(p.x * w, p.y * w, w)).collect()
+}
+
+/// Inverse of [`to_homogeneous`], returns the control points and their weights.
+pub fn from_homogeneous<T: Real>(points: &[Vector3<T>]) -> (Vec<Vector2<T>>, Vec<T>) {
+    points.iter().map(|p| (p.xy() / p.z, p.z)).unzip()
+}
+
+/// `order`-th derivative of the non-rational curve with the `p + 1` local control
+/// points `points` of the span `l`.
+fn local_derivative<const D: usize, T: Real>(points: &[SVector<T, D>], t: &[T], u: T, order: usize, l: usize) -> SVector<T, D> {
+    let p = points.len() - 1;
+    if order > p {
+        return SVector::zeros();
+    }
+    let base = l - p;
+    let mut temp = [SVector::zeros(); MAX_DEGREE + 1];
+    temp[..=p].copy_from_slice(points);
+
+    // control points of the derivative curve, degree drops by one per order
+    for k in 1..=order {
+        for j in 0..=p - k {
+            let i = base + j;
+            let dt = t[i + p + 1] - t[i + k];
+            temp[j] = if dt > T::zero() {
+                (temp[j + 1] - temp[j]) * (real::<T>((p - k + 1) as f64) / dt)
+            } else {
+                SVector::zeros()
+            };
+        }
+    }
+
+    de_boor(&mut temp[..=p - order], t, u, l)
+}
+
+/// Branch and bound search for the point of a homogeneous Bézier curve closest
+/// to `target`, improving on `best`.
+fn closest_in_bezier<T: Real>(points: &[Vector3<T>], range: (T, T), span: usize, target: Vector2<T>, best: &mut ClosestPoint<T>, depth: usize) {
+    let tolerance: T = real(CLOSEST_POINT_TOLERANCE as f64);
+    let half: T = real(0.5);
+    let mut projected = [Vector2::zeros(); MAX_DEGREE + 1];
+    for (p, c) in projected.iter_mut().zip(points) {
+        *p = c.xy() / c.z;
+    }
+    let projected = &projected[..points.len()];
+    let bounds = Aabb::from_points(projected.iter().copied()).expect("segment has control points");
+    if bounds.distance_squared(target).sqrt() + tolerance >= best.distance {
+        return;
+    }
+
+    // the curve passes through its end points
+    for (point, param) in [(projected[0], range.0), (projected[projected.len() - 1], range.1)] {
+        let distance = (point - target).norm();
+        if distance < best.distance {
+            *best = ClosestPoint {
+                param,
+                point,
+                distance,
+                span,
+            };
+        }
+    }
+
+    // once the control points hug their chord, so does the curve
+    let (a, b) = (projected[0], projected[projected.len() - 1]);
+    let chord = b - a;
+    let on_chord = |point: Vector2<T>| {
+        let length = chord.norm_squared();
+        let t = if length > T::zero() {
+            ((point - a).dot(&chord) / length).clamp(T::zero(), T::one())
+        } else {
+            T::zero()
+        };
+        (t, a + chord * t)
+    };
+    let flat = projected[1..projected.len() - 1].iter().all(|&c| (on_chord(c).1 - c).norm() < tolerance);
+    if depth == 0 || flat {
+        // the curve is within tolerance of the chord, skip it unless it can win
+        let (along, nearest) = on_chord(target);
+        if (nearest - target).norm() >= best.distance + tolerance {
+            return;
+        }
+
+        // parameter whose point projects onto the chord where the target does
+        let (mut lo, mut hi) = (T::zero(), T::one());
+        for _ in 0..24 {
+            let mid = half * (lo + hi);
+            if on_chord(bezier_point(points, mid)).0 < along {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let t = half * (lo + hi);
+        let point = bezier_point(points, t);
+        let distance = (point - target).norm();
+        if distance < best.distance {
+            *best = ClosestPoint {
+                param: range.0 + t * (range.1 - range.0),
+                point,
+                distance,
+                span,
+            };
+        }
+        return;
+    }
+
+    let k = points.len();
+    let mut left = [Vector3::zeros(); MAX_DEGREE + 1];
+    let mut right = [Vector3::zeros(); MAX_DEGREE + 1];
+    halve_bezier(points, &mut left[..k], &mut right[..k]);
+
+    let mid = half * (range.0 + range.1);
+    let halves = [(&left[..k], (range.0, mid)), (&right[..k], (mid, range.1))];
+    // nearer half first so the other one is more likely to be pruned
+    let near_first = (projected[0] - target).norm_squared() <= (projected[k - 1] - target).norm_squared();
+    let order = if near_first { [0, 1] } else { [1, 0] };
+    for i in order {
+        closest_in_bezier(halves[i].0, halves[i].1, span, target, best, depth - 1);
+    }
+}
+
+/// De Casteljau subdivision of a homogeneous Bézier curve at one half into
+/// `left` and `right`, which have to be as long as `points`.
+pub(crate) fn halve_bezier<T: Real>(points: &[Vector3<T>], left: &mut [Vector3<T>], right: &mut [Vector3<T>]) {
+    let k = points.len();
+    let mut temp = [Vector3::zeros(); MAX_DEGREE + 1];
+    temp[..k].copy_from_slice(points);
+    left[0] = temp[0];
+    right[k - 1] = temp[k - 1];
+    for r in 1..k {
+        for j in 0..k - r {
+            temp[j] = (temp[j] + temp[j + 1]) * real::<T>(0.5);
+        }
+        left[r] = temp[0];
+        right[k - 1 - r] = temp[k - 1 - r];
+    }
+}
+
+/// Point at `t` in `0..=1` of a Bézier curve with homogeneous control points.
+pub(crate) fn bezier_point<T: Real>(points: &[Vector3<T>], t: T) -> Vector2<T> {
+    let k = points.len();
+    let mut temp = [Vector3::zeros(); MAX_DEGREE + 1];
+    temp[..k].copy_from_slice(points);
+    for r in 1..k {
+        for j in 0..k - r {
+            temp[j] = temp[j] * (T::one() - t) + temp[j + 1] * t;
+        }
+    }
+    temp[0].xy() / temp[0].z
+}
+
+/// Signed number of times a Bézier curve crosses the ray from the origin along
+/// +x, upwards counting positive. `f` and `g` are the Bernstein coefficients of
+/// its y and x coordinate. Subdivides until the curve is entirely on one side of
+/// the ray's line or entirely right of the origin, where only the ends matter.
+fn bezier_winding<T: Real>(f: &mut [T], g: &mut [T], depth: usize) -> i32 {
+    let above = |v: T| v >= T::zero();
+    if f.iter().all(|&v| above(v)) || f.iter().all(|&v| !above(v)) || g.iter().all(|&v| v < T::zero()) {
+        return 0;
+    }
+    if depth == 0 || g.iter().all(|&v| v >= T::zero()) {
+        return above(f[f.len() - 1]) as i32 - above(f[0]) as i32;
+    }
+
+    let k = f.len();
+    let mut f_right = [T::zero(); MAX_DEGREE + 1];
+    let mut g_right = [T::zero(); MAX_DEGREE + 1];
+    split_bernstein(f, &mut f_right[..k]);
+    split_bernstein(g, &mut g_right[..k]);
+    bezier_winding(f, g, depth - 1) + bezier_winding(&mut f_right[..k], &mut g_right[..k], depth - 1)
+}
+
+/// De Casteljau subdivision at one half. `left` is replaced by the first half and
+/// `right` receives the second.
+fn split_bernstein<T: Real>(left: &mut [T], right: &mut [T]) {
+    let k = left.len();
+    let mut temp = [T::zero(); MAX_DEGREE + 1];
+    temp[..k].copy_from_slice(left);
+    right[k - 1] = temp[k - 1];
+    for r in 1..k {
+        for j in 0..k - r {
+            temp[j] = (temp[j] + temp[j + 1]) * real::<T>(0.5);
+        }
+        left[r] = temp[0];
+        right[k - 1 - r] = temp[k - 1 - r];
+    }
+}
+
+fn binomial<T: Real>(n: usize, k: usize) -> T {
+    (0..k).fold(T::one(), |acc, i| acc * real((n - i) as f64) / real((i + 1) as f64))
+}
+
+/// De Boor's algorithm on the `q + 1` control points in `d` of a degree `q` curve
+/// whose last basis function in the span `l` is `N_{l, q}`.
+fn de_boor<const D: usize, T: Real>(d: &mut [SVector<T, D>], t: &[T], u: T, l: usize) -> SVector<T, D> {
+    let q = d.len() - 1;
+    for r in 1..=q {
+        for j in (r..=q).rev() {
+            let i = l + j - q;
+            let denom = t[i + q + 1 - r] - t[i];
+            let alpha = if denom > T::zero() { (u - t[i]) / denom } else { T::zero() };
+            d[j] = d[j - 1] * (T::one() - alpha) + d[j] * alpha;
+        }
+    }
+    d[q]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn control_points_only_move_the_curve_within_their_support() {
+        let points: Vec<_> = (0..8).map(|i| Vector2::new(100.0 * i as f32, 0.0)).collect();
+        let curve = BSpline::clamped_uniform(points.clone(), 3).unwrap();
+        let (start, end) = curve.domain();
+        for i in 0..points.len() {
+            let mut moved = points.clone();
+            moved[i].y = 50.0;
+            let other = BSpline::clamped_uniform(moved, 3).unwrap();
+            let (low, high) = curve.support(i);
+            assert!(start <= low && low < high && high <= end, "{i} {low} {high}");
+            assert!((curve.eval(0.5 * (low + high)) - other.eval(0.5 * (low + high))).norm() > 1.0, "{i}");
+            for k in 0..=200 {
+                let u = start + (end - start) * k as f32 / 200.0;
+                if (curve.eval(u) - other.eval(u)).norm() > 1e-4 {
+                    assert!(low <= u && u <= high, "{i} {u} {low} {high}");
+                }
+            }
+        }
+    }
+
+    /// Closest of `samples` points spaced evenly in parameter.
+    fn brute_force(curve: &BSpline, target: Vector2<f32>, samples: usize) -> f32 {
+        let (start, end) = curve.domain();
+        (0..=samples)
+            .map(|i| (curve.eval(start + (end - start) * i as f32 / samples as f32) - target).norm())
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    fn random_curve(rng: &mut StdRng) -> BSpline {
+        let degree = rng.gen_range(1..=4);
+        let count = rng.gen_range(degree + 1..16);
+        let points = (0..count)
+            .map(|_| Vector2::new(rng.gen_range(-100.0..100.0), rng.gen_range(-100.0..100.0)))
+            .collect();
+        let curve = if rng.gen_bool(0.3) {
+            BSpline::periodic(points, degree).unwrap()
+        } else {
+            BSpline::clamped_uniform(points, degree).unwrap()
+        };
+        if rng.gen_bool(0.3) {
+            let weights = (0..count).map(|_| rng.gen_range(0.5..3.0)).collect();
+            curve.with_weights(weights).unwrap()
+        } else {
+            curve
+        }
+    }
+
+    /// Cubic zigzag whose turns are much sharper than the spacing of its spans.
+    fn s_curve(turns: usize, height: f32) -> BSpline {
+        let points = (0..turns)
+            .map(|i| Vector2::new(i as f32, if i % 2 == 0 { height } else { -height }))
+            .collect();
+        BSpline::clamped_uniform(points, 3).unwrap()
+    }
+
+    /// Random curves, each with targets around it.
+    pub(crate) fn random_cases() -> Vec<(BSpline, Vec<Vector2<f32>>)> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..200)
+            .map(|_| {
+                let curve = random_curve(&mut rng);
+                let targets = (0..10)
+                    .map(|_| Vector2::new(rng.gen_range(-150.0..150.0), rng.gen_range(-150.0..150.0)))
+                    .collect();
+                (curve, targets)
+            })
+            .collect()
+    }
+
+    /// Tight S-curves of several heights, each with targets around it.
+    pub(crate) fn s_curve_cases() -> Vec<(BSpline, Vec<Vector2<f32>>)> {
+        let mut rng = StdRng::seed_from_u64(11);
+        [0.5, 5.0, 50.0]
+            .into_iter()
+            .map(|height| {
+                let targets = (0..200)
+                    .map(|_| Vector2::new(rng.gen_range(-1.0..12.0), rng.gen_range(-1.5..1.5) * height))
+                    .collect();
+                (s_curve(12, height), targets)
+            })
+            .collect()
+    }
+
+    /// Asserts that `closest` is on `curve`, no farther from `target` than the
+    /// best of dense sampling, and that its span contains its parameter.
+    pub(crate) fn check_closest(curve: &BSpline, target: Vector2<f32>, closest: ClosestPoint) {
+        // sampling only finds points on the curve, so never beats the true minimum
+        let scale = curve.bounds().max - curve.bounds().min;
+        let slack = 1e-4 * scale.amax().max(1.0);
+        let brute = brute_force(curve, target, 20_000);
+        assert!(closest.distance <= brute + slack, "{closest:?} farther than {brute} from {target}");
+        assert!((closest.point - curve.eval(closest.param)).norm() <= slack, "{closest:?} off the curve");
+        assert!(((closest.point - target).norm() - closest.distance).abs() <= slack);
+        let knots = curve.knots();
+        assert!(
+            knots[closest.span] <= closest.param && closest.param <= knots[closest.span + 1],
+            "{closest:?} outside its span {}..{}",
+            knots[closest.span],
+            knots[closest.span + 1]
+        );
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force_on_random_curves() {
+        for (curve, targets) in random_cases() {
+            for target in targets {
+                check_closest(&curve, target, curve.closest_point(target));
+            }
+        }
+    }
+
+    #[test]
+    fn closest_point_matches_brute_force_on_tight_s_curves() {
+        for (curve, targets) in s_curve_cases() {
+            for target in targets {
+                check_closest(&curve, target, curve.closest_point(target));
+            }
+        }
+    }
+
+    /// Largest distance between the two curves at the same parameters.
+    fn max_deviation(a: &BSpline, b: &BSpline) -> f32 {
+        let (start, end) = a.domain();
+        (0..=500)
+            .map(|i| start + (end - start) * i as f32 / 500.0)
+            .map(|u| (a.eval(u) - b.eval(u)).norm())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn knot_insertion_keeps_the_shape() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for (curve, _) in random_cases() {
+            let (start, end) = curve.domain();
+            let mut refined = curve.clone();
+            for _ in 0..5 {
+                let inserted = refined.insert_knot(rng.gen_range(start..end));
+                assert_eq!(inserted.is_some(), !curve.is_closed());
+            }
+            if curve.is_closed() {
+                assert_eq!(refined.knots(), curve.knots());
+                continue;
+            }
+            assert_eq!(refined.control_points().len(), curve.control_points().len() + 5);
+            assert!(max_deviation(&curve, &refined) < 1e-3, "{curve:?} moved to {refined:?}");
+        }
+    }
+
+    #[test]
+    fn removing_an_inserted_knot_restores_the_curve() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for (curve, _) in random_cases().into_iter().filter(|(curve, _)| !curve.is_closed()) {
+            let (start, end) = curve.domain();
+            let u = rng.gen_range(start..end);
+            let mut edited = curve.clone();
+            edited.insert_knot(u).unwrap();
+            assert!(edited.remove_knot(u, 1e-2).is_some(), "{u} could not be removed from {edited:?}");
+
+            assert_eq!(edited.knots(), curve.knots());
+            for (a, b) in edited.control_points().iter().zip(curve.control_points()) {
+                assert!((a - b).norm() < 1e-2, "{a} is not {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn circular_arc_lies_on_its_circle() {
+        let center = Vector2::new(30.0, -20.0);
+        for (start, sweep) in [(0.0, 1.0), (0.3, std::f32::consts::PI), (-1.0, -2.5), (2.0, std::f32::consts::TAU)] {
+            let arc = BSpline::circular_arc(center, 50.0, start, sweep);
+            let (first, last) = arc.domain();
+            for i in 0..=200 {
+                let point = arc.eval(first + (last - first) * i as f32 / 200.0);
+                assert!(
+                    ((point - center).norm() - 50.0).abs() < 1e-3,
+                    "{point} off the circle for {start} + {sweep}"
+                );
+            }
+            let on_circle = |angle: f32| center + 50.0 * Vector2::new(angle.cos(), angle.sin());
+            assert!((arc.eval(first) - on_circle(start)).norm() < 1e-3);
+            assert!((arc.eval(last) - on_circle(start + sweep)).norm() < 1e-3);
+            // halfway along the parameters of a segment is halfway along its angle
+            let segments = arc.knots().windows(2).filter(|w| w[0] < w[1]).count();
+            let step = sweep / segments as f32;
+            assert!((arc.eval(first + 0.5) - on_circle(start + 0.5 * step)).norm() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn arc_curvature_is_one_over_its_radius_signed_by_its_turn() {
+        let center = Vector2::new(30.0, -20.0);
+        for (sweep, turn) in [(2.5, 1.0), (-2.5, -1.0)] {
+            let arc = BSpline::circular_arc(center, 50.0, 0.3, sweep);
+            let (first, last) = arc.domain();
+            for i in 0..=50 {
+                let frame = arc.frame(first + (last - first) * i as f32 / 50.0);
+                assert!((frame.curvature - turn / 50.0).abs() < 1e-5, "{sweep} {frame:?}");
+                let (circle_center, radius) = frame.osculating_circle().unwrap();
+                assert!((circle_center - center).norm() < 1e-2, "{sweep} {circle_center}");
+                assert!((radius - 50.0).abs() < 1e-2, "{sweep} {radius}");
+            }
+        }
+    }
+
+    #[test]
+    fn straight_segment_has_no_curvature() {
+        // unevenly spaced, so the curve speeds up along the line
+        let points = [0.0, 0.1, 0.5, 2.0, 2.2, 3.0].map(|t| Vector2::new(70.0, 30.0) * t).to_vec();
+        let line = BSpline::clamped_uniform(points, 3).unwrap();
+        let (first, last) = line.domain();
+        for i in 0..=50 {
+            let frame = line.frame(first + (last - first) * i as f32 / 50.0);
+            assert_eq!(frame.curvature, 0.0, "{frame:?}");
+            assert_eq!(frame.osculating_circle(), None);
+            assert_eq!(frame.radius_of_curvature(), f32::INFINITY);
+        }
+    }
+
+    #[test]
+    fn tangent_and_normal_are_orthonormal() {
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..50 {
+            let curve = random_curve(&mut rng);
+            let (first, last) = curve.domain();
+            for i in 0..=50 {
+                let Frame { tangent, normal, .. } = curve.frame(first + (last - first) * i as f32 / 50.0);
+                if tangent == Vector2::zeros() {
+                    continue;
+                }
+                assert!((tangent.norm() - 1.0).abs() < 1e-5, "{tangent}");
+                assert!((normal.norm() - 1.0).abs() < 1e-5, "{normal}");
+                assert!(tangent.dot(&normal).abs() < 1e-5, "{tangent} {normal}");
+                // counter-clockwise from the tangent
+                assert!((tangent.perp(&normal) - 1.0).abs() < 1e-5, "{tangent} {normal}");
+            }
+        }
+    }
+
+    #[test]
+    fn winding_number_inside_outside_and_near_a_circle() {
+        use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+        let circle = BSpline::circular_arc(Vector2::zeros(), 50.0, 0.0, TAU);
+        let clockwise = BSpline::circular_arc(Vector2::zeros(), 50.0, 0.0, -TAU);
+        let twice = BSpline::circular_arc(Vector2::zeros(), 50.0, 0.0, 2.0 * TAU);
+        assert_eq!(circle.winding_number(Vector2::zeros()), 1);
+        assert_eq!(clockwise.winding_number(Vector2::zeros()), -1);
+        assert_eq!(twice.winding_number(Vector2::new(10.0, 5.0)), 2);
+        assert!(!circle.contains(Vector2::new(60.0, 0.0)));
+
+        // just inside and outside, including level with the joints of the segments
+        for i in 0..64 {
+            let angle = i as f32 * TAU / 64.0;
+            let direction = Vector2::new(angle.cos(), angle.sin());
+            assert!(circle.contains(49.99 * direction), "{angle}");
+            assert!(!circle.contains(50.01 * direction), "{angle}");
+        }
+        assert!(circle.contains(Vector2::new(0.5, 49.99)));
+        assert!(circle.contains(Vector2::new(-0.5, -49.99)));
+
+        // open curves are closed by a line from their end back to their start
+        let half = BSpline::circular_arc(Vector2::zeros(), 50.0, 0.0, PI);
+        assert!(half.contains(Vector2::new(0.0, 25.0)));
+        assert!(!half.contains(Vector2::new(0.0, -25.0)));
+        assert!(half.contains(Vector2::new(20.0, 0.01)));
+        assert!(!half.contains(Vector2::new(20.0, -0.01)));
+        let quarter = BSpline::circular_arc(Vector2::zeros(), 50.0, FRAC_PI_2, -FRAC_PI_2);
+        assert_eq!(quarter.winding_number(Vector2::new(30.0, 30.0)), -1);
+    }
+
+    /// Length of the curve from the start of its domain to `u`, summed over
+    /// short chords.
+    fn polyline_length(curve: &BSpline, u: f32) -> f32 {
+        let (start, _) = curve.domain();
+        let points: Vec<_> = (0..=20_000).map(|i| curve.eval(start + (u - start) * i as f32 / 20_000.0)).collect();
+        points.windows(2).map(|w| (w[1] - w[0]).norm()).sum()
+    }
+
+    #[test]
+    fn arc_length_matches_the_integrated_length() {
+        let circle = BSpline::circular_arc(Vector2::zeros(), 50.0, 0.0, std::f32::consts::TAU);
+        let table = ArcLengthTable::new(&circle, 8);
+        assert!(
+            (table.total_length() - 100.0 * std::f32::consts::PI).abs() < 1e-2,
+            "{}",
+            table.total_length()
+        );
+
+        let mut rng = StdRng::seed_from_u64(17);
+        for (curve, _) in random_cases().into_iter().take(40) {
+            let table = ArcLengthTable::new(&curve, 8);
+            let (start, end) = curve.domain();
+            let total = polyline_length(&curve, end);
+            assert!(
+                (table.total_length() - total).abs() <= 1e-3 * total,
+                "{} vs {total} for {curve:?}",
+                table.total_length()
+            );
+
+            let u = rng.gen_range(start..end);
+            let length = polyline_length(&curve, u);
+            assert!(
+                (table.length_at_param(u) - length).abs() <= 1e-3 * total,
+                "{} vs {length} at {u}",
+                table.length_at_param(u)
+            );
+            assert!(
+                (table.param_at_length(length) - u).abs() <= 1e-2 * (end - start),
+                "{} vs {u}",
+                table.param_at_length(length)
+            );
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use crate::physics_plugin::{BodyShape, Collider, Gravitate, GrindEnded, GrindStarted, Grinding, PhysicsConfig, VerletObject};
+use crate::spines_plugin::{Position, Spline, SplineBvh, SplineCast, SplineCrossings, SplineCurve, SplineJunctions};
+use bevy::color::palettes::css;
+use bevy::prelude::*;
+use nalgebra::Vector2;
+
+/// Draws what splines and the physics are doing with gizmos, see
+/// [`SplineDebugLayers`]. F3 turns it on and off.
+pub struct SplineDebugPlugin;
+
+impl Plugin for SplineDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SplineDebugLayers>();
+        app.add_systems(Update, (toggle_debug, log_grinds));
+        app.add_systems(
+            Update,
+            (draw_splines, draw_nearest_points, draw_contacts, draw_intersections, draw_landing)
+                .after(toggle_debug)
+                .run_if(debug_enabled),
+        );
+    }
+}
+
+/// Which layers [`SplineDebugPlugin`] draws. Nothing is drawn while `enabled`
+/// is off.
+#[derive(Resource, Debug, Clone)]
+pub struct SplineDebugLayers {
+    pub enabled: bool,
+    pub control_polygons: bool,
+    pub knots: bool,
+    pub normals: bool,
+    pub curvature_combs: bool,
+    pub span_bounds: bool,
+    /// Line from every `VerletObject` to the closest point on any spline.
+    pub nearest_points: bool,
+    /// The `Collision`s of the last physics step.
+    pub contacts: bool,
+    /// Where splines cross each other, with both tangents, see
+    /// `SplineJunctions`, and where they cross themselves, with the ends of any
+    /// stretch they share, see `SplineCrossings`.
+    pub intersections: bool,
+    /// Where airborne `VerletObject`s will come down, and the spline right
+    /// below them.
+    pub landing: bool,
+    /// Logs when bodies start and stop grinding.
+    pub grinds: bool,
+    /// Length of a comb tooth per unit of curvature.
+    pub comb_scale: f32,
+}
+
+impl Default for SplineDebugLayers {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            control_polygons: true,
+            knots: true,
+            normals: true,
+            curvature_combs: true,
+            span_bounds: false,
+            nearest_points: true,
+            contacts: true,
+            intersections: false,
+            landing: true,
+            grinds: true,
+            comb_scale: 2000.0,
+        }
+    }
+}
+
+/// Normals drawn per knot span.
+const NORMAL_SAMPLES: usize = 2;
+
+/// Teeth of the curvature comb per knot span.
+const COMB_SAMPLES: usize = 8;
+
+const NORMAL_LENGTH: f32 = 20.0;
+
+const MARKER_RADIUS: f32 = 4.0;
+
+/// Time step, in seconds, of the predicted flight of an airborne body.
+const LANDING_STEP: f32 = 0.05;
+
+/// Steps of a predicted flight that doesn't come down.
+const LANDING_STEPS: usize = 60;
+
+/// How far below a body the spline under it is looked for.
+const SHADOW_LENGTH: f32 = 2000.0;
+
+fn toggle_debug(keys: Res<ButtonInput<KeyCode>>, mut layers: ResMut<SplineDebugLayers>) {
+    if keys.just_pressed(KeyCode::F3) {
+        layers.enabled = !layers.enabled;
+    }
+}
+
+fn debug_enabled(layers: Res<SplineDebugLayers>) -> bool {
+    layers.enabled
+}
+
+fn vec2(v: Vector2<f32>) -> Vec2 {
+    Vec2::new(v.x, v.y)
+}
+
+fn draw_splines(mut gizmos: Gizmos, layers: Res<SplineDebugLayers>, query: Query<&SplineCurve, With<Spline>>) {
+    for curve in &query {
+        let Some(curve) = &curve.0 else {
+            continue;
+        };
+        let knots = curve.knots();
+        let (start, end) = curve.domain();
+        // spans of the domain, the ones of a loop's wrapped control points included
+        let spans = (curve.degree()..curve.control_points().len()).filter(|&l| knots[l] < knots[l + 1]);
+
+        if layers.control_polygons {
+            gizmos.linestrip_2d(curve.control_points().iter().copied().map(vec2), css::GRAY);
+            for &point in curve.control_points() {
+                gizmos.circle_2d(vec2(point), MARKER_RADIUS, css::GRAY);
+            }
+        }
+        if layers.knots {
+            for (i, &knot) in knots.iter().enumerate() {
+                if start <= knot && knot <= end && (i == 0 || knots[i - 1] != knot) {
+                    gizmos.circle_2d(vec2(curve.eval(knot)), MARKER_RADIUS, css::ORANGE);
+                }
+            }
+        }
+
+        for l in spans {
+            let (a, b) = (knots[l], knots[l + 1]);
+            if layers.span_bounds {
+                let bounds = curve.span_bounds(l);
+                gizmos.rect_2d(vec2(0.5 * (bounds.min + bounds.max)), vec2(bounds.max - bounds.min), css::DARK_CYAN);
+            }
+            if layers.normals {
+                for i in 0..NORMAL_SAMPLES {
+                    let frame = curve.frame(a + (b - a) * i as f32 / NORMAL_SAMPLES as f32);
+                    gizmos.line_2d(vec2(frame.point), vec2(frame.point + NORMAL_LENGTH * frame.normal), css::LIME);
+                }
+            }
+            if layers.curvature_combs {
+                // teeth point away from the center of curvature
+                let tips: Vec<Vec2> = (0..=COMB_SAMPLES)
+                    .map(|i| {
+                        let frame = curve.frame(a + (b - a) * i as f32 / COMB_SAMPLES as f32);
+                        let tip = vec2(frame.point - layers.comb_scale * frame.curvature * frame.normal);
+                        gizmos.line_2d(vec2(frame.point), tip, css::MEDIUM_PURPLE);
+                        tip
+                    })
+                    .collect();
+                gizmos.linestrip_2d(tips, css::MEDIUM_PURPLE);
+            }
+        }
+    }
+}
+
+fn draw_nearest_points(
+    mut gizmos: Gizmos,
+    layers: Res<SplineDebugLayers>,
+    object_query: Query<&Position, With<VerletObject>>,
+    spline_query: Query<(&SplineCurve, &SplineBvh), With<Spline>>,
+) {
+    if !layers.nearest_points {
+        return;
+    }
+    for object in &object_query {
+        let nearest = spline_query
+            .iter()
+            .filter_map(|(curve, bvh)| {
+                let curve = curve.0.as_ref()?;
+                let closest = bvh.fits(curve).then(|| bvh.closest_point(curve, object.0)).flatten();
+                Some(closest.unwrap_or_else(|| curve.closest_point(object.0)))
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance));
+        if let Some(nearest) = nearest {
+            gizmos.line_2d(vec2(object.0), vec2(nearest.point), css::YELLOW);
+            gizmos.circle_2d(vec2(nearest.point), MARKER_RADIUS, css::YELLOW);
+        }
+    }
+}
+
+fn draw_contacts(mut gizmos: Gizmos, layers: Res<SplineDebugLayers>, query: Query<&Collider>) {
+    if !layers.contacts {
+        return;
+    }
+    for collider in &query {
+        for collision in &collider.collisions {
+            gizmos.circle_2d(vec2(collision.point), MARKER_RADIUS, css::RED);
+            gizmos.line_2d(vec2(collision.point), vec2(collision.point + NORMAL_LENGTH * collision.normal), css::RED);
+        }
+    }
+}
+
+fn draw_intersections(
+    mut gizmos: Gizmos,
+    layers: Res<SplineDebugLayers>,
+    query: Query<(Entity, &SplineCurve, &SplineCrossings, &SplineJunctions), With<Spline>>,
+) {
+    if !layers.intersections {
+        return;
+    }
+    for (entity, curve, crossings, junctions) in &query {
+        let Some(curve) = &curve.0 else {
+            continue;
+        };
+        for crossing in &crossings.0 {
+            gizmos.circle_2d(vec2(crossing.point), MARKER_RADIUS, css::FUCHSIA);
+            if let Some((end, _)) = crossing.overlap {
+                gizmos.line_2d(vec2(crossing.point), vec2(curve.eval(end)), css::FUCHSIA);
+                gizmos.circle_2d(vec2(curve.eval(end)), MARKER_RADIUS, css::FUCHSIA);
+            }
+        }
+        // both splines list the junction, the one listed first draws it with
+        // the tangents of both curves
+        for junction in junctions.0.iter().filter(|junction| entity < junction.other) {
+            let Some(other) = query.get(junction.other).ok().and_then(|(_, other, ..)| other.0.as_ref()) else {
+                continue;
+            };
+            gizmos.circle_2d(vec2(junction.point), MARKER_RADIUS, css::FUCHSIA);
+            for tangent in [curve.tangent(junction.param), other.tangent(junction.other_param)] {
+                gizmos.line_2d(
+                    vec2(junction.point - NORMAL_LENGTH * tangent),
+                    vec2(junction.point + NORMAL_LENGTH * tangent),
+                    css::FUCHSIA,
+                );
+            }
+        }
+    }
+}
+
+/// Bodies that fly when nothing holds them.
+type FallingBody = (With<Gravitate>, Without<Grinding>);
+
+fn draw_landing(
+    mut gizmos: Gizmos,
+    layers: Res<SplineDebugLayers>,
+    config: Res<PhysicsConfig>,
+    casts: SplineCast,
+    query: Query<(&Position, &VerletObject, &BodyShape, &Collider), FallingBody>,
+) {
+    if !layers.landing {
+        return;
+    }
+    for (position, verlet_object, shape, collider) in &query {
+        if !collider.collisions.is_empty() {
+            continue;
+        }
+        if let Some(hit) = casts.segment(position.0, position.0 - Vector2::new(0.0, SHADOW_LENGTH)) {
+            gizmos.line_2d(vec2(position.0), vec2(hit.point), css::DIM_GRAY);
+        }
+
+        // the flight in steps, until the body's circle touches a spline
+        let (mut point, mut velocity) = (position.0, verlet_object.velocity);
+        for _ in 0..LANDING_STEPS {
+            let step = (velocity + 0.5 * LANDING_STEP * config.gravity) * LANDING_STEP;
+            if let Some(hit) = casts.circle(point, shape.bounding_radius(), step, step.norm()) {
+                let direction = step.try_normalize(f32::EPSILON).unwrap_or_else(Vector2::zeros);
+                gizmos.line_2d(vec2(point), vec2(point + hit.distance * direction), css::SKY_BLUE);
+                gizmos.circle_2d(vec2(hit.point), MARKER_RADIUS, css::SKY_BLUE);
+                break;
+            }
+            gizmos.line_2d(vec2(point), vec2(point + step), css::SKY_BLUE);
+            point += step;
+            velocity += LANDING_STEP * config.gravity;
+        }
+    }
+}
+
+fn log_grinds(layers: Res<SplineDebugLayers>, mut started: EventReader<GrindStarted>, mut ended: EventReader<GrindEnded>) {
+    // read every frame, so turning the layer on doesn't log a stale batch
+    if !(layers.enabled && layers.grinds) {
+        started.clear();
+        ended.clear();
+        return;
+    }
+    for event in started.read() {
+        info!("{} started grinding on {}", event.body, event.spline);
+    }
+    for event in ended.read() {
+        info!("{} stopped grinding on {}", event.body, event.spline);
+    }
+}